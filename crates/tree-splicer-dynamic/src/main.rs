@@ -0,0 +1,194 @@
+//! tree-splicer for any tree-sitter grammar, loaded from a shared library at
+//! runtime
+
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use libloading::{Library, Symbol};
+use tree_sitter::{Language, LANGUAGE_VERSION, MIN_COMPATIBLE_LANGUAGE_VERSION};
+
+const USAGE: &str = "Usage: tree-splicer-dynamic --grammar <LIB> --node-types <FILE> \
+                     [--symbol <NAME>] [ARGS]...
+       tree-splicer-dynamic (--language <EXT>=<LIB>,<NODE_TYPES>[,<SYMBOL>])... [ARGS]...";
+
+/// The arguments of this binary, the rest go to [`tree_splicer::cli`]
+struct Options {
+    /// From `--grammar`, `--node-types`, and `--symbol`, if there's no
+    /// `--language`
+    single: Option<Grammar>,
+    /// Grammars for files with each extension
+    languages: Vec<(String, Grammar)>,
+    rest: Vec<OsString>,
+}
+
+struct Grammar {
+    library: PathBuf,
+    node_types: PathBuf,
+    symbol: Option<String>,
+}
+
+/// Parse `<EXT>=<LIB>,<NODE_TYPES>[,<SYMBOL>]`
+fn language(arg: OsString) -> Result<(String, Grammar)> {
+    let arg = arg
+        .into_string()
+        .map_err(|_| anyhow::anyhow!("--language must be valid UTF-8"))?;
+    let bad = || format!("Bad --language {arg}, expected <EXT>=<LIB>,<NODE_TYPES>[,<SYMBOL>]");
+    let (extension, grammar) = arg.split_once('=').with_context(bad)?;
+    let mut parts = grammar.split(',');
+    let (Some(library), Some(node_types), symbol, None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        bail!("{}", bad());
+    };
+    Ok((
+        extension.to_string(),
+        Grammar {
+            library: PathBuf::from(library),
+            node_types: PathBuf::from(node_types),
+            symbol: symbol.map(String::from),
+        },
+    ))
+}
+
+fn options(mut args: impl Iterator<Item = OsString>) -> Result<Options> {
+    let mut grammar = None;
+    let mut node_types = None;
+    let mut symbol = None;
+    let mut languages = Vec::new();
+    let mut rest = Vec::new();
+    while let Some(arg) = args.next() {
+        let (name, value) = match arg.to_str().and_then(|a| a.split_once('=')) {
+            Some((name, value)) => (name.to_string(), Some(OsString::from(value))),
+            None => (arg.to_string_lossy().into_owned(), None),
+        };
+        let slot = match name.as_str() {
+            "--grammar" => &mut grammar,
+            "--node-types" => &mut node_types,
+            "--symbol" => &mut symbol,
+            "--language" => {
+                let value = match value {
+                    Some(value) => value,
+                    None => args
+                        .next()
+                        .with_context(|| format!("{name} needs a value\n{USAGE}"))?,
+                };
+                languages.push(language(value)?);
+                continue;
+            }
+            _ => {
+                rest.push(arg);
+                continue;
+            }
+        };
+        let value = match value {
+            Some(value) => value,
+            None => args
+                .next()
+                .with_context(|| format!("{name} needs a value\n{USAGE}"))?,
+        };
+        *slot = Some(value);
+    }
+    let symbol = match symbol {
+        Some(symbol) => Some(
+            symbol
+                .into_string()
+                .map_err(|_| anyhow::anyhow!("--symbol must be valid UTF-8"))?,
+        ),
+        None => None,
+    };
+    let single = if languages.is_empty() {
+        Some(Grammar {
+            library: grammar
+                .map(PathBuf::from)
+                .with_context(|| format!("Missing --grammar\n{USAGE}"))?,
+            node_types: node_types
+                .map(PathBuf::from)
+                .with_context(|| format!("Missing --node-types\n{USAGE}"))?,
+            symbol,
+        })
+    } else if grammar.is_some() || node_types.is_some() || symbol.is_some() {
+        bail!("--grammar, --node-types, and --symbol can't be used with --language\n{USAGE}");
+    } else {
+        None
+    };
+    Ok(Options {
+        single,
+        languages,
+        rest,
+    })
+}
+
+/// `tree_sitter_<lang>` for a library named like `libtree-sitter-<lang>.so`
+/// or `<lang>.so`
+fn default_symbol(grammar: &Path) -> Result<String> {
+    let stem = grammar
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .with_context(|| format!("Can't guess the symbol for {}", grammar.display()))?;
+    let stem = stem.strip_prefix("lib").unwrap_or(stem);
+    let lang = stem
+        .strip_prefix("tree-sitter-")
+        .or_else(|| stem.strip_prefix("tree_sitter_"))
+        .unwrap_or(stem);
+    Ok(format!("tree_sitter_{}", lang.replace('-', "_")))
+}
+
+fn load(grammar: &Path, symbol: &str) -> Result<Language> {
+    // SAFETY: This runs the initializers of the library, which the user asked
+    // for by passing it.
+    let library = unsafe { Library::new(grammar) }
+        .with_context(|| format!("Failed to load grammar {}", grammar.display()))?;
+    let language = {
+        // SAFETY: tree-sitter grammars export `const TSLanguage
+        // *tree_sitter_<lang>(void)`, and `Language` is a transparent wrapper
+        // around that pointer.
+        let constructor: Symbol<unsafe extern "C" fn() -> Language> =
+            unsafe { library.get(symbol.as_bytes()) }.with_context(|| {
+                format!(
+                    "Grammar {} has no symbol {symbol}, pass the right one with --symbol",
+                    grammar.display()
+                )
+            })?;
+        unsafe { constructor() }
+    };
+    // The language points into the library, so it must never be unloaded
+    std::mem::forget(library);
+    let version = language.version();
+    if !(MIN_COMPATIBLE_LANGUAGE_VERSION..=LANGUAGE_VERSION).contains(&version) {
+        bail!(
+            "Grammar {} has ABI version {version}, but only versions \
+             {MIN_COMPATIBLE_LANGUAGE_VERSION} to {LANGUAGE_VERSION} are supported. \
+             Regenerate it with a compatible version of the tree-sitter CLI.",
+            grammar.display()
+        );
+    }
+    Ok(language)
+}
+
+/// The language and node types of `grammar`
+fn open(grammar: &Grammar) -> Result<(Language, &'static str)> {
+    let symbol = match &grammar.symbol {
+        Some(symbol) => symbol.clone(),
+        None => default_symbol(&grammar.library)?,
+    };
+    let language = load(&grammar.library, &symbol)?;
+    let node_types = fs::read_to_string(&grammar.node_types)
+        .with_context(|| format!("Failed to read node types {}", grammar.node_types.display()))?;
+    Ok((language, Box::leak(node_types.into_boxed_str())))
+}
+
+fn main() -> Result<()> {
+    let options = options(std::env::args_os())?;
+    if let Some(grammar) = &options.single {
+        let (language, node_types) = open(grammar)?;
+        return tree_splicer::cli::main_from(language, node_types, options.rest);
+    }
+    let mut languages = Vec::with_capacity(options.languages.len());
+    for (extension, grammar) in &options.languages {
+        let (language, node_types) = open(grammar)?;
+        languages.push((extension.clone(), language, node_types));
+    }
+    tree_splicer::cli::main_multi(languages, options.rest)
+}
@@ -0,0 +1,199 @@
+//! Runs the binary with grammars compiled from the sources of the grammar
+//! crates in the workspace.
+
+#![cfg(unix)]
+
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Output};
+use std::{env, fs};
+
+const RUST: &str = "fn f(x: u8) -> u8 {\n    let y = x + 1;\n    g(x, y);\n    y\n}\n";
+
+/// A fresh directory for the files of the test `name`
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("tree-splicer-dynamic-{name}-{}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// The source directory of the dependency `package`, e.g., `tree-sitter-rust`
+fn source(package: &str) -> PathBuf {
+    let workspace = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../Cargo.toml");
+    let output = Command::new(env!("CARGO"))
+        .args(["metadata", "--format-version", "1", "--manifest-path"])
+        .arg(workspace)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let metadata = String::from_utf8(output.stdout).unwrap();
+    let prefix = format!("{package}-0.");
+    metadata
+        .split("\"manifest_path\":\"")
+        .skip(1)
+        .filter_map(|rest| rest.split('"').next())
+        .filter_map(|manifest| Path::new(manifest).parent())
+        .find(|dir| {
+            dir.file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with(&prefix))
+        })
+        .unwrap_or_else(|| panic!("{package} isn't a dependency"))
+        .to_path_buf()
+}
+
+/// `package` compiled to a shared library in `dir`, and its node types
+fn grammar(package: &str, dir: &Path) -> (PathBuf, PathBuf) {
+    let src = source(package).join("src");
+    let library = dir.join(format!("lib{package}.so"));
+    let mut cc = Command::new(env::var("CC").unwrap_or_else(|_| "cc".to_string()));
+    cc.args(["-shared", "-fPIC", "-O1", "-I"])
+        .arg(&src)
+        .arg(src.join("parser.c"));
+    if src.join("scanner.c").exists() {
+        cc.arg(src.join("scanner.c"));
+    }
+    let status = cc.arg("-o").arg(&library).status().unwrap();
+    assert!(status.success(), "Couldn't compile {package}");
+    (library, src.join("node-types.json"))
+}
+
+fn run(args: &[&std::ffi::OsStr]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_tree-splicer-dynamic"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+/// The contents of the files in `dir`
+fn outputs(dir: &Path) -> Vec<String> {
+    fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| fs::read_to_string(entry.unwrap().path()).unwrap())
+        .collect()
+}
+
+#[test]
+fn loads_a_grammar() {
+    let dir = temp_dir("load");
+    let (library, node_types) = grammar("tree-sitter-rust", &dir);
+    let input = dir.join("in.rs");
+    fs::write(&input, RUST).unwrap();
+    let out = dir.join("out");
+    let output = run(&[
+        "--grammar".as_ref(),
+        library.as_ref(),
+        "--node-types".as_ref(),
+        node_types.as_ref(),
+        "--seed".as_ref(),
+        "0".as_ref(),
+        "--tests".as_ref(),
+        "4".as_ref(),
+        "--allow-repeats".as_ref(),
+        "--output".as_ref(),
+        out.as_ref(),
+        input.as_ref(),
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(outputs(&out).len(), 4);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn bad_grammars_are_errors() {
+    let dir = temp_dir("bad");
+    let (library, node_types) = grammar("tree-sitter-rust", &dir);
+    let input = dir.join("in.rs");
+    fs::write(&input, RUST).unwrap();
+
+    let missing = dir.join("libtree-sitter-missing.so");
+    let output = run(&[
+        "--grammar".as_ref(),
+        missing.as_ref(),
+        "--node-types".as_ref(),
+        node_types.as_ref(),
+        input.as_ref(),
+    ]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Failed to load grammar"));
+
+    let output = run(&[
+        "--grammar".as_ref(),
+        library.as_ref(),
+        "--node-types".as_ref(),
+        node_types.as_ref(),
+        "--symbol".as_ref(),
+        "tree_sitter_nothing".as_ref(),
+        input.as_ref(),
+    ]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("has no symbol tree_sitter_nothing"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn languages_are_spliced_apart() {
+    let dir = temp_dir("multi");
+    let (rust, rust_types) = grammar("tree-sitter-rust", &dir);
+    let (js, js_types) = grammar("tree-sitter-javascript", &dir);
+    let inputs = dir.join("in");
+    fs::create_dir_all(&inputs).unwrap();
+    fs::write(inputs.join("a.rs"), RUST).unwrap();
+    fs::write(
+        inputs.join("b.js"),
+        "function g(a) {\n    const b = a * 2;\n    return h(a, b);\n}\n",
+    )
+    .unwrap();
+    fs::write(inputs.join("c.txt"), "neither").unwrap();
+    let language = |ext: &str, library: &Path, node_types: &Path| {
+        format!("{ext}={},{}", library.display(), node_types.display())
+    };
+    let rs = language("rs", &rust, &rust_types);
+    let js = language("js", &js, &js_types);
+    let out = dir.join("out");
+    let output = run(&[
+        "--language".as_ref(),
+        rs.as_ref(),
+        "--language".as_ref(),
+        js.as_ref(),
+        // Exits if a file doesn't parse with its grammar
+        "--strict".as_ref(),
+        "--seed".as_ref(),
+        "0".as_ref(),
+        "--tests".as_ref(),
+        "8".as_ref(),
+        "--allow-repeats".as_ref(),
+        "--output".as_ref(),
+        out.as_ref(),
+        inputs.as_ref(),
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+    let mut subdirectories: Vec<_> = fs::read_dir(&out)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    subdirectories.sort();
+    assert_eq!(subdirectories, ["js", "rs"]);
+    let rust_outputs = outputs(&out.join("rs"));
+    assert_eq!(rust_outputs.len(), 8);
+    for output in rust_outputs {
+        assert!(!["function", "const", "return"]
+            .iter()
+            .any(|js| output.contains(js)));
+    }
+    let js_outputs = outputs(&out.join("js"));
+    assert_eq!(js_outputs.len(), 8);
+    for output in js_outputs {
+        assert!(!["fn ", "let", "u8"]
+            .iter()
+            .any(|rust| output.contains(rust)));
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,75 @@
+//! Using tree-splicer as a libFuzzer custom mutator.
+//!
+//! In a cargo-fuzz target, the body of `LLVMFuzzerCustomMutator` would go in
+//! `libfuzzer_sys::fuzz_mutator!`:
+//!
+//! ```ignore
+//! fuzz_mutator!(|data: &mut [u8], size: usize, max_size: usize, seed: u32| {
+//!     let mut buf = data[..size].to_vec();
+//!     let len = SPLICER.with_borrow_mut(|splicer| {
+//!         tree_splicer::splice::mutate(splicer, &mut buf, max_size, seed)
+//!     });
+//!     data[..len].copy_from_slice(&buf);
+//!     len
+//! });
+//! ```
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use tree_sitter::Tree;
+use tree_splicer::node_types::NodeTypes;
+use tree_splicer::splice::{self, ConfigBuilder, PersistentSplicer};
+
+/// Files to take candidates from, e.g., the seed corpus of the fuzzer
+fn corpus() -> &'static HashMap<String, (Vec<u8>, Tree)> {
+    static CORPUS: OnceLock<HashMap<String, (Vec<u8>, Tree)>> = OnceLock::new();
+    CORPUS.get_or_init(|| {
+        let text = b"fn f() { let x = 1; let y = x + 2; g(x, y); }".to_vec();
+        let mut parser = tree_sitter::Parser::new();
+        parser.set_language(tree_sitter_rust::language()).unwrap();
+        let tree = parser.parse(&text, None).unwrap();
+        HashMap::from([("seed.rs".to_string(), (text, tree))])
+    })
+}
+
+thread_local! {
+    /// Made once per thread, rather than once per mutation
+    static SPLICER: RefCell<PersistentSplicer<'static>> = RefCell::new({
+        let node_types = NodeTypes::new(tree_sitter_rust::NODE_TYPES).unwrap();
+        let config = ConfigBuilder::new(tree_sitter_rust::language(), node_types)
+            .with_inter_splices(4)
+            .build()
+            .unwrap();
+        PersistentSplicer::new(config, corpus()).unwrap()
+    });
+}
+
+/// # Safety
+///
+/// `data` must point to `max_size` bytes, the first `size` of which are
+/// initialized.
+#[no_mangle]
+pub unsafe extern "C" fn LLVMFuzzerCustomMutator(
+    data: *mut u8,
+    size: usize,
+    max_size: usize,
+    seed: u32,
+) -> usize {
+    let data = std::slice::from_raw_parts_mut(data, max_size);
+    let mut buf = data[..size].to_vec();
+    let len = SPLICER.with_borrow_mut(|splicer| splice::mutate(splicer, &mut buf, max_size, seed));
+    data[..len].copy_from_slice(&buf);
+    len
+}
+
+fn main() {
+    let mut data = b"fn f() { let x = 1; let y = x + 2; g(x, y); }".to_vec();
+    let mut size = data.len();
+    data.resize(2 * size, 0);
+    for seed in 0..4 {
+        size = unsafe { LLVMFuzzerCustomMutator(data.as_mut_ptr(), size, data.len(), seed) };
+        println!("{}", String::from_utf8_lossy(&data[..size]));
+    }
+}
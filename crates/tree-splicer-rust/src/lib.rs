@@ -0,0 +1,18 @@
+//! The Rust grammar, for use from JavaScript with the `wasm` feature
+
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+/// Make `tests` variants of the Rust program `source`, see
+/// [`tree_splicer::wasm::splice_js`]
+#[cfg(feature = "wasm")]
+#[wasm_bindgen]
+pub fn splice(source: &str, seed: u64, tests: usize) -> Result<Vec<JsValue>, JsValue> {
+    tree_splicer::wasm::splice_js(
+        tree_sitter_rust::language(),
+        tree_sitter_rust::NODE_TYPES,
+        source,
+        seed,
+        tests,
+    )
+}
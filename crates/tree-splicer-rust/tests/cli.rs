@@ -0,0 +1,174 @@
+//! Runs the binary on a small corpus, with commands from the shell as hooks.
+
+#![cfg(unix)]
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Output, Stdio};
+use std::{env, fs};
+
+use tree_sitter::Parser;
+
+const INPUT: &str = "fn f(x: u8) -> u8 {\n    g(keep, 1);\n    h(x, 2)\n}\n";
+
+/// A fresh directory for the files of the test `name`, with `INPUT` in it
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("tree-splicer-rust-{name}-{}", process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("in.rs"), INPUT).unwrap();
+    dir
+}
+
+/// The tests made from the input in `dir`, saved in `dir/<out>`, in order
+fn run(dir: &Path, out: &str, args: &[&str]) -> Vec<String> {
+    let out = dir.join(out);
+    let output = Command::new(env!("CARGO_BIN_EXE_tree-splicer-rust"))
+        .args(["--seed", "0", "--tests", "32", "--allow-repeats"])
+        .args(args)
+        .arg("--output")
+        .arg(&out)
+        .arg(dir.join("in.rs"))
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let mut tests: Vec<(usize, String)> = fs::read_dir(&out)
+        .unwrap()
+        .map(|entry| {
+            let path = entry.unwrap().path();
+            let index = path.file_name().unwrap().to_str().unwrap().parse().unwrap();
+            (index, fs::read_to_string(path).unwrap())
+        })
+        .collect();
+    tests.sort();
+    tests.into_iter().map(|(_, test)| test).collect()
+}
+
+#[test]
+fn validators_filter_tests() {
+    let dir = temp_dir("validate");
+    let kept = run(&dir, "kept", &["--validate", "grep -q keep"]);
+    let rejected = run(
+        &dir,
+        "rejected",
+        &["--validate", "grep -q keep", "--keep-rejected"],
+    );
+    assert!(kept.iter().all(|test| test.contains("keep")));
+    assert!(!rejected.iter().any(|test| test.contains("keep")));
+    // The same tests, split between the two
+    assert!(!kept.is_empty() && !rejected.is_empty());
+    assert_eq!(kept.len() + rejected.len(), 32);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn formatters_transform_tests() {
+    let dir = temp_dir("format");
+    let plain = run(&dir, "plain", &[]);
+    let upper = run(&dir, "upper", &["--format", "tr a-z A-Z"]);
+    let upper_plain: Vec<_> = plain.iter().map(|test| test.to_uppercase()).collect();
+    assert_eq!(upper, upper_plain);
+    // Tests the formatter fails on are kept as they are
+    let failed = run(&dir, "failed", &["--format", "false"]);
+    assert_eq!(failed, plain);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// What the binary prints with `input` on stdin
+fn pipe(input: &str, args: &[&str]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_tree-splicer-rust"))
+        .args(["--stdin", "--stdout"])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn stdin_is_spliced_to_stdout() {
+    let output = pipe(
+        INPUT,
+        &[
+            "--seed",
+            "0",
+            "--tests",
+            "8",
+            "--allow-repeats",
+            "--delimiter",
+            r"\n----\n",
+        ],
+    );
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let variants: Vec<_> = stdout.split("\n----\n").collect();
+    assert_eq!(variants.len(), 9);
+    assert_eq!(variants[8], "");
+    let mut parser = Parser::new();
+    parser.set_language(tree_sitter_rust::language()).unwrap();
+    for variant in &variants[..8] {
+        let tree = parser.parse(variant, None).unwrap();
+        assert_eq!(tree.root_node().kind(), "source_file");
+        assert!(!tree.root_node().is_error());
+    }
+}
+
+#[test]
+fn unparseable_stdin_is_an_error() {
+    let output = pipe("fn f( {", &["--on-parse-error", "error"]);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Parse error in <stdin>"));
+}
+
+#[test]
+fn files_in_other_languages_are_skipped() {
+    let dir = temp_dir("wrong");
+    let inputs = dir.join("inputs");
+    fs::create_dir_all(&inputs).unwrap();
+    fs::write(inputs.join("good.rs"), INPUT).unwrap();
+    fs::write(inputs.join("wrong.rs"), "]) }) ]) }").unwrap();
+    // Without `--stdout`, the log is on stdout
+    let command = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_tree-splicer-rust"))
+            .args(["--seed", "0", "--tests", "8", "--allow-repeats"])
+            .args(args)
+            .arg("--output")
+            .arg(dir.join("out"))
+            .arg(&inputs)
+            .output()
+            .unwrap()
+    };
+    let output = command(&[]);
+    let log = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(log.contains("Skipping") && log.contains("wrong.rs"));
+    for entry in fs::read_dir(dir.join("out")).unwrap() {
+        let test = fs::read_to_string(entry.unwrap().path()).unwrap();
+        assert!(!test.contains("])"));
+    }
+    let output = command(&["--strict"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("wrong.rs"));
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,284 @@
+//! Run with `cargo bench -p tree-splicer`
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use rustc_hash::FxHasher;
+use tree_sitter::{Parser, Tree};
+use tree_splicer::node_types::NodeTypes;
+use tree_splicer::splice::{splice, ConfigBuilder, PersistentSplicer, Splicer};
+
+/// A few files of a few KB of Rust each
+fn corpus() -> HashMap<String, (Vec<u8>, Tree)> {
+    let mut parser = Parser::new();
+    parser.set_language(tree_sitter_rust::language()).unwrap();
+    (0..8)
+        .map(|i| {
+            let text: String = (0..32)
+                .map(|j| {
+                    format!(
+                        "fn f{j}(x: u{}) -> u64 {{\n    let y = x as u64 + {j};\n    g(x, y, \"{i}\");\n    y * 2\n}}\n",
+                        8 << (j % 4)
+                    )
+                })
+                .collect();
+            let tree = parser.parse(&text, None).unwrap();
+            (format!("{i}.rs"), (text.into_bytes(), tree))
+        })
+        .collect()
+}
+
+fn builder() -> ConfigBuilder {
+    let node_types = NodeTypes::new(tree_sitter_rust::NODE_TYPES).unwrap();
+    ConfigBuilder::new(tree_sitter_rust::language(), node_types)
+        .with_seed(0)
+        .with_tests(64)
+}
+
+/// How outputs are hashed for `unique`, before and after FxHash
+fn hashers(c: &mut Criterion) {
+    let files = corpus();
+    let outputs: Vec<_> = splice(builder().build().unwrap(), &files)
+        .unwrap()
+        .collect();
+    let mut group = c.benchmark_group("hash");
+    group.bench_function("default", |b| {
+        b.iter(|| {
+            for output in &outputs {
+                let mut hasher = DefaultHasher::new();
+                output.hash(&mut hasher);
+                black_box(hasher.finish());
+            }
+        })
+    });
+    group.bench_function("fx", |b| {
+        b.iter(|| {
+            for output in &outputs {
+                let mut hasher = FxHasher::default();
+                output.hash(&mut hasher);
+                black_box(hasher.finish());
+            }
+        })
+    });
+    group.finish();
+}
+
+/// Collecting the candidates in the corpus, which hashes each node's text
+fn branches(c: &mut Criterion) {
+    let files = corpus();
+    c.bench_function("branches", |b| {
+        b.iter(|| Splicer::new(builder().build().unwrap(), &files).unwrap())
+    });
+}
+
+/// A whole run, hashing each output
+fn unique(c: &mut Criterion) {
+    let files = corpus();
+    c.bench_function("unique", |b| {
+        b.iter(|| {
+            let config = builder().with_unique(true).build().unwrap();
+            splice(config, &files).unwrap().count()
+        })
+    });
+}
+
+/// Applying several mutations per render and re-parse, rather than one
+fn batches(c: &mut Criterion) {
+    let files = corpus();
+    let mut group = c.benchmark_group("batch");
+    for batch_size in [1, 4, 16] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(batch_size),
+            &batch_size,
+            |b, batch_size| {
+                b.iter(|| {
+                    let config = builder()
+                        .with_inter_splices(16)
+                        .with_batch_size(*batch_size)
+                        .build()
+                        .unwrap();
+                    splice(config, &files).unwrap().count()
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+/// Collecting the nodes once per re-parse, for all the mutations in a batch,
+/// rather than walking the tree for each
+fn node_walks(c: &mut Criterion) {
+    let files = corpus();
+    let mut group = c.benchmark_group("nodes");
+    for (name, reservoir_sampling) in [("collected", false), ("walked", true)] {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let config = builder()
+                    .with_inter_splices(16)
+                    .with_batch_size(8)
+                    .with_deletions(25)
+                    .with_duplications(25)
+                    .with_reservoir_sampling(reservoir_sampling)
+                    .build()
+                    .unwrap();
+                splice(config, &files).unwrap().count()
+            })
+        });
+    }
+    group.finish();
+}
+
+/// Re-parsing outputs with one parser, as splicing does, or a new one for each
+fn parsers(c: &mut Criterion) {
+    let files = corpus();
+    let outputs: Vec<_> = splice(builder().with_tests(256).build().unwrap(), &files)
+        .unwrap()
+        .collect();
+    let mut group = c.benchmark_group("parser");
+    group.bench_function("fresh", |b| {
+        b.iter(|| {
+            for output in &outputs {
+                let mut parser = Parser::new();
+                parser.set_language(tree_sitter_rust::language()).unwrap();
+                black_box(parser.parse(output, None));
+            }
+        })
+    });
+    group.bench_function("reused", |b| {
+        let mut parser = Parser::new();
+        parser.set_language(tree_sitter_rust::language()).unwrap();
+        b.iter(|| {
+            for output in &outputs {
+                black_box(parser.parse(output, None));
+            }
+        })
+    });
+    group.finish();
+}
+
+/// Splicing from thousands of distinct candidates of each kind, which are
+/// borrowed rather than copied for each splice
+fn many_candidates(c: &mut Criterion) {
+    let mut parser = Parser::new();
+    parser.set_language(tree_sitter_rust::language()).unwrap();
+    let files: HashMap<_, _> = (0..8)
+        .map(|i| {
+            let text: String = (0..512)
+                .map(|j| format!("fn f{i}_{j}(x{j}: u8) -> u8 {{\n    g{j}(x{j}, {i}{j})\n}}\n"))
+                .collect();
+            let tree = parser.parse(&text, None).unwrap();
+            (format!("{i}.rs"), (text.into_bytes(), tree))
+        })
+        .collect();
+    c.bench_function("many_candidates", |b| {
+        b.iter(|| {
+            let config = builder().with_inter_splices(64).build().unwrap();
+            splice(config, &files).unwrap().count()
+        })
+    });
+}
+
+/// Many outputs with few mutations each from one large file, whose tree is
+/// borrowed until the first re-parse rather than cloned for each output. The
+/// clones alone are measured too, for comparison.
+fn large_seed(c: &mut Criterion) {
+    let mut parser = Parser::new();
+    parser.set_language(tree_sitter_rust::language()).unwrap();
+    let text: String = (0..4096)
+        .map(|j| format!("fn f{j}(x: u8) -> u8 {{\n    let y = x + {j};\n    g(x, y)\n}}\n"))
+        .collect();
+    let tree = parser.parse(&text, None).unwrap();
+    let files = HashMap::from([("0.rs".to_string(), (text.into_bytes(), tree))]);
+    let mut group = c.benchmark_group("large_seed");
+    group.bench_function("splice", |b| {
+        b.iter(|| {
+            let config = builder()
+                .with_tests(256)
+                .with_inter_splices(1)
+                .with_allow_repeats(true)
+                .build()
+                .unwrap();
+            splice(config, &files).unwrap().count()
+        })
+    });
+    let (_, tree) = &files["0.rs"];
+    group.bench_function("clones", |b| {
+        b.iter(|| {
+            for _ in 0..256 {
+                black_box(tree.clone());
+            }
+        })
+    });
+    group.finish();
+}
+
+/// Deleting optional nodes of one large tree, which are picked without
+/// collecting the matches, from nodes collected once per re-parse or walked
+/// for each pick
+fn large_tree(c: &mut Criterion) {
+    let mut parser = Parser::new();
+    parser.set_language(tree_sitter_rust::language()).unwrap();
+    let text: String = (0..4096)
+        .map(|j| format!("fn f{j}(x: u8, y: u8) -> u8 {{\n    g(x, y);\n    x + y * {j}\n}}\n"))
+        .collect();
+    let tree = parser.parse(&text, None).unwrap();
+    let files = HashMap::from([("0.rs".to_string(), (text.into_bytes(), tree))]);
+    let mut group = c.benchmark_group("large_tree");
+    for (name, reservoir_sampling) in [("collected", false), ("walked", true)] {
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let config = builder()
+                    .with_tests(16)
+                    .with_inter_splices(16)
+                    .with_deletions(50)
+                    .with_allow_repeats(true)
+                    .with_reservoir_sampling(reservoir_sampling)
+                    .build()
+                    .unwrap();
+                splice(config, &files).unwrap().count()
+            })
+        });
+    }
+    group.finish();
+}
+
+/// One output at a time, as a fuzzer's custom mutator makes them, reading the
+/// corpus for each or once up front
+fn persistent(c: &mut Criterion) {
+    let files = corpus();
+    let mut group = c.benchmark_group("persistent");
+    group.bench_function("splice", |b| {
+        let mut seed = 0;
+        b.iter(|| {
+            seed += 1;
+            let config = builder().with_seed(seed).with_tests(1).build().unwrap();
+            black_box(splice(config, &files).unwrap().next())
+        })
+    });
+    group.bench_function("mutate_one", |b| {
+        let mut splicer = PersistentSplicer::new(builder().build().unwrap(), &files).unwrap();
+        let mut seed = 0;
+        b.iter(|| {
+            seed += 1;
+            black_box(splicer.mutate_one(seed))
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    hashers,
+    branches,
+    unique,
+    batches,
+    node_walks,
+    parsers,
+    many_candidates,
+    large_seed,
+    large_tree,
+    persistent
+);
+criterion_main!(benches);
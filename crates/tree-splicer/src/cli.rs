@@ -1,19 +1,30 @@
 use std::collections::HashMap;
+use std::ffi::OsString;
 use std::fs;
+use std::hash::Hasher;
 use std::io;
-use std::io::Read;
-use std::path::PathBuf;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 use std::process;
+use std::process::Stdio;
+use std::thread;
+use std::time::Duration;
 
 use anyhow::{Context, Result};
-use clap::Parser;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser};
 use clap_verbosity_flag::{InfoLevel, Verbosity};
-use tracing::{error, warn};
+use flate2::write::GzEncoder;
+use rustc_hash::FxHasher;
+use tracing::{debug, error, info, warn};
 use tracing_subscriber::fmt::format::FmtSpan;
 use tree_sitter::Tree;
 
 use crate::splice;
-use crate::splice::Config;
+use crate::splice::{
+    CandidateSizeBias, CandidateWeighting, ChaosWeighting, ConfigBuilder, Direction, FileSelection,
+    RunStats, Settings,
+};
 
 mod formatter;
 
@@ -34,6 +45,33 @@ impl std::fmt::Display for OnParseError {
     }
 }
 
+/// How to compress saved tests
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compress {
+    /// gzip, saved with the extension `.gz`
+    Gz,
+    /// Zstandard, saved with the extension `.zst`
+    Zstd,
+}
+
+impl Compress {
+    fn extension(self) -> &'static str {
+        match self {
+            Compress::Gz => "gz",
+            Compress::Zstd => "zst",
+        }
+    }
+}
+
+impl std::fmt::Display for Compress {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Compress::Gz => write!(f, "gz"),
+            Compress::Zstd => write!(f, "zstd"),
+        }
+    }
+}
+
 #[allow(clippy::derivable_impls)]
 impl Default for OnParseError {
     fn default() -> Self {
@@ -57,18 +95,160 @@ fn handle_parse_errors(path: &str, tree: &Tree, on_parse_error: &OnParseError) {
     }
 }
 
+/// Whether to use a file, i.e., unless it doesn't look like it's in the
+/// language at all (e.g., it has the wrong extension), which could fill the
+/// candidates with garbage
+fn handle_wrong_language(path: &str, text: &str, tree: &Tree, strict: bool) -> bool {
+    if splice::in_language(text.as_bytes(), tree) {
+        return true;
+    }
+    if strict {
+        error!(
+            path,
+            "{} doesn't look like it's in the right language", path
+        );
+        process::exit(1);
+    }
+    warn!(
+        path,
+        "Skipping {}, it doesn't look like it's in the right language", path
+    );
+    false
+}
+
 /// TODO description
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Args {
-    /// Percent of "chaotic" mutations - may introduce syntax errors
+    /// Make all `--tests` outputs even if some must repeat
+    #[arg(long)]
+    pub allow_repeats: bool,
+
+    /// Print how many nodes of each kind the inputs have, then exit
+    #[arg(long)]
+    pub analyze: bool,
+
+    /// Only mutate nodes inside nodes of this kind
+    #[arg(long, value_name = "KIND")]
+    pub anchor_kind: Option<String>,
+
+    /// Maximum number of mutations to apply before re-parsing
+    #[arg(long, default_value_t = 1)]
+    pub batch_size: usize,
+
+    /// How splices pick among the texts that could replace a node
+    #[arg(long, default_value_t = CandidateWeighting::Uniform, value_name = "CHOICE")]
+    pub candidate_weighting: CandidateWeighting,
+
+    /// Read settings from this JSON file (see `--print-config`), or TOML file
+    /// if its name ends with `.toml`. Flags given on the command line take
+    /// precedence.
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Default for `--splice-chaos` and `--delete-chaos`
     #[arg(short, long, default_value_t = 5)]
     pub chaos: u8,
 
+    /// How chaotic mutations pick the kind of node to splice in
+    #[arg(long, default_value_t = ChaosWeighting::Uniform, value_name = "CHOICE")]
+    pub chaos_weighting: ChaosWeighting,
+
+    /// Kind of comment nodes for `--preserve-comments` (repeatable, default:
+    /// `comment`, `line_comment`, and `block_comment`)
+    #[arg(long, value_name = "KIND")]
+    pub comment_kind: Vec<String>,
+
+    /// Start each test by splicing a node from one file into another
+    #[arg(long)]
+    pub crossover: bool,
+
+    /// Compress each saved test, adding the extension of the format to its
+    /// name. Each file is a single gzip member or Zstandard frame.
+    #[arg(long, value_name = "FORMAT")]
+    pub compress: Option<Compress>,
+
+    /// With `--stdout`, write this after each test. `\0`, `\n`, `\t`, and `\\`
+    /// stand for a NUL byte, a newline, a tab, and a backslash.
+    #[arg(long, default_value = r"\0", value_name = "TEXT")]
+    pub delimiter: String,
+
+    /// Percent of deletions that may delete any node - may introduce syntax
+    /// errors
+    #[arg(long)]
+    pub delete_chaos: Option<u8>,
+
     /// Percent of deletion mutations - the rest are splices
     #[arg(short, long, default_value_t = 5)]
     pub deletions: u8,
 
+    /// Only apply mutations that make tests bigger, or smaller
+    #[arg(long, default_value_t = Direction::Any, value_name = "CHOICE")]
+    pub direction: Direction,
+
+    /// Write the tokens in the inputs to this file as an AFL/libFuzzer
+    /// dictionary, or to stdout if it's `-`, then exit
+    #[arg(long, value_name = "FILE")]
+    pub dictionary: Option<PathBuf>,
+
+    /// Kind of node that tokens from `--import-dictionary` may replace
+    #[arg(long, value_name = "KIND", requires = "import_dictionary")]
+    pub dictionary_kind: Option<String>,
+
+    /// Don't save tests that are the same as one of the inputs
+    #[arg(long)]
+    pub drop_unchanged: bool,
+
+    /// Percent of mutations that copy a node in a list next to itself
+    #[arg(long, default_value_t = 0)]
+    pub duplications: u8,
+
+    /// When reading directories, only read files with these extensions, e.g.,
+    /// `sol,t.sol` (default: all files)
+    #[arg(long, value_delimiter = ',', value_name = "EXT")]
+    pub extensions: Vec<String>,
+
+    /// Never mutate or splice in nodes of this kind (repeatable)
+    #[arg(long, value_name = "KIND")]
+    pub exclude_kind: Vec<String>,
+
+    /// Only splice in nodes from the same field of their parent as the node
+    /// they replace
+    #[arg(long)]
+    pub field_aware: bool,
+
+    /// How to pick the file to mutate for each test
+    #[arg(long, default_value_t = FileSelection::Uniform, value_name = "CHOICE")]
+    pub file_selection: FileSelection,
+
+    /// Never mutate code marked by `splicer:ignore`, `splicer:ignore-start`, and
+    /// `splicer:ignore-end` comments
+    #[arg(long)]
+    pub honor_pragmas: bool,
+
+    /// Splice in the tokens from this AFL/libFuzzer dictionary, in place of
+    /// nodes of the kind given by `--dictionary-kind`
+    #[arg(long, value_name = "FILE", requires = "dictionary_kind")]
+    pub import_dictionary: Option<PathBuf>,
+
+    /// Percent of insertion mutations
+    #[arg(long, default_value_t = 0)]
+    pub insertions: u8,
+
+    /// With `--validate`, keep the tests the command fails on instead
+    #[arg(long, requires = "validate")]
+    pub keep_rejected: bool,
+
+    /// Only mutate and splice in nodes of this kind (repeatable), minus any
+    /// `--exclude-kind`
+    #[arg(long, value_name = "KIND")]
+    pub include_kind: Vec<String>,
+
+    /// Pipe each test through this shell command, e.g., a code formatter,
+    /// after `--validate`. Tests it fails on are saved as they are.
+    #[arg(long, value_name = "CMD")]
+    pub format: Option<String>,
+
     /// Behavior on parse errors
     #[arg(long, default_value_t = OnParseError::Warn, value_name = "CHOICE")]
     on_parse_error: OnParseError,
@@ -77,27 +257,157 @@ pub struct Args {
     #[arg(short, long, default_value_t = num_cpus::get())]
     pub jobs: usize,
 
-    /// Number of mutations per teset
+    /// Maximum size of outputs, in bytes
+    #[arg(long, value_name = "BYTES")]
+    pub max_bytes: Option<usize>,
+
+    /// Keep at most this many candidate texts of each node kind
+    #[arg(long, value_name = "N")]
+    pub max_candidates_per_kind: Option<usize>,
+
+    /// Only mutate nodes at most this deep, where the root is at depth 0
+    #[arg(long, value_name = "DEPTH")]
+    pub max_depth: Option<usize>,
+
+    /// Stop splicing a test before its tree gets deeper than this
+    #[arg(long, value_name = "DEPTH")]
+    pub max_tree_depth: Option<usize>,
+
+    /// Only mutate nodes at least this deep
+    #[arg(long, default_value_t = 0, value_name = "DEPTH")]
+    pub min_depth: usize,
+
+    /// Apply at least this many mutations to each test, if possible
+    #[arg(long, default_value_t = 0)]
+    pub min_splices: usize,
+
+    /// Maximum number of mutations per test, each gets between 0 and this
+    /// many
     #[arg(short, long, default_value_t = 16)]
     pub mutations: usize,
 
+    /// Maximum number of swaps of same-kind nodes within a test
+    #[arg(long, default_value_t = 0)]
+    pub intra_splices: usize,
+
+    /// Percent of mutations that replace number literals with boundary values
+    #[arg(long, default_value_t = 0)]
+    pub number_mutations: u8,
+
     /// Directory to output to
     #[arg(short, long, default_value_os = "tree-splicer.out")]
     pub output: PathBuf,
 
-    /// Seed
-    #[arg(short, long, default_value_t = 0)]
-    pub seed: u64,
+    /// Instead of `--output`, write to this directory with files named by a
+    /// hash of their contents, so that duplicates collapse
+    #[arg(long, value_name = "DIR")]
+    pub output_dir: Option<PathBuf>,
+
+    /// Never delete, replace, or splice in comments, nor nodes containing them
+    #[arg(long)]
+    pub preserve_comments: bool,
+
+    /// Never mutate the first this many bytes of each file, e.g., a header
+    #[arg(long, default_value_t = 0, value_name = "BYTES")]
+    pub preserve_prefix_bytes: usize,
+
+    /// Print the settings as JSON, for use with `--config`, then exit
+    #[arg(long)]
+    pub print_config: bool,
+
+    /// Only mutate nodes captured by this tree-sitter query
+    #[arg(long)]
+    pub query: Option<String>,
+
+    /// Discard mutations that introduce parse errors
+    #[arg(long)]
+    pub reject_errors: bool,
+
+    /// Percent of mutations that rename all occurrences of an identifier
+    #[arg(long, default_value_t = 0)]
+    pub renames: u8,
+
+    /// Pick nodes to mutate without collecting them, to save memory on huge
+    /// files
+    #[arg(long)]
+    pub reservoir_sampling: bool,
+
+    /// Seed (default: random, and logged)
+    #[arg(short, long)]
+    pub seed: Option<u64>,
+
+    /// Percent of mutations that swap adjacent siblings of the same kind
+    #[arg(long, default_value_t = 0)]
+    pub sibling_swaps: u8,
+
+    /// Prefer splicing in texts at most this many times bigger or smaller than
+    /// the node they replace
+    #[arg(long, value_name = "RATIO")]
+    pub similar_size_ratio: Option<f32>,
+
+    /// Percent of splices that may splice in any kind of node - may introduce
+    /// syntax errors
+    #[arg(long)]
+    pub splice_chaos: Option<u8>,
+
+    /// Write statistics about the run as JSON to this file, or to stderr if
+    /// it's `-`
+    #[arg(long, value_name = "FILE")]
+    pub stats_json: Option<PathBuf>,
+
+    /// Read a single file from stdin, like passing `-`
+    #[arg(long)]
+    pub stdin: bool,
+
+    /// Write the tests to stdout, each followed by `--delimiter`, rather than
+    /// to files
+    #[arg(long, conflicts_with_all = ["output_dir", "compress"])]
+    pub stdout: bool,
+
+    /// Percent of mutations that replace the contents of string literals
+    #[arg(long, default_value_t = 0)]
+    pub string_mutations: u8,
+
+    /// Exit instead of skipping files that don't look like they're in the
+    /// language at all
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Don't save tests that only differ from earlier ones in whitespace
+    #[arg(long)]
+    pub structural_unique: bool,
+
+    /// Let nodes be replaced by nodes of other kinds with the same supertype
+    #[arg(long)]
+    pub supertype_splicing: bool,
 
     /// How many tests to make
     #[arg(long, default_value_t = 4)]
     pub tests: usize,
 
+    /// Stop mutating a file after this many milliseconds
+    #[arg(long, value_name = "MS")]
+    pub timeout: Option<u64>,
+
+    /// Stop before the tests add up to more than this many bytes
+    #[arg(long, value_name = "BYTES")]
+    pub total_bytes_budget: Option<usize>,
+
+    /// Don't save duplicate tests
+    #[arg(long)]
+    pub unique: bool,
+
+    /// Only save tests for which this shell command succeeds, given the test
+    /// on stdin. Runs up to `--jobs` commands at once.
+    #[arg(long, value_name = "CMD")]
+    pub validate: Option<String>,
+
     #[clap(flatten)]
     verbose: Verbosity<InfoLevel>,
 
-    /// Input files, use `-` to pass a single file on stdin
-    #[arg(value_name = "FILE", required = true, num_args = 1..)]
+    /// Input files or directories (read recursively), use `-` to pass a single
+    /// file on stdin
+    #[arg(value_name = "FILE", required_unless_present = "stdin", num_args = 1..)]
     pub files: Vec<String>,
 }
 
@@ -113,6 +423,185 @@ fn parse(language: tree_sitter::Language, code: &str) -> Result<tree_sitter::Tre
     parser.parse(code, None).context("Failed to parse code")
 }
 
+/// Files under `dir` with one of `extensions` (or any, if empty), recursively
+fn walk(dir: &Path, extensions: &[String], paths: &mut Vec<PathBuf>) -> Result<()> {
+    let entries =
+        fs::read_dir(dir).with_context(|| format!("Failed to read directory {}", dir.display()))?;
+    for entry in entries {
+        let path = entry?.path();
+        if path.is_dir() {
+            walk(&path, extensions, paths)?;
+            continue;
+        }
+        if extensions.is_empty() || has_extension(&path, extensions) {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Whether the name of `path` ends with one of `extensions`
+fn has_extension(path: &Path, extensions: &[String]) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    extensions
+        .iter()
+        .any(|ext| name.ends_with(&format!(".{}", ext.trim_start_matches('.'))))
+}
+
+/// `path` with `.<extension>` appended, unless it's `-`
+fn with_suffix(path: PathBuf, extension: &str) -> PathBuf {
+    if path.as_os_str() == "-" {
+        return path;
+    }
+    let mut path = path.into_os_string();
+    path.push(format!(".{}", extension.trim_start_matches('.')));
+    PathBuf::from(path)
+}
+
+/// A file name made from a hash of `bytes`, with the extension of `source`.
+/// Unlike the standard library's hasher, the Fx hasher doesn't change between
+/// Rust releases, so the names stay the same across builds.
+fn hashed_name(bytes: &[u8], source: &str) -> String {
+    let mut hasher = FxHasher::default();
+    hasher.write(bytes);
+    match Path::new(source).extension() {
+        Some(ext) => format!("{:016x}.{}", hasher.finish(), ext.to_string_lossy()),
+        None => format!("{:016x}", hasher.finish()),
+    }
+}
+
+fn shell(cmd: &str) -> process::Command {
+    if cfg!(windows) {
+        let mut command = process::Command::new("cmd");
+        command.arg("/C").arg(cmd);
+        command
+    } else {
+        let mut command = process::Command::new("sh");
+        command.arg("-c").arg(cmd);
+        command
+    }
+}
+
+/// Whether `cmd` succeeds with `bytes` on stdin
+fn validate(cmd: &str, bytes: &[u8]) -> Result<bool> {
+    let mut child = shell(cmd)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .with_context(|| format!("Failed to run validator {cmd}"))?;
+    // The command may exit without reading all of it
+    let _ = child.stdin.take().expect("stdin is piped").write_all(bytes);
+    Ok(child
+        .wait()
+        .context("Failed to wait for validator")?
+        .success())
+}
+
+/// The output of `cmd` with `bytes` on stdin, if it succeeds
+fn reformat(cmd: &str, bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut child = shell(cmd)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| warn!("Failed to run formatter {}: {}", cmd, e))
+        .ok()?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    // Write from another thread, in case the command fills up its stdout
+    // before reading all of its input
+    let output = thread::scope(|s| {
+        s.spawn(move || {
+            let _ = stdin.write_all(bytes);
+        });
+        child.wait_with_output()
+    })
+    .ok()?;
+    if !output.status.success() {
+        debug!("Formatter failed with {}", output.status);
+        return None;
+    }
+    Some(output.stdout)
+}
+
+/// Commands to run on each test before saving it
+struct Hooks {
+    /// See [`Args::validate`]
+    validate: Option<String>,
+    keep_rejected: bool,
+    /// See [`Args::format`]
+    format: Option<String>,
+}
+
+impl Hooks {
+    fn any(&self) -> bool {
+        self.validate.is_some() || self.format.is_some()
+    }
+
+    /// `bytes` after `--validate` and `--format`, or `None` if `--validate`
+    /// drops them
+    fn finish(&self, bytes: Vec<u8>) -> Result<Option<Vec<u8>>> {
+        if let Some(cmd) = &self.validate {
+            if validate(cmd, &bytes)? == self.keep_rejected {
+                return Ok(None);
+            }
+        }
+        Ok(Some(match &self.format {
+            Some(cmd) => reformat(cmd, &bytes).unwrap_or(bytes),
+            None => bytes,
+        }))
+    }
+}
+
+/// `s` with `\0`, `\n`, `\t`, and `\\` replaced by what they stand for
+fn unescape(s: &str) -> Vec<u8> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = match (bytes[i], bytes.get(i + 1)) {
+            (b'\\', Some(b'0')) => Some(b'\0'),
+            (b'\\', Some(b'n')) => Some(b'\n'),
+            (b'\\', Some(b't')) => Some(b'\t'),
+            (b'\\', Some(b'\\')) => Some(b'\\'),
+            _ => None,
+        };
+        match escaped {
+            Some(byte) => {
+                out.push(byte);
+                i += 2;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+/// Write `bytes` to `path`, compressed if `compress` is set
+fn save(path: &Path, bytes: &[u8], compress: Option<Compress>) -> io::Result<()> {
+    let mut file = io::BufWriter::new(fs::File::create(path)?);
+    let mut file = match compress {
+        None => {
+            file.write_all(bytes)?;
+            file
+        }
+        Some(Compress::Gz) => {
+            let mut encoder = GzEncoder::new(file, flate2::Compression::default());
+            encoder.write_all(bytes)?;
+            encoder.finish()?
+        }
+        Some(Compress::Zstd) => {
+            let mut encoder = zstd::Encoder::new(file, 0)?;
+            encoder.write_all(bytes)?;
+            encoder.finish()?
+        }
+    };
+    file.flush()
+}
+
 #[inline]
 fn stdin_string() -> Result<String> {
     let mut stdin_str: String = String::new();
@@ -139,47 +628,506 @@ fn init_tracing(args: &Args) {
         .with_max_level(log_tracing_level(
             &args.verbose.log_level().unwrap_or(log::Level::Info),
         ));
-    builder.event_format(formatter::TerseFormatter).init();
+    // Keep stdout for the tests
+    if args.stdout {
+        builder
+            .with_writer(io::stderr)
+            .event_format(formatter::TerseFormatter)
+            .init();
+    } else {
+        builder.event_format(formatter::TerseFormatter).init();
+    }
+}
+
+/// The flags that set a field of [`Settings`]
+fn setting_args(setting: &str) -> Vec<&str> {
+    match setting {
+        "candidate_size_bias" => vec!["similar_size_ratio"],
+        "comment_kinds" => vec!["comment_kind"],
+        "delete_chaos" => vec!["delete_chaos", "chaos"],
+        "exclude_kinds" => vec!["exclude_kind"],
+        "include_kinds" => vec!["include_kind"],
+        "inter_splices" => vec!["mutations"],
+        "splice_chaos" => vec!["splice_chaos", "chaos"],
+        "timeout_ms" => vec!["timeout"],
+        _ => vec![setting],
+    }
+}
+
+/// Settings from the file at `path`, except those given on the command line.
+/// The file is TOML if its extension is `.toml`, and JSON otherwise.
+fn read_settings(matches: &ArgMatches, args: Settings, path: &Path) -> Result<Settings> {
+    let file = read_file(&path.to_string_lossy())?;
+    let file: serde_json::Map<String, serde_json::Value> =
+        if path.extension().is_some_and(|ext| ext == "toml") {
+            toml::from_str(&file).context("Couldn't parse TOML config file")?
+        } else {
+            serde_json::from_str(&file).context("Couldn't parse config file")?
+        };
+    let mut settings = match serde_json::to_value(args)? {
+        serde_json::Value::Object(settings) => settings,
+        _ => unreachable!("settings serialize to an object"),
+    };
+    for (key, value) in file {
+        if !settings.contains_key(&key) {
+            anyhow::bail!("Unknown setting in config file: {key}");
+        }
+        let given = setting_args(&key)
+            .into_iter()
+            .any(|arg| matches.value_source(arg) == Some(ValueSource::CommandLine));
+        if !given {
+            settings.insert(key, value);
+        }
+    }
+    serde_json::from_value(serde_json::Value::Object(settings)).context("Invalid config file")
 }
 
 pub fn main(language: tree_sitter::Language, node_types_json_str: &'static str) -> Result<()> {
-    let args = Args::parse();
+    main_from(language, node_types_json_str, std::env::args_os())
+}
 
+/// Like [`main`], but with the given arguments, starting with the name of the
+/// program
+pub fn main_from(
+    language: tree_sitter::Language,
+    node_types_json_str: &'static str,
+    args: impl IntoIterator<Item = OsString>,
+) -> Result<()> {
+    let matches = Args::command().get_matches_from(args);
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
     init_tracing(&args);
+    run(language, node_types_json_str, &matches, args)
+}
 
+/// Like [`main_from`], but for inputs in several languages, each given with
+/// its file extension. Files are only spliced with others of the same
+/// extension, and the outputs for each extension go in a subdirectory named
+/// after it. Files passed on stdin are ignored.
+pub fn main_multi(
+    languages: Vec<(String, tree_sitter::Language, &'static str)>,
+    args: impl IntoIterator<Item = OsString>,
+) -> Result<()> {
+    let matches = Args::command().get_matches_from(args);
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    init_tracing(&args);
+    if args.stdin || args.files.iter().any(|f| f == "-") {
+        warn!("Ignoring stdin, its language is unknown");
+    }
+    for (extension, language, node_types_json_str) in languages {
+        let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+        args.stdin = false;
+        args.extensions = vec![extension.clone()];
+        args.files.retain(|f| {
+            let path = Path::new(f);
+            f != "-" && (path.is_dir() || has_extension(path, &args.extensions))
+        });
+        if args.files.is_empty() {
+            continue;
+        }
+        info!("Splicing .{} files", extension.trim_start_matches('.'));
+        if args.analyze {
+            println!("{extension}:");
+        }
+        args.output = args.output.join(&extension);
+        args.output_dir = args.output_dir.map(|dir| dir.join(&extension));
+        args.dictionary = args.dictionary.map(|path| with_suffix(path, &extension));
+        args.stats_json = args.stats_json.map(|path| with_suffix(path, &extension));
+        run(language, node_types_json_str, &matches, args)?;
+    }
+    Ok(())
+}
+
+fn run(
+    language: tree_sitter::Language,
+    node_types_json_str: &'static str,
+    matches: &ArgMatches,
+    mut args: Args,
+) -> Result<()> {
+    if args.stdin {
+        args.files.push("-".to_string());
+    }
     let mut files = HashMap::new();
     for f in args.files {
         if f == "-" {
             let path = "<stdin>".to_string();
             let s = stdin_string()?;
             let tree = parse(language, &s)?;
+            if !handle_wrong_language(&path, &s, &tree, args.strict) {
+                continue;
+            }
             handle_parse_errors(&path, &tree, &args.on_parse_error);
             files.insert(path, (s.into_bytes(), tree));
+        } else if Path::new(&f).is_dir() {
+            let mut paths = Vec::new();
+            walk(Path::new(&f), &args.extensions, &mut paths)?;
+            for path in paths {
+                let path = path.to_string_lossy().into_owned();
+                let parsed = read_file(&path).and_then(|s| Ok((parse(language, &s)?, s)));
+                let (tree, s) = match parsed {
+                    Ok(parsed) => parsed,
+                    Err(e) => {
+                        warn!(path, "Skipping {}: {:#}", path, e);
+                        continue;
+                    }
+                };
+                if !handle_wrong_language(&path, &s, &tree, args.strict) {
+                    continue;
+                }
+                handle_parse_errors(&path, &tree, &args.on_parse_error);
+                files.insert(path, (s.into_bytes(), tree));
+            }
         } else {
             let path = f;
             let s = read_file(&path)?;
             let tree = parse(language, &s)?;
+            if !handle_wrong_language(&path, &s, &tree, args.strict) {
+                continue;
+            }
             handle_parse_errors(&path, &tree, &args.on_parse_error);
             files.insert(path, (s.into_bytes(), tree));
         }
     }
 
+    if args.analyze {
+        let stats = splice::analyze(&files);
+        let width = stats.keys().map(|k| k.len()).max().unwrap_or(0).max(4);
+        println!("{:width$} {:>8} {:>8}", "kind", "count", "distinct");
+        for (kind, s) in stats {
+            println!("{:width$} {:>8} {:>8}", kind, s.count, s.distinct);
+        }
+        return Ok(());
+    }
+    if let Some(path) = &args.dictionary {
+        let dict = splice::dictionary(&files);
+        if path.as_os_str() == "-" {
+            print!("{dict}");
+        } else {
+            std::fs::write(path, dict).context("Couldn't save dictionary")?;
+        }
+        return Ok(());
+    }
+
     let node_types = crate::node_types::NodeTypes::new(node_types_json_str)?;
-    let config = Config {
-        chaos: args.chaos,
-        deletions: args.deletions,
-        language,
-        // intra_splices: 10,
-        inter_splices: args.mutations,
-        node_types,
-        seed: args.seed,
-        tests: args.tests,
+    // Node kinds from the grammar are 'static, so these must be too
+    let leak = |kinds: Vec<String>| -> Vec<&'static str> {
+        kinds
+            .into_iter()
+            .map(|k| &*Box::leak(k.into_boxed_str()))
+            .collect()
     };
-    std::fs::create_dir_all(&args.output).context("Couldn't create output directory")?;
-    for (i, out) in splice::splice(config, &files).enumerate() {
-        std::fs::write(args.output.join(i.to_string()), out)
-            .context("Couldn't save generated test case")?;
+    let mut builder = ConfigBuilder::new(language, node_types)
+        .with_allow_repeats(args.allow_repeats)
+        .with_batch_size(args.batch_size)
+        .with_candidate_weighting(args.candidate_weighting)
+        .with_delete_chaos(args.delete_chaos.unwrap_or(args.chaos))
+        .with_splice_chaos(args.splice_chaos.unwrap_or(args.chaos))
+        .with_chaos_weighting(args.chaos_weighting)
+        .with_crossover(args.crossover)
+        .with_deletions(args.deletions)
+        .with_direction(args.direction)
+        .with_drop_unchanged(args.drop_unchanged)
+        .with_duplications(args.duplications)
+        .with_exclude_kinds(leak(args.exclude_kind))
+        .with_field_aware(args.field_aware)
+        .with_file_selection(args.file_selection)
+        .with_honor_pragmas(args.honor_pragmas)
+        .with_insertions(args.insertions)
+        .with_intra_splices(args.intra_splices)
+        .with_inter_splices(args.mutations)
+        .with_min_depth(args.min_depth)
+        .with_min_splices(args.min_splices)
+        .with_number_mutations(args.number_mutations)
+        .with_preserve_comments(args.preserve_comments)
+        .with_preserve_prefix_bytes(args.preserve_prefix_bytes)
+        .with_reject_errors(args.reject_errors)
+        .with_renames(args.renames)
+        .with_reservoir_sampling(args.reservoir_sampling)
+        .with_sibling_swaps(args.sibling_swaps)
+        .with_string_mutations(args.string_mutations)
+        .with_structural_unique(args.structural_unique)
+        .with_supertype_splicing(args.supertype_splicing)
+        .with_tests(args.tests)
+        .with_unique(args.unique);
+    if let Some(kind) = args.anchor_kind {
+        builder = builder.with_anchor_kind(leak(vec![kind])[0]);
+    }
+    if let Some(query) = args.query {
+        builder = builder.with_query(query);
+    }
+    if let Some(seed) = args.seed {
+        builder = builder.with_seed(seed);
+    }
+    if !args.comment_kind.is_empty() {
+        builder = builder.with_comment_kinds(leak(args.comment_kind));
+    }
+    if !args.include_kind.is_empty() {
+        builder = builder.with_include_kinds(leak(args.include_kind));
+    }
+    if let Some(timeout) = args.timeout {
+        builder = builder.with_timeout(Duration::from_millis(timeout));
+    }
+    if let Some(budget) = args.total_bytes_budget {
+        builder = builder.with_total_bytes_budget(budget);
+    }
+    if let Some(max_bytes) = args.max_bytes {
+        builder = builder.with_max_bytes(max_bytes);
+    }
+    if let Some(ratio) = args.similar_size_ratio {
+        builder = builder.with_candidate_size_bias(CandidateSizeBias::Similar { ratio });
+    }
+    if let Some(max) = args.max_candidates_per_kind {
+        builder = builder.with_max_candidates_per_kind(max);
+    }
+    if let Some(max_depth) = args.max_depth {
+        builder = builder.with_max_depth(max_depth);
+    }
+    if let Some(max_tree_depth) = args.max_tree_depth {
+        builder = builder.with_max_tree_depth(max_tree_depth);
+    }
+    if let (Some(path), Some(kind)) = (&args.import_dictionary, args.dictionary_kind) {
+        let dict = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read dictionary {}", path.display()))?;
+        let tokens = splice::parse_dictionary(&dict)?;
+        builder = builder.with_dictionary(leak(vec![kind])[0], tokens);
+    }
+    if let Some(path) = &args.config {
+        let settings = read_settings(matches, builder.settings(), path)?;
+        builder = builder.with_settings(settings);
+    }
+    if args.print_config {
+        println!("{}", serde_json::to_string_pretty(&builder.settings())?);
+        return Ok(());
+    }
+    if args.seed.is_none() {
+        info!("Seed: {}", builder.settings().seed);
+    }
+    let config = builder.build()?;
+    let output = args.output_dir.as_ref().unwrap_or(&args.output);
+    if !args.stdout {
+        std::fs::create_dir_all(output).context("Couldn't create output directory")?;
+    }
+    let delimiter = unescape(&args.delimiter);
+    let mut stdout = io::BufWriter::new(io::stdout().lock());
+    let mut count = 0;
+    let mut dropped = 0;
+    let mut stats = RunStats::new();
+    let mut outputs = splice::splice_with_provenance(config, &files)?;
+    let hooks = Hooks {
+        validate: args.validate,
+        keep_rejected: args.keep_rejected,
+        format: args.format,
+    };
+    loop {
+        // Run the commands for a test per job at a time
+        let batch: Vec<_> = outputs
+            .by_ref()
+            .take(args.jobs.max(1))
+            .map(|out| {
+                stats.record(&out);
+                (out.source_file, out.bytes)
+            })
+            .collect();
+        if batch.is_empty() {
+            break;
+        }
+        let batch = if hooks.any() {
+            let hooks = &hooks;
+            thread::scope(|s| {
+                let workers: Vec<_> = batch
+                    .into_iter()
+                    .map(|(source, bytes)| (source, s.spawn(move || hooks.finish(bytes))))
+                    .collect();
+                workers
+                    .into_iter()
+                    .map(|(source, w)| Ok((source, w.join().expect("worker panicked")?)))
+                    .collect::<Result<Vec<_>>>()
+            })?
+        } else {
+            batch
+                .into_iter()
+                .map(|(source, bytes)| (source, Some(bytes)))
+                .collect()
+        };
+        for (source, bytes) in batch {
+            let Some(bytes) = bytes else {
+                dropped += 1;
+                continue;
+            };
+            if args.stdout {
+                stdout.write_all(&bytes)?;
+                stdout.write_all(&delimiter)?;
+                count += 1;
+                continue;
+            }
+            let mut path = if args.output_dir.is_some() {
+                output.join(hashed_name(&bytes, &source))
+            } else {
+                output.join(count.to_string())
+            };
+            if let Some(compress) = args.compress {
+                path = with_suffix(path, compress.extension());
+            }
+            if args.output_dir.is_some() && path.exists() {
+                continue;
+            }
+            save(&path, &bytes, args.compress).context("Couldn't save generated test case")?;
+            count += 1;
+        }
+    }
+    stdout.flush()?;
+    if hooks.validate.is_some() {
+        info!("Dropped {} tests after validation", dropped);
+    }
+    info!("Generated {} tests", count);
+    if let Some(budget) = args.total_bytes_budget {
+        info!(
+            "Made {} tests of {} bytes in all, the budget was {}",
+            stats.outputs, stats.bytes, budget
+        );
+    }
+    if stats.degraded_splices > 0 {
+        info!(
+            "{} batches of splices may have introduced parse errors",
+            stats.degraded_splices
+        );
+    }
+    if let Some(path) = &args.stats_json {
+        if path.as_os_str() == "-" {
+            eprintln!("{}", stats.to_json());
+        } else {
+            std::fs::write(path, stats.to_json()).context("Couldn't save statistics")?;
+        }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::node_types::NodeTypes;
+
+    #[test]
+    fn settings_round_trip() {
+        let language = tree_sitter_rust::language();
+        let builder = || {
+            let node_types = NodeTypes::new(tree_sitter_rust::NODE_TYPES).unwrap();
+            ConfigBuilder::new(language, node_types)
+        };
+        let settings = builder()
+            .with_candidate_size_bias(CandidateSizeBias::Similar { ratio: 2.0 })
+            .with_deletions(20)
+            .with_exclude_kinds(["line_comment"])
+            .with_max_bytes(1000)
+            .with_seed(7)
+            .with_tests(8)
+            .settings();
+        let source = "fn f(x: u8) -> u8 {\n    // one more\n    g(x, 1);\n    x + 1\n}\n";
+        let tree = parse(language, source).unwrap();
+        let files = HashMap::from([("a.rs".to_string(), (source.as_bytes().to_vec(), tree))]);
+        let run = |settings: Settings| -> Vec<Vec<u8>> {
+            let config = builder().with_settings(settings).build().unwrap();
+            splice::splice(config, &files).unwrap().collect()
+        };
+        let matches = Args::command().get_matches_from(["tree-splicer", "a.rs"]);
+        let dir = std::env::temp_dir().join(format!("tree-splicer-settings-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for (name, text) in [
+            (
+                "settings.json",
+                serde_json::to_string_pretty(&settings).unwrap(),
+            ),
+            ("settings.toml", toml::to_string(&settings).unwrap()),
+        ] {
+            let path = dir.join(name);
+            fs::write(&path, text).unwrap();
+            let read = read_settings(&matches, builder().settings(), &path).unwrap();
+            assert_eq!(read, settings);
+            assert_eq!(run(read), run(settings.clone()));
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn hashed_names() {
+        let name = hashed_name(b"fn main() {}", "src/main.rs");
+        let (hash, extension) = name.split_once('.').unwrap();
+        assert_eq!(hash.len(), 16);
+        assert!(hash.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_eq!(extension, "rs");
+        assert_eq!(name, hashed_name(b"fn main() {}", "other.rs"));
+        assert_ne!(name, hashed_name(b"fn main() { }", "src/main.rs"));
+        assert_eq!(hashed_name(b"", "Makefile").len(), 16);
+    }
+
+    #[test]
+    fn walks_matching_files() {
+        let dir = std::env::temp_dir().join(format!("tree-splicer-walk-{}", process::id()));
+        fs::create_dir_all(dir.join("nested/deeper")).unwrap();
+        for name in [
+            "a.sol",
+            "b.rs",
+            "notes.txt",
+            "nested/c.t.sol",
+            "nested/sol",
+            "nested/deeper/d.sol",
+        ] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        let walked = |extensions: &[&str]| {
+            let extensions: Vec<_> = extensions.iter().map(|e| e.to_string()).collect();
+            let mut paths = Vec::new();
+            walk(&dir, &extensions, &mut paths).unwrap();
+            let mut names: Vec<_> = paths
+                .iter()
+                .map(|p| p.strip_prefix(&dir).unwrap().to_string_lossy().into_owned())
+                .collect();
+            names.sort();
+            names
+        };
+        assert_eq!(
+            walked(&["sol", "t.sol"]),
+            ["a.sol", "nested/c.t.sol", "nested/deeper/d.sol"]
+        );
+        assert_eq!(walked(&[".t.sol"]), ["nested/c.t.sol"]);
+        assert_eq!(walked(&["rs"]), ["b.rs"]);
+        assert_eq!(walked(&[]).len(), 6);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn compressed_tests_decompress() {
+        let dir = std::env::temp_dir().join(format!("tree-splicer-compress-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let bytes = b"fn f() -> u8 {\n    g(1, 2)\n}\n".repeat(16);
+        let path = dir.join("0");
+        save(&path, &bytes, None).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), bytes);
+        let gz = with_suffix(path.clone(), Compress::Gz.extension());
+        save(&gz, &bytes, Some(Compress::Gz)).unwrap();
+        let mut decompressed = Vec::new();
+        flate2::read::GzDecoder::new(fs::File::open(&gz).unwrap())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, bytes);
+        let zst = with_suffix(path, Compress::Zstd.extension());
+        save(&zst, &bytes, Some(Compress::Zstd)).unwrap();
+        assert_eq!(
+            zstd::decode_all(fs::File::open(&zst).unwrap()).unwrap(),
+            bytes
+        );
+        assert!(zst.ends_with("0.zst"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn wrong_languages_are_skipped() {
+        let language = tree_sitter_rust::language();
+        let used =
+            |text: &str| handle_wrong_language("f", text, &parse(language, text).unwrap(), false);
+        assert!(used("fn f() {}"));
+        assert!(used("fn f( {}\nfn g() {}"));
+        assert!(used(" \n"));
+        assert!(!used("]) }) ]) }"));
+    }
+}
@@ -2,3 +2,5 @@
 pub mod cli;
 pub mod node_types;
 pub mod splice;
+#[cfg(feature = "wasm")]
+pub mod wasm;
@@ -4,7 +4,7 @@
 //
 // [treeedbgen]: https://github.com/langston-barrett/treeedb/blob/1a2fae3509c76cd5a8e1004f808ea800d49d1a19/treeedbgen/src/lib.rs
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use serde::{Deserialize, Serialize};
 
@@ -14,6 +14,8 @@ struct Node {
     #[serde(rename(deserialize = "type", serialize = "type"))]
     ty: String,
     named: bool,
+    #[serde(default)] // false
+    extra: bool,
     #[serde(default)] // empty
     children: Children,
     #[serde(default)] // empty
@@ -50,13 +52,48 @@ pub struct FieldInfo {
     required: bool,
 }
 
+/// Where children of a node may go, and what kinds they may have, see
+/// [`NodeTypes::legal_children`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChildSpec {
+    /// `None` for children that aren't in a field
+    pub field: Option<String>,
+    pub multiple: bool,
+    pub required: bool,
+    /// Kinds that may go here, including the subtypes of any supertypes
+    pub types: Vec<String>,
+}
+
 #[derive(Clone, Debug)]
 pub struct NodeTypes {
     children: HashMap<String, Children>,
+    legal_children: HashMap<String, Vec<ChildSpec>>,
+    named: HashSet<String>,
+    extra: HashSet<String>,
+    /// Names of the fields of each kind, sorted
+    fields: HashMap<String, Vec<String>>,
+    supertypes: HashSet<String>,
     subtypes: HashMap<String, Vec<String>>,
     reverse_fields: HashMap<String, Vec<FieldInfo>>,
 }
 
+/// `types` and all of their subtypes, without duplicates
+fn expand(types: &[Subtype], subtypes: &HashMap<String, Vec<String>>) -> Vec<String> {
+    let mut r: Vec<String> = Vec::new();
+    for ty in types {
+        let expanded = subtypes
+            .get(&ty.ty)
+            .cloned()
+            .unwrap_or_else(|| vec![ty.ty.clone()]);
+        for subty in expanded {
+            if !r.contains(&subty) {
+                r.push(subty);
+            }
+        }
+    }
+    r
+}
+
 fn subtypes(name: &str, nodes: &Vec<Node>) -> Vec<String> {
     let mut r = vec![name.to_string()];
     for n in nodes {
@@ -106,11 +143,60 @@ impl NodeTypes {
                 }
             }
         }
+        let legal_children = nodes
+            .iter()
+            .map(|node| {
+                let mut specs = Vec::with_capacity(node.fields.len() + 1);
+                if !node.children.types.is_empty() {
+                    specs.push(ChildSpec {
+                        field: None,
+                        multiple: node.children.multiple,
+                        required: node.children.required,
+                        types: expand(&node.children.types, &subtypes),
+                    });
+                }
+                let mut fields: Vec<_> = node.fields.iter().collect();
+                fields.sort_by_key(|(name, _)| *name);
+                for (name, field) in fields {
+                    specs.push(ChildSpec {
+                        field: Some(name.clone()),
+                        multiple: field.multiple,
+                        required: field.required,
+                        types: expand(&field.types, &subtypes),
+                    });
+                }
+                (node.ty.clone(), specs)
+            })
+            .collect();
         Ok(NodeTypes {
             children: nodes
                 .iter()
                 .map(|n| (n.ty.clone(), n.children.clone()))
                 .collect(),
+            legal_children,
+            named: nodes
+                .iter()
+                .filter(|n| n.named)
+                .map(|n| n.ty.clone())
+                .collect(),
+            extra: nodes
+                .iter()
+                .filter(|n| n.extra)
+                .map(|n| n.ty.clone())
+                .collect(),
+            fields: nodes
+                .iter()
+                .map(|n| {
+                    let mut names: Vec<_> = n.fields.keys().cloned().collect();
+                    names.sort();
+                    (n.ty.clone(), names)
+                })
+                .collect(),
+            supertypes: nodes
+                .iter()
+                .filter(|n| !n.subtypes.is_empty())
+                .map(|n| n.ty.clone())
+                .collect(),
             subtypes,
             reverse_fields,
         })
@@ -128,15 +214,75 @@ impl NodeTypes {
         true
     }
 
+    /// Whether `node` is the only child of its parent that isn't in a field,
+    /// and the parent requires such a child.
+    fn sole_required_child(&self, node: &tree_sitter::Node, parent: &tree_sitter::Node) -> bool {
+        if !node.is_named() || !self.children.get(parent.kind()).is_some_and(|c| c.required) {
+            return false;
+        }
+        let mut cursor = parent.walk();
+        if !cursor.goto_first_child() {
+            return false;
+        }
+        let mut others = 0;
+        let mut found = false;
+        loop {
+            let child = cursor.node();
+            if child.is_named() && !child.is_extra() && cursor.field_name().is_none() {
+                if child.id() == node.id() {
+                    found = true;
+                } else {
+                    others += 1;
+                }
+            }
+            if !cursor.goto_next_sibling() {
+                break;
+            }
+        }
+        found && others == 0
+    }
+
     /// Defaults to `true` if the real answer can't be determined.
     pub fn optional_node(&self, node: &tree_sitter::Node) -> bool {
         if let Some(p) = node.parent() {
-            self.optional(node.kind(), p.kind())
+            self.optional(node.kind(), p.kind()) && !self.sole_required_child(node, &p)
         } else {
             true
         }
     }
 
+    /// Whether a node of kind `node_kind` may be one of several siblings in
+    /// a node of kind `parent_kind`, either as a child or in a field.
+    fn repeatable(&self, node_kind: &str, parent_kind: &str) -> bool {
+        let is = |ty: &String| {
+            ty == node_kind
+                || self
+                    .subtypes
+                    .get(ty)
+                    .is_some_and(|subtys| subtys.iter().any(|t| t == node_kind))
+        };
+        if let Some(children) = self.children.get(parent_kind) {
+            if children.multiple && children.types.iter().any(|t| is(&t.ty)) {
+                return true;
+            }
+        }
+        if let Some(flds) = self.reverse_fields.get(node_kind) {
+            return flds
+                .iter()
+                .any(|fi| parent_kind == fi.parent_ty && fi.multiple);
+        }
+        false
+    }
+
+    /// Defaults to `false` if the real answer can't be determined.
+    pub fn repeatable_node(&self, node: &tree_sitter::Node) -> bool {
+        if let Some(p) = node.parent() {
+            self.repeatable(node.kind(), p.kind())
+        } else {
+            false
+        }
+    }
+
     // TODO(#21): Also include fields, include multiple and not required
     pub fn list_types(&self, node: &tree_sitter::Node) -> Vec<String> {
         let mut kinds = Vec::new();
@@ -150,7 +296,175 @@ impl NodeTypes {
         kinds
     }
 
+    /// Kinds that may appear wherever `kind` may, i.e., the subtypes of the
+    /// smallest supertype of `kind` (ties are broken by name). Just `kind` if
+    /// it has no supertype.
+    pub fn interchangeable<'a>(&'a self, kind: &'a str) -> Vec<&'a str> {
+        self.subtypes
+            .values()
+            .filter(|subtys| subtys[1..].iter().any(|t| t == kind))
+            .min_by_key(|subtys| (subtys.len(), &subtys[0]))
+            .map(|subtys| subtys.iter().map(String::as_str).collect())
+            .unwrap_or_else(|| vec![kind])
+    }
+
+    /// Where children of nodes of kind `kind` may go, children that aren't in
+    /// a field first, then fields by name. Empty if `kind` has no children
+    /// or isn't a kind.
+    pub fn legal_children(&self, kind: &str) -> &[ChildSpec] {
+        self.legal_children.get(kind).map_or(&[], Vec::as_slice)
+    }
+
+    /// The kinds that may be siblings of `node` in the same list, i.e., in
+    /// the same field of its parent, or also not in a field. Empty if that
+    /// can't be determined.
+    pub fn legal_siblings(&self, node: &tree_sitter::Node, field: Option<&str>) -> &[String] {
+        let Some(parent) = node.parent() else {
+            return &[];
+        };
+        self.legal_children(parent.kind())
+            .iter()
+            .find(|spec| {
+                spec.multiple
+                    && spec.field.as_deref() == field
+                    && spec.types.iter().any(|t| t == node.kind())
+            })
+            .map_or(&[], |spec| spec.types.as_slice())
+    }
+
+    /// Whether there are named nodes of kind `kind`
+    pub fn is_named(&self, kind: &str) -> bool {
+        self.named.contains(kind)
+    }
+
+    /// Whether nodes of kind `kind` may appear anywhere, e.g., comments.
+    /// Older versions of tree-sitter don't say, then this is always `false`.
+    pub fn is_extra(&self, kind: &str) -> bool {
+        self.extra.contains(kind)
+    }
+
+    /// Names of the fields of nodes of kind `kind`, sorted. Empty if it has
+    /// none or isn't a kind.
+    pub fn fields(&self, kind: &str) -> &[String] {
+        self.fields.get(kind).map_or(&[], Vec::as_slice)
+    }
+
+    /// Whether `kind` is a supertype, i.e., a hidden kind that stands for any
+    /// one of its subtypes
+    pub fn is_supertype(&self, kind: &str) -> bool {
+        self.supertypes.contains(kind)
+    }
+
+    /// `kind` followed by all of its subtypes, recursively. Panics if `kind`
+    /// isn't a kind.
     pub fn subtypes(&self, kind: &String) -> &[String] {
         self.subtypes.get(kind).expect("Invalid node kind")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A call with a function in a field and any number of arguments
+    const CALLS: &str = r#"[
+        {
+            "type": "_expression",
+            "named": true,
+            "subtypes": [
+                {"type": "number", "named": true},
+                {"type": "call", "named": true}
+            ]
+        },
+        {
+            "type": "call",
+            "named": true,
+            "fields": {
+                "function": {
+                    "multiple": false,
+                    "required": true,
+                    "types": [{"type": "identifier", "named": true}]
+                }
+            },
+            "children": {
+                "multiple": true,
+                "required": false,
+                "types": [{"type": "_expression", "named": true}]
+            }
+        },
+        {"type": "comment", "named": true, "extra": true},
+        {"type": "identifier", "named": true},
+        {"type": "number", "named": true},
+        {"type": "(", "named": false}
+    ]"#;
+
+    #[test]
+    fn legal_children_expand_supertypes() {
+        let node_types = NodeTypes::new(CALLS).unwrap();
+        let strings = |kinds: &[&str]| kinds.iter().map(|k| k.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            node_types.legal_children("call"),
+            [
+                ChildSpec {
+                    field: None,
+                    multiple: true,
+                    required: false,
+                    types: strings(&["_expression", "number", "call"]),
+                },
+                ChildSpec {
+                    field: Some("function".to_string()),
+                    multiple: false,
+                    required: true,
+                    types: strings(&["identifier"]),
+                },
+            ]
+        );
+        assert!(node_types.legal_children("number").is_empty());
+        assert!(node_types.legal_children("nope").is_empty());
+
+        let node_types = NodeTypes::new(tree_sitter_rust::NODE_TYPES).unwrap();
+        let arguments = node_types.legal_children("arguments");
+        assert_eq!(arguments.len(), 1);
+        assert!(arguments[0].multiple && arguments[0].field.is_none());
+        let legal = |kind: &str| arguments[0].types.iter().any(|t| t == kind);
+        assert!(legal("call_expression") && legal("integer_literal"));
+        assert!(!legal("let_declaration") && !legal("parameter"));
+    }
+
+    #[test]
+    fn accessors_describe_the_kinds() {
+        let node_types = NodeTypes::new(CALLS).unwrap();
+        assert!(node_types.is_named("call") && node_types.is_named("_expression"));
+        assert!(!node_types.is_named("(") && !node_types.is_named("nope"));
+        assert!(node_types.is_extra("comment"));
+        assert!(!node_types.is_extra("number"));
+        assert_eq!(node_types.fields("call"), ["function"]);
+        assert!(node_types.fields("number").is_empty());
+        assert!(node_types.fields("nope").is_empty());
+        assert!(node_types.is_supertype("_expression"));
+        assert!(!node_types.is_supertype("call"));
+        let expression = node_types.subtypes(&"_expression".to_string());
+        assert_eq!(expression[0], "_expression");
+        let mut subtypes = expression[1..].to_vec();
+        subtypes.sort();
+        subtypes.dedup();
+        assert_eq!(subtypes, ["call", "number"]);
+        assert_eq!(node_types.subtypes(&"number".to_string()), ["number"]);
+
+        let node_types = NodeTypes::new(tree_sitter_rust::NODE_TYPES).unwrap();
+        assert!(node_types.is_named("identifier"));
+        assert!(!node_types.is_named("fn"));
+        assert!(!node_types.is_extra("identifier"));
+        let fields = node_types.fields("function_item");
+        assert!(["name", "parameters", "body"]
+            .iter()
+            .all(|f| fields.iter().any(|g| g == f)));
+        assert!(fields.windows(2).all(|w| w[0] < w[1]));
+        assert!(node_types.is_supertype("_expression"));
+        assert!(!node_types.is_supertype("call_expression"));
+        assert!(node_types
+            .subtypes(&"_expression".to_string())
+            .iter()
+            .any(|t| t == "call_expression"));
+    }
+}
@@ -1,19 +1,94 @@
 #![allow(dead_code)]
-use std::collections::{HashMap, HashSet};
+use std::borrow::Cow;
+use std::cell::OnceCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::ops::ControlFlow;
+use std::ops::Range;
+use std::sync::Arc;
+#[cfg(feature = "parallel")]
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex,
+};
+use std::time::{Duration, Instant};
 
-use rand::{prelude::StdRng, Rng, SeedableRng};
-use tree_sitter::{Language, Node, Tree};
+use rand::{prelude::StdRng, Rng, RngCore, SeedableRng};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use rustc_hash::{FxHashMap, FxHashSet, FxHasher};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+use tree_sitter::{
+    InputEdit, Language, LanguageError, Node, Parser, Point, Query, QueryCursor, Tree, TreeCursor,
+};
 
-use tree_sitter_edit::{Editor, NodeId};
+use tree_sitter_edit::Editor;
 
 use crate::node_types::NodeTypes;
 
+#[derive(Debug)]
+pub enum SpliceError {
+    /// The grammar is incompatible with the linked version of tree-sitter
+    Language(LanguageError),
+    /// tree-sitter didn't produce a tree
+    Parse,
+    /// A [`Config`] setting is out of range
+    InvalidConfig(String),
+    /// There were no input files, or all of them were empty or failed to
+    /// parse
+    EmptyCorpus,
+    /// A [`MutationTrace`] doesn't apply to the files passed to [`replay`]
+    Replay(String),
+    /// A file doesn't look like it's in the language of the grammar, see
+    /// [`Splicer::add_input`]
+    WrongLanguage,
+}
+
+impl fmt::Display for SpliceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SpliceError::Language(e) => write!(f, "Failed to set tree-sitter parser language: {e}"),
+            SpliceError::Parse => write!(f, "Failed to parse code"),
+            SpliceError::InvalidConfig(msg) => write!(f, "Invalid configuration: {msg}"),
+            SpliceError::EmptyCorpus => write!(
+                f,
+                "No input files to splice; provide at least one non-empty file that parses"
+            ),
+            SpliceError::Replay(msg) => write!(f, "Failed to replay mutations: {msg}"),
+            SpliceError::WrongLanguage => {
+                write!(f, "The code doesn't look like it's in the right language")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SpliceError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SpliceError::Language(e) => Some(e),
+            SpliceError::Parse
+            | SpliceError::InvalidConfig(_)
+            | SpliceError::EmptyCorpus
+            | SpliceError::Replay(_)
+            | SpliceError::WrongLanguage => None,
+        }
+    }
+}
+
+impl From<LanguageError> for SpliceError {
+    fn from(e: LanguageError) -> Self {
+        SpliceError::Language(e)
+    }
+}
+
 #[derive(Debug, Default)]
-pub struct Edits<'a>(HashMap<usize, &'a [u8]>);
+pub struct Edits<'a>(FxHashMap<usize, &'a [u8]>);
 
 impl<'a> Editor for Edits<'a> {
     fn has_edit(&self, _tree: &Tree, node: &Node) -> bool {
-        self.0.get(&node.id()).is_some()
+        self.0.contains_key(&node.id())
     }
 
     fn edit(&self, _source: &[u8], tree: &Tree, node: &Node) -> Vec<u8> {
@@ -22,248 +97,6770 @@ impl<'a> Editor for Edits<'a> {
     }
 }
 
-#[derive(Debug)]
-struct Branches<'a>(HashMap<&'static str, Vec<&'a [u8]>>);
+/// A node kind, and the field the node occupies in its parent if splicing is
+/// field-aware
+type Key = (Option<&'static str>, &'static str);
+
+/// The name, text, and tree of a file from [`Splicer::add_input`]
+type AddedFile = (Arc<str>, Arc<[u8]>, Arc<Tree>);
+
+/// Replacement of a node with new text
+type NodeEdit<'n, 't> = (Node<'n>, Cow<'t, [u8]>);
+
+/// Candidates are kept in sorted order so that splicing is deterministic for
+/// a given seed.
+#[derive(Clone, Debug)]
+struct Branches<'a>(
+    BTreeMap<Key, Vec<&'a [u8]>>,
+    /// How many times each candidate occurs in the corpus, in the same order
+    BTreeMap<Key, Vec<usize>>,
+);
+
+/// The texts that may replace nodes of each kind, and how many times each
+/// occurs in the corpus, see [`candidate_counts`]
+pub type CandidateCounts<'a> = BTreeMap<&'static str, BTreeMap<&'a [u8], usize>>;
+
+/// Candidates of each kind, and how many times each occurs
+type Counted<'a> = BTreeMap<Key, BTreeMap<&'a [u8], usize>>;
 
 impl<'a> Branches<'a> {
-    fn new(trees: Vec<(&'a [u8], &'a Tree)>) -> Self {
-        let mut branches = HashMap::with_capacity(trees.len()); // min
+    /// Collect the text of every node of an `allowed` kind, except `ERROR` and
+    /// `MISSING` nodes.
+    ///
+    /// If `max_per_kind` is set, keep at most that many texts of each kind,
+    /// chosen by reservoir sampling with `rng`.
+    fn new(
+        trees: Vec<(&'a [u8], &'a Tree)>,
+        allowed: impl Fn(&str) -> bool + Sync,
+        field_aware: bool,
+        max_per_kind: Option<usize>,
+        rng: &mut impl Rng,
+    ) -> Self {
+        let max = match max_per_kind {
+            Some(max) => max,
+            None => return Self::all(trees, &allowed, field_aware),
+        };
+        // Texts are only counted while they're kept
+        let mut branches: BTreeMap<Key, (BTreeMap<&[u8], usize>, usize)> = BTreeMap::new();
         for (text, tree) in trees {
-            let mut nodes = vec![tree.root_node()];
-            while !nodes.is_empty() {
-                let mut children = Vec::with_capacity(nodes.len()); // guesstimate
-                for node in nodes {
-                    branches
-                        .entry(node.kind())
-                        .or_insert_with(|| HashSet::with_capacity(1))
-                        .insert(&text[node.byte_range()]);
-                    let mut i = 0;
-                    while let Some(child) = node.child(i) {
-                        children.push(child);
-                        i += 1;
+            for_each_branch(text, tree, &allowed, field_aware, |key, branch| {
+                let (kept, seen) = branches.entry(key).or_default();
+                if let Some(count) = kept.get_mut(branch) {
+                    *count += 1;
+                    return;
+                }
+                *seen += 1;
+                if kept.len() < max {
+                    kept.insert(branch, 1);
+                } else {
+                    let i = rng.gen_range(0..*seen);
+                    if let Some(evicted) = kept.keys().nth(i).copied() {
+                        kept.remove(evicted);
+                        kept.insert(branch, 1);
                     }
                 }
-                nodes = children;
+            });
+        }
+        Self::counted(branches.into_iter().map(|(k, (c, _))| (k, c)).collect())
+    }
+
+    fn counted(counted: Counted<'a>) -> Self {
+        let mut candidates = BTreeMap::new();
+        let mut counts = BTreeMap::new();
+        for (key, texts) in counted {
+            // None were kept, as with a maximum of 0 per kind
+            if texts.is_empty() {
+                continue;
             }
+            let (texts, n) = texts.into_iter().unzip();
+            candidates.insert(key, texts);
+            counts.insert(key, n);
         }
-        Branches(
-            branches
-                .into_iter()
-                .map(|(k, s)| (k, s.iter().copied().collect()))
-                .collect(),
-        )
+        Branches(candidates, counts)
+    }
+
+    /// Like [`Branches::new`], without a maximum per kind. With the `parallel`
+    /// feature, files are walked in parallel. The candidates are sorted either
+    /// way, so they're the same.
+    fn all(
+        trees: Vec<(&'a [u8], &'a Tree)>,
+        allowed: &(impl Fn(&str) -> bool + Sync),
+        field_aware: bool,
+    ) -> Self {
+        #[cfg(feature = "parallel")]
+        let found = trees
+            .into_par_iter()
+            .map(|file| Self::in_file(file, allowed, field_aware))
+            .reduce(Counted::new, Self::merge_counts);
+        #[cfg(not(feature = "parallel"))]
+        let found = Self::sequential(trees, allowed, field_aware);
+        Self::counted(found)
+    }
+
+    /// Count the candidates for [`Branches::all`], one file at a time
+    #[cfg(any(test, not(feature = "parallel")))]
+    fn sequential(
+        trees: Vec<(&'a [u8], &'a Tree)>,
+        allowed: &impl Fn(&str) -> bool,
+        field_aware: bool,
+    ) -> Counted<'a> {
+        trees
+            .into_iter()
+            .map(|file| Self::in_file(file, allowed, field_aware))
+            .fold(Counted::new(), Self::merge_counts)
+    }
+
+    /// Count the candidates in one file
+    fn in_file(
+        (text, tree): (&'a [u8], &'a Tree),
+        allowed: &impl Fn(&str) -> bool,
+        field_aware: bool,
+    ) -> Counted<'a> {
+        let mut found = Counted::new();
+        for_each_branch(text, tree, allowed, field_aware, |key, branch| {
+            *found.entry(key).or_default().entry(branch).or_default() += 1;
+        });
+        found
+    }
+
+    /// Add the counts in `found` to `all`
+    fn merge_counts(mut all: Counted<'a>, found: Counted<'a>) -> Counted<'a> {
+        for (key, branches) in found {
+            let counts = all.entry(key).or_default();
+            for (branch, n) in branches {
+                *counts.entry(branch).or_default() += n;
+            }
+        }
+        all
+    }
+
+    /// Add `token` to the candidates for nodes of kind `kind`, in any field.
+    /// It counts as occurring once, unless it's already a candidate.
+    fn add(&mut self, kind: &'static str, token: &'a [u8]) {
+        let keys: Vec<_> = self.0.keys().copied().filter(|(_, k)| *k == kind).collect();
+        if keys.is_empty() {
+            self.insert((None, kind), token, 1);
+        }
+        for key in keys {
+            if self.count(&key, token) == 0 {
+                self.insert(key, token, 1);
+            }
+        }
+    }
+
+    /// Add `n` occurrences of `branch` to the candidates for `key`
+    fn insert(&mut self, key: Key, branch: &'a [u8], n: usize) {
+        let candidates = self.0.entry(key).or_default();
+        let counts = self.1.entry(key).or_default();
+        match candidates.binary_search(&branch) {
+            Ok(i) => counts[i] += n,
+            Err(i) => {
+                candidates.insert(i, branch);
+                counts.insert(i, n);
+            }
+        }
+    }
+
+    /// How many times `branch` occurs as a candidate for `key`
+    fn count(&self, key: &Key, branch: &[u8]) -> usize {
+        match (self.0.get(key), self.1.get(key)) {
+            (Some(candidates), Some(counts)) => {
+                candidates.binary_search(&branch).map_or(0, |i| counts[i])
+            }
+            _ => 0,
+        }
+    }
+
+    /// Add the candidates in `other`, except new ones for kinds that already
+    /// have `max_per_kind` candidates
+    fn merge(&mut self, other: Branches<'a>, max_per_kind: Option<usize>) {
+        for ((key, added), counts) in other.0.into_iter().zip(other.1.into_values()) {
+            for (branch, n) in added.into_iter().zip(counts) {
+                let full = self
+                    .0
+                    .get(&key)
+                    .is_some_and(|c| max_per_kind.is_some_and(|max| c.len() >= max));
+                if full && self.count(&key, branch) == 0 {
+                    continue;
+                }
+                self.insert(key, branch, n);
+            }
+        }
+    }
+
+    /// Every candidate of each kind, in any field, and how many times it
+    /// occurs
+    fn by_kind(&self) -> CandidateCounts<'a> {
+        let mut by_kind = CandidateCounts::new();
+        for (((_, kind), candidates), counts) in self.0.iter().zip(self.1.values()) {
+            let by_text = by_kind.entry(*kind).or_default();
+            for (branch, n) in candidates.iter().zip(counts) {
+                *by_text.entry(*branch).or_default() += n;
+            }
+        }
+        by_kind
     }
 
     fn possible(&self) -> usize {
         let mut possible_mutations = 0;
         for s in self.0.values() {
-            possible_mutations += s.len() - 1;
+            possible_mutations += s.len().saturating_sub(1);
         }
         possible_mutations
     }
 }
 
-fn parse(language: Language, code: &str) -> tree_sitter::Tree {
-    let mut parser = tree_sitter::Parser::new();
-    parser
-        .set_language(language)
-        .expect("Failed to set tree-sitter parser language");
-    parser.parse(code, None).expect("Failed to parse code")
+/// Call `f` with the key and text of every node of an `allowed` kind in
+/// `tree`, except `ERROR` and `MISSING` nodes
+fn for_each_branch<'a>(
+    text: &'a [u8],
+    tree: &Tree,
+    allowed: impl Fn(&str) -> bool,
+    field_aware: bool,
+    mut f: impl FnMut(Key, &'a [u8]),
+) {
+    let mut cursor = tree.walk();
+    'walk: loop {
+        let node = cursor.node();
+        // Fragments of broken code would spread the breakage
+        if allowed(node.kind()) && !node.is_error() && !node.is_missing() {
+            let field = if field_aware {
+                cursor.field_name()
+            } else {
+                None
+            };
+            f((field, node.kind()), &text[node.byte_range()]);
+        }
+        if cursor.goto_first_child() {
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() {
+                break 'walk;
+            }
+        }
+    }
 }
 
-#[derive(Debug)]
-pub struct Config {
-    pub chaos: u8,
-    pub deletions: u8,
-    pub language: Language,
-    // pub intra_splices: usize,
-    pub inter_splices: usize,
-    pub node_types: NodeTypes,
-    pub seed: u64,
-    pub tests: usize,
+/// Whether two byte ranges intersect, or one is nested inside the other
+fn overlaps(r0: &Range<usize>, r1: &Range<usize>) -> bool {
+    (r0.start < r1.end && r1.start < r0.end)
+        || (r0.start <= r1.start && r1.end <= r0.end)
+        || (r1.start <= r0.start && r0.end <= r1.end)
 }
 
-struct Splicer<'a> {
-    language: Language,
-    branches: Branches<'a>,
-    chaos: u8,
-    deletions: u8,
-    kinds: Vec<&'static str>,
-    // intra_splices: usize,
-    inter_splices: usize,
-    node_types: NodeTypes,
-    trees: Vec<(&'a [u8], &'a Tree)>,
-    remaining: usize,
-    rng: StdRng,
+/// Number of bytes in both ranges
+fn overlap(r0: &Range<usize>, r1: &Range<usize>) -> usize {
+    r0.end.min(r1.end).saturating_sub(r0.start.max(r1.start))
 }
 
-impl<'a> Splicer<'a> {
-    fn pick_usize(&mut self, n: usize) -> usize {
-        self.rng.gen_range(0..n)
+/// Where text ends up after inserting `bytes` at `start`
+fn end_point(start: Point, bytes: &[u8]) -> Point {
+    match bytes.iter().rposition(|b| *b == b'\n') {
+        None => Point::new(start.row, start.column + bytes.len()),
+        Some(i) => Point::new(
+            start.row + bytes.iter().filter(|b| **b == b'\n').count(),
+            bytes.len() - i - 1,
+        ),
     }
+}
 
-    fn pick_idx<T>(&mut self, v: &Vec<T>) -> usize {
-        self.pick_usize(v.len())
+/// Describe replacements of nodes with new text to tree-sitter, so that it
+/// can reuse the old tree when re-parsing.
+///
+/// Returns `None` if the edits overlap or don't account for the change in
+/// length of the text, in which case the text should be parsed from scratch.
+fn input_edits(old_len: usize, new_len: usize, edits: &[(Node, &[u8])]) -> Option<Vec<InputEdit>> {
+    let mut len = old_len;
+    for (i, (node, bytes)) in edits.iter().enumerate() {
+        if edits[i + 1..]
+            .iter()
+            .any(|(n, _)| overlaps(&n.byte_range(), &node.byte_range()))
+        {
+            return None;
+        }
+        len = len + bytes.len() - node.byte_range().len();
+    }
+    if len != new_len {
+        return None;
     }
+    // Apply edits back-to-front so earlier positions stay valid
+    let mut edits = edits.to_vec();
+    edits.sort_unstable_by_key(|(node, _)| std::cmp::Reverse(node.start_byte()));
+    Some(
+        edits
+            .iter()
+            .map(|(node, bytes)| InputEdit {
+                start_byte: node.start_byte(),
+                old_end_byte: node.end_byte(),
+                new_end_byte: node.start_byte() + bytes.len(),
+                start_position: node.start_position(),
+                old_end_position: node.end_position(),
+                new_end_position: end_point(node.start_position(), bytes),
+            })
+            .collect(),
+    )
+}
 
-    fn all_nodes<'b>(&self, tree: &'b Tree) -> Vec<Node<'b>> {
-        let mut all = Vec::with_capacity(16); // min
-        let root = tree.root_node();
-        let mut cursor = tree.walk();
-        let mut nodes: HashSet<_> = root.children(&mut cursor).collect();
-        while !nodes.is_empty() {
-            let mut next = HashSet::new();
-            for node in nodes {
-                debug_assert!(!next.contains(&node));
-                all.push(node);
-                let mut child_cursor = tree.walk();
-                for child in node.children(&mut child_cursor) {
-                    debug_assert!(child.id() != node.id());
-                    debug_assert!(!next.contains(&child));
-                    next.insert(child);
-                }
+/// Number of `ERROR` and `MISSING` nodes in a tree
+fn errors(tree: &Tree) -> usize {
+    let mut count = 0;
+    let mut cursor = tree.walk();
+    loop {
+        let node = cursor.node();
+        if node.is_error() || node.is_missing() {
+            count += 1;
+        }
+        // Skip subtrees without errors
+        if node.has_error() && cursor.goto_first_child() {
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() {
+                return count;
             }
-            nodes = next;
         }
-        all
     }
+}
 
-    fn pick_node<'b>(&mut self, tree: &'b Tree) -> Node<'b> {
-        let nodes = self.all_nodes(tree);
-        if nodes.is_empty() {
-            return tree.root_node();
+/// Whether `text` looks like it's in the language it was parsed with into
+/// `tree`: it's blank, or it has something in it other than errors
+pub(crate) fn in_language(text: &[u8], tree: &Tree) -> bool {
+    let root = tree.root_node();
+    // Nothing but errors is as bad as nothing at all
+    let mut cursor = root.walk();
+    let parsed = !root.is_error() && root.named_children(&mut cursor).any(|c| !c.is_error());
+    parsed || text.iter().all(u8::is_ascii_whitespace)
+}
+
+/// Number of nodes in `tree`, including the root
+fn node_count(tree: &Tree) -> usize {
+    let mut count = 0;
+    let mut cursor = tree.walk();
+    loop {
+        count += 1;
+        if cursor.goto_first_child() {
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() {
+                return count;
+            }
         }
-        *nodes.get(self.pick_idx(&nodes)).unwrap()
     }
+}
 
-    fn delete_node(&mut self, _text: &[u8], tree: &Tree) -> (usize, Vec<u8>) {
-        let chaotic = self.rng.gen_range(0..100) < self.chaos;
-        if chaotic {
-            return (self.pick_node(tree).id(), Vec::new());
+/// Depth of the deepest node in `tree`, where the root is at depth 0
+fn tree_depth(tree: &Tree) -> usize {
+    let mut depth = 0;
+    let mut deepest = 0;
+    let mut cursor = tree.walk();
+    loop {
+        if cursor.goto_first_child() {
+            depth += 1;
+            deepest = std::cmp::max(deepest, depth);
+            continue;
         }
-        let nodes = self.all_nodes(tree);
-        if nodes.iter().all(|n| !self.node_types.optional_node(n)) {
-            return (self.pick_node(tree).id(), Vec::new());
-        }
-        let mut node = nodes.get(self.pick_idx(&nodes)).unwrap();
-        while !self.node_types.optional_node(node) {
-            node = nodes.get(self.pick_idx(&nodes)).unwrap();
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() {
+                return deepest;
+            }
+            depth -= 1;
         }
-        (node.id(), Vec::new())
     }
+}
 
-    fn splice_node(&mut self, text: &[u8], tree: &Tree) -> (usize, Vec<u8>) {
-        let chaotic = self.rng.gen_range(0..100) < self.chaos;
+fn compile_query(language: Language, query: &str) -> Result<Query, SpliceError> {
+    Query::new(language, query)
+        .map_err(|e| SpliceError::InvalidConfig(format!("invalid query: {e}")))
+}
 
-        let mut node = tree.root_node();
-        let mut candidates = Vec::new();
-        // When modified trees are re-parsed, their nodes may have novel kinds
-        // not in Branches (candidates.len() == 0). Also, avoid not mutating
-        // (candidates.len() == 1).
-        while candidates.len() <= 1 {
-            node = self.pick_node(tree);
-            candidates = if chaotic {
-                let kind_idx = self.rng.gen_range(0..self.kinds.len());
-                let kind = self.kinds.get(kind_idx).unwrap();
-                self.branches.0.get(kind).unwrap().clone()
-            } else {
-                self.branches
-                    .0
-                    .get(node.kind())
-                    .cloned()
-                    .unwrap_or_default()
-            };
+fn parser(language: Language) -> Result<Parser, SpliceError> {
+    let mut parser = Parser::new();
+    parser.set_language(language)?;
+    Ok(parser)
+}
+
+/// A parser for a language that has already been checked, reused across
+/// re-parses. Clones get their own parser.
+struct ReusedParser(Parser);
+
+impl Clone for ReusedParser {
+    fn clone(&self) -> Self {
+        let language = self.0.language().expect("Parser language was set");
+        ReusedParser(parser(language).expect("Language was checked"))
+    }
+}
+
+fn parse(language: Language, code: &[u8], old_tree: Option<&Tree>) -> Result<Tree, SpliceError> {
+    parser(language)?
+        .parse(code, old_tree)
+        .ok_or(SpliceError::Parse)
+}
+
+/// Outputs aren't adversarial, so FxHash is good enough, and faster than the
+/// default on long texts, see `benches/splice.rs`
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hasher = FxHasher::default();
+    bytes.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// A hash of the kinds of the nodes in `tree` and the text of its leaves,
+/// which ignores whitespace between nodes
+fn structural_hash(text: &[u8], tree: &Tree) -> u64 {
+    let mut hasher = FxHasher::default();
+    let mut cursor = tree.walk();
+    loop {
+        let node = cursor.node();
+        node.kind_id().hash(&mut hasher);
+        node.child_count().hash(&mut hasher);
+        if node.child_count() == 0 {
+            text[node.byte_range()].trim_ascii().hash(&mut hasher);
+        }
+        if cursor.goto_first_child() {
+            continue;
+        }
+        while !cursor.goto_next_sibling() {
+            if !cursor.goto_parent() {
+                return hasher.finish();
+            }
         }
+    }
+}
 
-        let idx = self.rng.gen_range(0..candidates.len());
-        let mut candidate = candidates.get(idx).unwrap();
-        // Try to avoid not mutating
-        let node_text = &text[node.byte_range()];
-        while candidates.len() > 1 && candidate == &node_text {
-            let idx = self.rng.gen_range(0..candidates.len());
-            candidate = candidates.get(idx).unwrap();
+/// The name of the field that `node` occupies in its parent, if any
+fn field_name(node: Node) -> Option<&'static str> {
+    let parent = node.parent()?;
+    let mut cursor = parent.walk();
+    if !cursor.goto_first_child() {
+        return None;
+    }
+    loop {
+        if cursor.node().id() == node.id() {
+            return cursor.field_name();
+        }
+        if !cursor.goto_next_sibling() {
+            return None;
         }
-        // eprintln!(
-        //     "Replacing '{}' with '{}'",
-        //     std::str::from_utf8(&text[node.byte_range()]).unwrap(),
-        //     std::str::from_utf8(candidate).unwrap(),
-        // );
-        (node.id(), Vec::from(*candidate))
     }
+}
 
-    fn splice_tree(&mut self, text0: &[u8], mut tree: Tree) -> Option<Vec<u8>> {
-        let splices = self.rng.gen_range(0..self.inter_splices);
-        let mut text = Vec::from(text0);
-        for _ in 0..splices {
-            let (id, bytes) = if self.rng.gen_range(0..100) < self.deletions {
-                self.delete_node(text.as_slice(), &tree)
-            } else {
-                self.splice_node(text.as_slice(), &tree)
-            };
-            let id = NodeId { id };
-            let bytes = bytes.to_vec();
-            let mut result = Vec::with_capacity(text.len() / 4); // low guesstimate
-            tree_sitter_edit::render(
-                &mut result,
-                &tree,
-                text.as_slice(),
-                &tree_sitter_edit::Replace { id, bytes },
-            )
-            .ok()?;
-            text = result.clone();
-            tree = parse(self.language, &String::from_utf8_lossy(text.as_slice()));
+/// The text of `node` followed by `bytes`, separated like `node` is from its
+/// named neighbors (e.g., by a comma and a space, or a newline)
+fn insert_after(text: &[u8], node: Node, bytes: &[u8]) -> Vec<u8> {
+    // Not the unnamed siblings, which are the separators themselves
+    let separator = match (node.prev_named_sibling(), node.next_named_sibling()) {
+        (_, Some(next)) => &text[node.end_byte()..next.start_byte()],
+        (Some(prev), None) => &text[prev.end_byte()..node.start_byte()],
+        (None, None) => b" ",
+    };
+    let node_text = &text[node.byte_range()];
+    let mut out = Vec::with_capacity(node_text.len() + separator.len() + bytes.len());
+    out.extend_from_slice(node_text);
+    out.extend_from_slice(if separator.is_empty() {
+        b" "
+    } else {
+        separator
+    });
+    out.extend_from_slice(bytes);
+    out
+}
+
+/// Whether `node` is a named leaf with a kind like `identifier` or
+/// `type_identifier`
+fn is_identifier(node: &Node) -> bool {
+    node.is_named() && node.child_count() == 0 && node.kind().ends_with("identifier")
+}
+
+/// Whether `node` is a named leaf with a kind like `integer_literal` or
+/// `number`
+fn is_number_literal(node: &Node) -> bool {
+    let kind = node.kind();
+    node.is_named()
+        && node.child_count() == 0
+        && ["float", "integer", "number"]
+            .iter()
+            .any(|k| kind.contains(k))
+}
+
+/// Whether `node` is named with a kind like `string_literal` or `string`,
+/// and its text ends with a quote
+fn is_string_literal(text: &[u8], node: &Node) -> bool {
+    node.is_named()
+        && node.kind().contains("string")
+        && matches!(text[node.byte_range()].last(), Some(b'"' | b'\'' | b'`'))
+}
+
+/// Numbers that often expose bugs, e.g., because they overflow
+const BOUNDARY_INTEGERS: &[&str] = &[
+    "0",
+    "1",
+    "127",
+    "128",
+    "255",
+    "256",
+    "2147483647",
+    "2147483648",
+    "4294967295",
+    "4294967296",
+    "9223372036854775807",
+    "9223372036854775808",
+    "18446744073709551615",
+    "18446744073709551616",
+];
+
+const BOUNDARY_FLOATS: &[&str] = &["0.0", "1.0e308", "1.0e309", "5.0e-324", "1.0e-400"];
+
+/// Contents of strings that often expose bugs
+const STRING_CONTENTS: &[&str] = &["", r"\\", r"\\\\", r"\n", r"\0", r"\x00", r"\u{0}"];
+
+/// See [`Splicer::splice_tree`]
+struct Spliced<'t> {
+    text: Vec<u8>,
+    /// The parse of `text`, borrowed if nothing changed
+    tree: Cow<'t, Tree>,
+    /// Operators applied to the text, in order
+    operators: Vec<Operator>,
+    /// See [`Output::kinds`]
+    kinds: Vec<&'static str>,
+    /// See [`Output::render_failures`]
+    render_failures: usize,
+    /// See [`Output::degraded_splices`]
+    degraded_splices: usize,
+    /// See [`Output::edits`]
+    edits: Vec<Edit>,
+}
+
+/// Nodes that may be mutated
+enum Nodes<'t> {
+    Collected(Vec<Node<'t>>),
+    /// Found by walking the tree each time one is needed, to save memory
+    Walk {
+        tree: &'t Tree,
+        /// See [`Splicer::kept`]
+        kept: Vec<Range<usize>>,
+        within: Within<'t>,
+    },
+}
+
+/// Where in a tree nodes may be mutated, see [`Splicer::within`]
+struct Within<'t> {
+    /// IDs of the nodes captured by `query`, if there is one
+    captured: Option<FxHashSet<usize>>,
+    /// A node of kind `anchor_kind`, if set
+    anchor: Option<Node<'t>>,
+}
+
+impl Within<'_> {
+    fn contains(&self, node: &Node) -> bool {
+        self.captured
+            .as_ref()
+            .is_none_or(|ids| ids.contains(&node.id()))
+            && self.anchor.is_none_or(|anchor| {
+                let mut parent = node.parent();
+                while let Some(p) = parent {
+                    if p.id() == anchor.id() {
+                        return true;
+                    }
+                    parent = p.parent();
+                }
+                false
+            })
+    }
+}
+
+/// Nodes of a tree from the root down to `max_depth`, along with their
+/// depths
+struct Descendants<'t> {
+    cursor: TreeCursor<'t>,
+    depth: usize,
+    max_depth: Option<usize>,
+    started: bool,
+    done: bool,
+}
+
+impl<'t> Descendants<'t> {
+    fn new(tree: &'t Tree, max_depth: Option<usize>) -> Self {
+        Descendants {
+            cursor: tree.walk(),
+            depth: 0,
+            max_depth,
+            started: false,
+            done: false,
         }
-        Some(text)
     }
 }
 
-impl<'a> Iterator for Splicer<'a> {
-    type Item = Vec<u8>;
+impl<'t> Iterator for Descendants<'t> {
+    type Item = (Node<'t>, usize);
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.remaining == 0 {
+        if self.done {
             return None;
         }
-        self.remaining -= 1;
+        if !self.started {
+            self.started = true;
+            return Some((self.cursor.node(), 0));
+        }
+        if self.max_depth.is_none_or(|max| self.depth < max) && self.cursor.goto_first_child() {
+            self.depth += 1;
+        } else {
+            while !self.cursor.goto_next_sibling() {
+                if !self.cursor.goto_parent() {
+                    self.done = true;
+                    return None;
+                }
+                self.depth -= 1;
+            }
+        }
+        Some((self.cursor.node(), self.depth))
+    }
+}
 
-        let tree_idx: usize = self.pick_usize(self.trees.len());
-        let (text, tree) = *self.trees.get(tree_idx).unwrap();
-        self.splice_tree(text, tree.clone())
+/// `nodes` without those with the same byte range as their parent, if it's
+/// one of `nodes` too, so that each range is picked at most once
+fn outermost(mut nodes: Vec<Node>) -> Vec<Node> {
+    let ids: FxHashSet<usize> = nodes.iter().map(Node::id).collect();
+    nodes.retain(|node| {
+        !node.parent().is_some_and(|parent| {
+            parent.byte_range() == node.byte_range() && ids.contains(&parent.id())
+        })
+    });
+    nodes
+}
+
+/// Whether `range` overlaps any of `ranges`
+fn overlaps_any(range: &Range<usize>, ranges: &[Range<usize>]) -> bool {
+    ranges.iter().any(|r| overlaps(range, r))
+}
+
+/// A `splicer:ignore` comment, see [`Config::honor_pragmas`]
+enum Pragma {
+    /// `splicer:ignore-start`
+    Start,
+    /// `splicer:ignore-end`
+    End,
+    /// `splicer:ignore`, which applies to the next node
+    Next,
+}
+
+fn pragma(comment: &[u8]) -> Option<Pragma> {
+    const PREFIX: &[u8] = b"splicer:ignore";
+    let at = comment
+        .windows(PREFIX.len())
+        .position(|window| window == PREFIX)?;
+    let rest = &comment[at + PREFIX.len()..];
+    if rest.starts_with(b"-start") {
+        Some(Pragma::Start)
+    } else if rest.starts_with(b"-end") {
+        Some(Pragma::End)
+    } else if rest
+        .first()
+        .is_some_and(|b| *b == b'-' || b.is_ascii_alphanumeric())
+    {
+        None
+    } else {
+        Some(Pragma::Next)
     }
 }
 
-#[allow(clippy::needless_lifetimes)]
-pub fn splice<'a>(
-    config: Config,
-    files: &'a HashMap<String, (Vec<u8>, Tree)>,
-) -> impl Iterator<Item = Vec<u8>> + 'a {
-    let trees: Vec<_> = files
-        .iter()
-        .map(|(_, (txt, tree))| (txt.as_ref(), tree))
-        .collect();
-    let branches = Branches::new(
-        files
-            .iter()
-            .map(|(_, (txt, tree))| (txt.as_ref(), tree))
-            .collect(),
-    );
-    let possible = branches.possible();
-    if possible < config.tests {
-        eprintln!("[WARN] Only {possible} possible mutations");
+/// Whether `kind` is in `include` (if set) and not in `exclude`
+fn allowed<S: BuildHasher, T: BuildHasher>(
+    include: &Option<HashSet<&'static str, S>>,
+    exclude: &HashSet<&'static str, T>,
+    kind: &str,
+) -> bool {
+    include
+        .as_ref()
+        .is_none_or(|include| include.contains(kind))
+        && !exclude.contains(kind)
+}
+
+/// Seed for the random number generator for the `index`th output, so that
+/// each output can be made independently of the others
+fn output_seed(seed: u64, index: usize) -> u64 {
+    // splitmix64
+    let mut z = (seed ^ index as u64).wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// Pick an index with probability proportional to its weight
+fn pick_weighted(rng: &mut (impl Rng + ?Sized), weights: &[usize]) -> usize {
+    let total: usize = weights.iter().sum();
+    debug_assert!(total > 0);
+    let mut n = rng.gen_range(0..total);
+    for (i, weight) in weights.iter().enumerate() {
+        if n < *weight {
+            return i;
+        }
+        n -= weight;
     }
-    let rng = rand::rngs::StdRng::seed_from_u64(config.seed);
-    let kinds = branches.0.keys().copied().collect();
-    Splicer {
-        chaos: config.chaos,
-        deletions: config.deletions,
-        language: config.language,
-        branches,
-        kinds,
-        // intra_splices: config.intra_splices,
-        inter_splices: config.inter_splices,
-        node_types: config.node_types,
-        remaining: std::cmp::min(config.tests, possible),
-        rng,
-        trees,
+    unreachable!()
+}
+
+/// Whether `len` is within a factor of `ratio` of `target`
+fn similar_size(len: usize, target: usize, ratio: f32) -> bool {
+    let (len, target) = (len.max(1) as f32, target.max(1) as f32);
+    len <= target * ratio && target <= len * ratio
+}
+
+/// How many times to re-pick nodes before giving up on a mutation
+const MAX_ATTEMPTS: usize = 32;
+
+/// How chaotic mutations pick the kind of node to splice in
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum ChaosWeighting {
+    /// Every kind is equally likely
+    #[default]
+    Uniform,
+    /// Kinds with more distinct candidates are more likely
+    ByFrequency,
+}
+
+impl fmt::Display for ChaosWeighting {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChaosWeighting::Uniform => write!(f, "uniform"),
+            ChaosWeighting::ByFrequency => write!(f, "by-frequency"),
+        }
+    }
+}
+
+/// How splices pick among the candidates for a node, see
+/// [`Config::candidate_weighting`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum CandidateWeighting {
+    /// Every distinct candidate is equally likely
+    #[default]
+    Uniform,
+    /// Candidates that occur more often in the corpus are more likely, so
+    /// common idioms are spliced in more than oddities
+    ByFrequency,
+}
+
+impl fmt::Display for CandidateWeighting {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CandidateWeighting::Uniform => write!(f, "uniform"),
+            CandidateWeighting::ByFrequency => write!(f, "by-frequency"),
+        }
+    }
+}
+
+/// How splices pick among texts to replace a node with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum CandidateSizeBias {
+    /// Every candidate is equally likely
+    #[default]
+    None,
+    /// Prefer candidates at most `ratio` times bigger or smaller than the
+    /// node they replace, if there are any
+    Similar { ratio: f32 },
+}
+
+/// Whether mutations must make files bigger or smaller
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum Direction {
+    #[default]
+    Any,
+    /// Only apply mutations that make the file bigger, e.g., insertions,
+    /// duplications, and splices of bigger nodes
+    Grow,
+    /// Only apply mutations that make the file smaller, e.g., deletions and
+    /// splices of smaller nodes
+    Shrink,
+}
+
+impl Direction {
+    /// Whether a mutation from `old` to `new` bytes goes this way
+    fn allows(self, old: usize, new: usize) -> bool {
+        match self {
+            Direction::Any => true,
+            Direction::Grow => new > old,
+            Direction::Shrink => new < old,
+        }
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Direction::Any => write!(f, "any"),
+            Direction::Grow => write!(f, "grow"),
+            Direction::Shrink => write!(f, "shrink"),
+        }
+    }
+}
+
+/// How to pick the file to mutate for each output
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+#[serde(rename_all = "kebab-case")]
+pub enum FileSelection {
+    /// Every file is equally likely
+    #[default]
+    Uniform,
+    /// Go through the files in order of their names, so each is used before
+    /// any is used again
+    RoundRobin,
+    /// Files with more nodes are more likely
+    WeightedBySize,
+    /// Files are picked in proportion to their energy, which starts at 1 and
+    /// can be changed with [`Splicer::set_energy`] and
+    /// [`Splicer::bump_energy`], e.g., when an output finds new coverage
+    Energy,
+}
+
+impl fmt::Display for FileSelection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FileSelection::Uniform => write!(f, "uniform"),
+            FileSelection::RoundRobin => write!(f, "round-robin"),
+            FileSelection::WeightedBySize => write!(f, "weighted-by-size"),
+            FileSelection::Energy => write!(f, "energy"),
+        }
+    }
+}
+
+/// A way of mutating a file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operator {
+    /// Replace a node in one file with one from another, see
+    /// [`Config::crossover`]
+    Crossover,
+    Delete,
+    Duplicate,
+    Insert,
+    /// Replace a number literal with a nearby or boundary value, see
+    /// [`NumberMutations`]
+    Number,
+    /// Rename all occurrences of an identifier
+    Rename,
+    SiblingSwap,
+    /// Replace a node with another of the same kind from any file
+    Splice,
+    /// Replace the contents of a string literal, see
+    /// [`StringMutations`]
+    String,
+    /// Swap two nodes of the same kind within a file
+    Swap,
+    /// A [`MutationOperator`], by its name
+    Custom(&'static str),
+}
+
+/// An output of [`splice_with_provenance`], with where it came from
+#[derive(Clone, Debug)]
+pub struct Output {
+    pub bytes: Vec<u8>,
+    /// Name of the file that was mutated
+    pub source_file: String,
+    pub seed: u64,
+    /// Pass to [`reproduce`] with the same seed to make this output again
+    pub index: usize,
+    /// Equal to `operators.len()`
+    pub splices_applied: usize,
+    /// Operators that were applied, in order
+    pub operators: Vec<Operator>,
+    /// Kind of the node each of `operators` changed
+    pub kinds: Vec<&'static str>,
+    /// Number of batches of mutations that were skipped because they
+    /// couldn't be rendered
+    pub render_failures: usize,
+    /// Number of batches of mutations whose parse had more `ERROR` or
+    /// `MISSING` nodes than the text they were applied to, including ones
+    /// that were skipped due to `reject_errors`
+    pub degraded_splices: usize,
+    /// Turn the source file into `bytes` with [`apply_edits`]. Only recorded
+    /// by [`splice_with_edits`], empty otherwise.
+    pub edits: Vec<Edit>,
+}
+
+impl Output {
+    /// How this output was made, for [`replay`]. `None` unless the edits
+    /// were recorded, see [`Output::edits`].
+    pub fn trace(&self) -> Option<MutationTrace> {
+        if self.edits.is_empty() && !self.operators.is_empty() {
+            return None;
+        }
+        Some(MutationTrace {
+            source_file: self.source_file.clone(),
+            operators: self.operators.clone(),
+            kinds: self.kinds.clone(),
+            edits: self.edits.clone(),
+        })
+    }
+}
+
+/// The mutations that made an output, see [`Output::trace`].
+///
+/// Unlike a seed and an index, this doesn't depend on the rest of the corpus
+/// or the configuration, just on the contents of `source_file`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MutationTrace {
+    pub source_file: String,
+    /// See [`Output::operators`]
+    pub operators: Vec<Operator>,
+    /// See [`Output::kinds`]
+    pub kinds: Vec<&'static str>,
+    /// See [`Output::edits`]
+    pub edits: Vec<Edit>,
+}
+
+/// Replacement of some bytes of a text, see [`Output::edits`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Edit {
+    /// [`Node::id`] of the replaced node, in the parse of the text the edit
+    /// applies to
+    pub node_id: usize,
+    /// Bytes of the text that are replaced, after the edits before this one
+    /// have been applied
+    pub byte_range: Range<usize>,
+    pub old_bytes: Vec<u8>,
+    pub new_bytes: Vec<u8>,
+}
+
+/// Identifies a node by how to get to it from the root, so that it can be
+/// found again in the parse of an edited text, unlike [`Node::id`], which
+/// only means something within one parse.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct StableId {
+    root_kind: &'static str,
+    /// Index among the children of its parent, kind, and bytes of each node
+    /// on the way down, starting with a child of the root
+    path: Vec<(usize, &'static str, Range<usize>)>,
+    /// Bytes of the node in the text it was found in, moved by the edits
+    /// passed to [`StableId::edit`] since
+    pub byte_range: Range<usize>,
+}
+
+impl StableId {
+    pub fn new(node: Node) -> Self {
+        let mut path = Vec::new();
+        let mut cursor = node.walk();
+        let mut current = node;
+        while let Some(parent) = current.parent() {
+            let idx = parent
+                .children(&mut cursor)
+                .position(|child| child.id() == current.id())
+                .expect("A node is a child of its parent");
+            path.push((idx, current.kind(), current.byte_range()));
+            current = parent;
+        }
+        path.reverse();
+        StableId {
+            root_kind: current.kind(),
+            path,
+            byte_range: node.byte_range(),
+        }
+    }
+
+    /// Move the bytes recorded for the node and those above it like
+    /// [`Tree::edit`] moves the nodes of a tree, so that they are where the
+    /// nodes are in the edited text. Call this with each edit made to the text
+    /// since, before [`StableId::resolve`].
+    pub fn edit(&mut self, edit: &InputEdit) {
+        let moved = |byte: usize| {
+            if byte <= edit.start_byte {
+                byte
+            } else if byte >= edit.old_end_byte {
+                byte - edit.old_end_byte + edit.new_end_byte
+            } else {
+                // In the replaced bytes, so it goes to the closest point in
+                // the new ones
+                byte.min(edit.new_end_byte)
+            }
+        };
+        for (_, _, range) in &mut self.path {
+            *range = moved(range.start)..moved(range.end);
+        }
+        self.byte_range = moved(self.byte_range.start)..moved(self.byte_range.end);
+    }
+
+    /// The node in `tree` at the same place as the one this was made from,
+    /// if it has the same kind as that one and so does each node on the way
+    /// down to it.
+    ///
+    /// Each step down goes to the child of the right kind that overlaps the
+    /// most with the bytes of the node it was in the old text, or if none
+    /// does, to the child at the same index. So edits that add or remove
+    /// siblings before a node don't send it astray, as long as the edits that
+    /// change the length of the text have been passed to [`StableId::edit`].
+    pub fn resolve<'t>(&self, tree: &'t Tree) -> Option<Node<'t>> {
+        let mut node = tree.root_node();
+        if node.kind() != self.root_kind {
+            return None;
+        }
+        let mut cursor = tree.walk();
+        for (idx, kind, range) in &self.path {
+            let overlapping = node
+                .children(&mut cursor)
+                .filter(|child| child.kind() == *kind)
+                .map(|child| (overlap(&child.byte_range(), range), child))
+                .filter(|(overlap, _)| *overlap > 0)
+                .max_by_key(|(overlap, child)| (*overlap, std::cmp::Reverse(child.start_byte())))
+                .map(|(_, child)| child);
+            node = match overlapping {
+                Some(child) => child,
+                None => node.child(*idx).filter(|child| child.kind() == *kind)?,
+            };
+        }
+        Some(node)
+    }
+
+    /// Number of nodes above this one, see [`Config::max_depth`]
+    pub fn depth(&self) -> usize {
+        self.path.len()
+    }
+}
+
+/// Statistics about the outputs of a run, see [`RunStats::record`]
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct RunStats {
+    pub outputs: usize,
+    /// Number of distinct outputs
+    pub unique_outputs: usize,
+    /// Total size of the outputs
+    pub bytes: usize,
+    /// Total number of mutations of any operator
+    pub splices_applied: usize,
+    pub deletions: usize,
+    /// See [`Output::render_failures`]
+    pub render_failures: usize,
+    /// See [`Output::degraded_splices`]
+    pub degraded_splices: usize,
+    /// Number of mutations of nodes of each kind
+    pub kinds: BTreeMap<&'static str, usize>,
+    #[serde(skip)]
+    seen: HashSet<u64>,
+}
+
+impl RunStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&mut self, output: &Output) {
+        self.outputs += 1;
+        if self.seen.insert(hash(&output.bytes)) {
+            self.unique_outputs += 1;
+        }
+        self.bytes += output.bytes.len();
+        self.splices_applied += output.splices_applied;
+        self.deletions += output
+            .operators
+            .iter()
+            .filter(|op| **op == Operator::Delete)
+            .count();
+        self.render_failures += output.render_failures;
+        self.degraded_splices += output.degraded_splices;
+        for kind in &output.kinds {
+            *self.kinds.entry(kind).or_default() += 1;
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("RunStats is always valid JSON")
+    }
+}
+
+/// Called with the number of outputs so far after each output. Iteration
+/// stops if it returns [`ControlFlow::Break`].
+pub struct OnOutput(pub Box<dyn FnMut(usize) -> ControlFlow<()> + Send>);
+
+impl fmt::Debug for OnOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "OnOutput(..)")
+    }
+}
+
+/// Called with a candidate text and the kind of the node it would replace.
+/// Candidates for which it returns `false` aren't spliced in.
+#[derive(Clone)]
+pub struct CandidateFilter(pub Arc<CandidateFn>);
+
+type CandidateFn = dyn Fn(&[u8], &str) -> bool + Send + Sync;
+
+impl fmt::Debug for CandidateFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CandidateFilter(..)")
+    }
+}
+
+/// A way of mutating a file, see [`Config::operators`]. The built-in ones
+/// are [`Splices`], [`Deletions`], and the like.
+pub trait MutationOperator: Send + Sync {
+    /// Pick one of `ctx.nodes()` and return it along with the text to replace
+    /// it with, or `None` if there's no such node.
+    fn apply<'t>(
+        &self,
+        rng: &mut dyn RngCore,
+        text: &[u8],
+        tree: &'t Tree,
+        ctx: &MutationContext<'_, 't>,
+    ) -> Option<(Node<'t>, Vec<u8>)>;
+
+    /// How often this operator is picked, relative to the others. The weights
+    /// of the built-in operators are percentages, which add up to 100.
+    fn weight(&self) -> u32;
+
+    /// Used in [`Operator::Custom`] and in errors
+    fn name(&self) -> &'static str {
+        "custom"
+    }
+
+    /// Recorded in [`Output::operators`]
+    fn operator(&self) -> Operator {
+        Operator::Custom(self.name())
+    }
+
+    /// Like [`MutationOperator::apply`], but may replace several nodes, which
+    /// mustn't overlap
+    fn apply_all<'t>(
+        &self,
+        rng: &mut dyn RngCore,
+        text: &[u8],
+        tree: &'t Tree,
+        ctx: &MutationContext<'_, 't>,
+    ) -> Option<Vec<(Node<'t>, Vec<u8>)>> {
+        self.apply(rng, text, tree, ctx).map(|edit| vec![edit])
+    }
+}
+
+impl fmt::Debug for dyn MutationOperator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}({})", self.name(), self.weight())
+    }
+}
+
+/// What a [`MutationOperator`] may use
+pub struct MutationContext<'c, 't> {
+    nodes: &'c Nodes<'t>,
+    /// `nodes`, collected the first time they're needed
+    collected: OnceCell<Cow<'c, [Node<'t>]>>,
+    splicer: &'c dyn Builtins,
+    branches: &'c Branches<'c>,
+    field_aware: bool,
+}
+
+impl<'c, 't> MutationContext<'c, 't> {
+    /// Nodes that may be mutated, e.g., given `include_kinds` and `max_depth`
+    pub fn nodes(&self) -> &[Node<'t>] {
+        self.collected
+            .get_or_init(|| self.splicer.collected(self.nodes))
+    }
+
+    /// Texts of nodes in the input files that may replace `node`, in sorted
+    /// order
+    pub fn candidates(&self, node: Node) -> &[&[u8]] {
+        let field = if self.field_aware {
+            field_name(node)
+        } else {
+            None
+        };
+        self.branches
+            .0
+            .get(&(field, node.kind()))
+            .map_or(&[], Vec::as_slice)
+    }
+}
+
+/// `edits` as one edit of the smallest node containing all of them
+fn combined<'t>(
+    text: &[u8],
+    tree: &'t Tree,
+    mut edits: Vec<(Node<'t>, Vec<u8>)>,
+) -> Option<(Node<'t>, Vec<u8>)> {
+    edits.sort_by_key(|(node, _)| node.start_byte());
+    let start = edits.first()?.0.start_byte();
+    let end = edits.iter().map(|(node, _)| node.end_byte()).max()?;
+    let node = tree.root_node().descendant_for_byte_range(start, end)?;
+    let mut new = Vec::new();
+    let mut at = node.start_byte();
+    for (edited, bytes) in edits {
+        new.extend(&text[at..edited.start_byte()]);
+        new.extend(bytes);
+        at = edited.end_byte();
+    }
+    new.extend(&text[at..node.end_byte()]);
+    Some((node, new))
+}
+
+/// Replaces a node with another of the same kind from any file, or of any
+/// kind if chaotic, see [`Config::splice_chaos`]. Picked whenever no other
+/// operator is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Splices(pub u32);
+
+impl MutationOperator for Splices {
+    fn apply<'t>(
+        &self,
+        rng: &mut dyn RngCore,
+        text: &[u8],
+        tree: &'t Tree,
+        ctx: &MutationContext<'_, 't>,
+    ) -> Option<(Node<'t>, Vec<u8>)> {
+        ctx.splicer.splice_node(rng, text, tree, ctx.nodes)
+    }
+
+    fn weight(&self) -> u32 {
+        self.0
+    }
+
+    fn name(&self) -> &'static str {
+        "splices"
+    }
+
+    fn operator(&self) -> Operator {
+        Operator::Splice
+    }
+}
+
+/// Deletes an optional node, or any node if chaotic, see
+/// [`Config::delete_chaos`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Deletions(pub u32);
+
+impl MutationOperator for Deletions {
+    fn apply<'t>(
+        &self,
+        rng: &mut dyn RngCore,
+        _text: &[u8],
+        tree: &'t Tree,
+        ctx: &MutationContext<'_, 't>,
+    ) -> Option<(Node<'t>, Vec<u8>)> {
+        Some(ctx.splicer.delete_node(rng, tree, ctx.nodes))
+    }
+
+    fn weight(&self) -> u32 {
+        self.0
+    }
+
+    fn name(&self) -> &'static str {
+        "deletions"
+    }
+
+    fn operator(&self) -> Operator {
+        Operator::Delete
+    }
+}
+
+/// Copies a node in a list next to itself
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Duplications(pub u32);
+
+impl MutationOperator for Duplications {
+    fn apply<'t>(
+        &self,
+        rng: &mut dyn RngCore,
+        text: &[u8],
+        _tree: &'t Tree,
+        ctx: &MutationContext<'_, 't>,
+    ) -> Option<(Node<'t>, Vec<u8>)> {
+        ctx.splicer.duplicate_node(rng, text, ctx.nodes)
+    }
+
+    fn weight(&self) -> u32 {
+        self.0
+    }
+
+    fn name(&self) -> &'static str {
+        "duplications"
+    }
+
+    fn operator(&self) -> Operator {
+        Operator::Duplicate
+    }
+}
+
+/// Inserts a node next to one of the same kind in a list
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Insertions(pub u32);
+
+impl MutationOperator for Insertions {
+    fn apply<'t>(
+        &self,
+        rng: &mut dyn RngCore,
+        text: &[u8],
+        _tree: &'t Tree,
+        ctx: &MutationContext<'_, 't>,
+    ) -> Option<(Node<'t>, Vec<u8>)> {
+        ctx.splicer.insert_node(rng, text, ctx.nodes)
+    }
+
+    fn weight(&self) -> u32 {
+        self.0
+    }
+
+    fn name(&self) -> &'static str {
+        "insertions"
+    }
+
+    fn operator(&self) -> Operator {
+        Operator::Insert
+    }
+}
+
+/// Swaps two adjacent siblings of the same kind
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SiblingSwaps(pub u32);
+
+impl MutationOperator for SiblingSwaps {
+    fn apply<'t>(
+        &self,
+        rng: &mut dyn RngCore,
+        text: &[u8],
+        tree: &'t Tree,
+        ctx: &MutationContext<'_, 't>,
+    ) -> Option<(Node<'t>, Vec<u8>)> {
+        combined(text, tree, self.apply_all(rng, text, tree, ctx)?)
+    }
+
+    fn apply_all<'t>(
+        &self,
+        rng: &mut dyn RngCore,
+        text: &[u8],
+        tree: &'t Tree,
+        ctx: &MutationContext<'_, 't>,
+    ) -> Option<Vec<(Node<'t>, Vec<u8>)>> {
+        ctx.splicer.swap_siblings(rng, text, tree, ctx.nodes)
+    }
+
+    fn weight(&self) -> u32 {
+        self.0
+    }
+
+    fn name(&self) -> &'static str {
+        "sibling swaps"
+    }
+
+    fn operator(&self) -> Operator {
+        Operator::SiblingSwap
+    }
+}
+
+/// Consistently renames all occurrences of an identifier
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Renames(pub u32);
+
+impl MutationOperator for Renames {
+    fn apply<'t>(
+        &self,
+        rng: &mut dyn RngCore,
+        text: &[u8],
+        tree: &'t Tree,
+        ctx: &MutationContext<'_, 't>,
+    ) -> Option<(Node<'t>, Vec<u8>)> {
+        combined(text, tree, self.apply_all(rng, text, tree, ctx)?)
+    }
+
+    fn apply_all<'t>(
+        &self,
+        rng: &mut dyn RngCore,
+        text: &[u8],
+        tree: &'t Tree,
+        ctx: &MutationContext<'_, 't>,
+    ) -> Option<Vec<(Node<'t>, Vec<u8>)>> {
+        ctx.splicer.rename(rng, text, tree, ctx.nodes)
+    }
+
+    fn weight(&self) -> u32 {
+        self.0
+    }
+
+    fn name(&self) -> &'static str {
+        "renames"
+    }
+
+    fn operator(&self) -> Operator {
+        Operator::Rename
+    }
+}
+
+/// Replaces a number literal with a nearby or boundary value
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NumberMutations(pub u32);
+
+impl MutationOperator for NumberMutations {
+    fn apply<'t>(
+        &self,
+        rng: &mut dyn RngCore,
+        text: &[u8],
+        _tree: &'t Tree,
+        ctx: &MutationContext<'_, 't>,
+    ) -> Option<(Node<'t>, Vec<u8>)> {
+        ctx.splicer.mutate_number(rng, text, ctx.nodes)
+    }
+
+    fn weight(&self) -> u32 {
+        self.0
+    }
+
+    fn name(&self) -> &'static str {
+        "number mutations"
+    }
+
+    fn operator(&self) -> Operator {
+        Operator::Number
+    }
+}
+
+/// Replaces the contents of a string literal with something empty, long, or
+/// full of escapes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StringMutations(pub u32);
+
+impl MutationOperator for StringMutations {
+    fn apply<'t>(
+        &self,
+        rng: &mut dyn RngCore,
+        text: &[u8],
+        _tree: &'t Tree,
+        ctx: &MutationContext<'_, 't>,
+    ) -> Option<(Node<'t>, Vec<u8>)> {
+        ctx.splicer.mutate_string(rng, text, ctx.nodes)
+    }
+
+    fn weight(&self) -> u32 {
+        self.0
+    }
+
+    fn name(&self) -> &'static str {
+        "string mutations"
+    }
+
+    fn operator(&self) -> Operator {
+        Operator::String
+    }
+}
+
+/// The built-in operators whose weights are percentages, i.e., all but
+/// splices, which get the rest
+const PERCENTAGES: [Operator; 7] = [
+    Operator::Delete,
+    Operator::Duplicate,
+    Operator::Insert,
+    Operator::SiblingSwap,
+    Operator::Rename,
+    Operator::Number,
+    Operator::String,
+];
+
+#[derive(Debug)]
+pub struct Config {
+    /// Make all `tests` outputs even if there are fewer possible distinct
+    /// splices, so some outputs may repeat (unless `unique` is set)
+    pub allow_repeats: bool,
+    /// Only mutate nodes inside a node of this kind, e.g., a function body.
+    /// If there are several, one is picked each time the tree is re-parsed.
+    pub anchor_kind: Option<&'static str>,
+    /// Splices only use candidates it accepts, see [`CandidateFilter`]
+    pub candidate_filter: Option<CandidateFilter>,
+    /// See [`CandidateSizeBias`]
+    pub candidate_size_bias: CandidateSizeBias,
+    /// How splices pick among the candidates for a node
+    pub candidate_weighting: CandidateWeighting,
+    /// How chaotic mutations pick the kind of node to splice in
+    pub chaos_weighting: ChaosWeighting,
+    /// Kinds of comment nodes, see `preserve_comments`
+    pub comment_kinds: HashSet<&'static str>,
+    /// Start each test by splicing a node from one file into another, before
+    /// the usual mutations
+    pub crossover: bool,
+    /// Percent (0 to 100) of deletions that are "chaotic", i.e., delete any
+    /// node rather than an optional one, so may introduce syntax errors
+    pub delete_chaos: u8,
+    /// Extra texts to splice in, with the kinds of nodes they may replace,
+    /// e.g., from [`parse_dictionary`]
+    pub dictionary: Vec<(&'static str, &'static [u8])>,
+    /// See [`Direction`]
+    pub direction: Direction,
+    /// Don't make outputs that are the same as one of the input files
+    pub drop_unchanged: bool,
+    /// Kinds of nodes that are never replaced, deleted, or spliced in. Applied
+    /// after `include_kinds`.
+    pub exclude_kinds: HashSet<&'static str>,
+    /// Only splice in nodes that occupied the same field in their parent as
+    /// the node they replace, and only swap nodes in the same field
+    pub field_aware: bool,
+    /// See [`FileSelection`]
+    pub file_selection: FileSelection,
+    /// Never mutate code between `splicer:ignore-start` and
+    /// `splicer:ignore-end` comments (or from an unmatched start to the end of
+    /// the file), nor the node after a `splicer:ignore` comment
+    pub honor_pragmas: bool,
+    /// If set, only nodes of these kinds are replaced, deleted, or spliced in,
+    /// minus any in `exclude_kinds`
+    pub include_kinds: Option<HashSet<&'static str>>,
+    pub language: Language,
+    /// Skip splices that would make the output longer than this many bytes,
+    /// and skip outputs longer than this
+    pub max_bytes: Option<usize>,
+    /// Keep at most this many distinct texts of each kind to splice in,
+    /// sampled with the seed (or the random number generator passed to
+    /// [`splice_with_rng`]), which bounds memory use on large corpora
+    pub max_candidates_per_kind: Option<usize>,
+    /// Only mutate nodes at most this deep, where the root is at depth 0
+    pub max_depth: Option<usize>,
+    /// Stop splicing an output before its tree gets deeper than this, where
+    /// the root is at depth 0
+    pub max_tree_depth: Option<usize>,
+    /// Only mutate nodes at least this deep, where the root is at depth 0
+    pub min_depth: usize,
+    /// Apply at least this many mutations that change the text to each
+    /// output, if possible. Mutations that make a copy of an input file, e.g.,
+    /// by splicing in its root, don't count.
+    pub min_splices: usize,
+    /// Maximum number of non-overlapping splices to apply before re-parsing
+    pub batch_size: usize,
+    /// Maximum number of swaps of same-kind nodes within a single file. Each
+    /// output gets between 0 and this many, inclusive.
+    pub intra_splices: usize,
+    /// Maximum number of other mutations of each output. Each output gets
+    /// between 0 and this many, inclusive.
+    pub inter_splices: usize,
+    pub node_types: NodeTypes,
+    /// Progress callback, e.g., for progress bars or cancellation. Doesn't
+    /// affect the outputs, except for how many there are. With [`par_splice`],
+    /// it's called from several threads, and a few more outputs may be made
+    /// after it breaks.
+    pub on_output: Option<OnOutput>,
+    /// Mutation operators, each picked with probability proportional to its
+    /// weight. [`ConfigBuilder`] keeps the weights of the built-in ones
+    /// percentages that add up to 100, see [`Config::percent`].
+    pub operators: Vec<Arc<dyn MutationOperator>>,
+    /// Never delete, replace, or splice in comments (nodes of the kinds in
+    /// `comment_kinds`), nor nodes containing them
+    pub preserve_comments: bool,
+    /// Never mutate the first this many bytes of each file (e.g., a license
+    /// header), nor nodes overlapping them
+    pub preserve_prefix_bytes: usize,
+    /// A tree-sitter query. If set, only nodes it captures are mutated.
+    pub query: Option<String>,
+    /// Discard splices that introduce parse errors
+    pub reject_errors: bool,
+    /// Pick nodes by walking the tree each time one is needed, rather than
+    /// collecting them all after each re-parse. Uses less memory on huge
+    /// files, but is slower, and makes different outputs for the same seed.
+    pub reservoir_sampling: bool,
+    pub seed: u64,
+    /// Percent (0 to 100) of splices that are "chaotic", i.e., splice in a
+    /// node of any kind, so may introduce syntax errors
+    pub splice_chaos: u8,
+    /// Let nodes be replaced by nodes of any kind that shares their
+    /// supertype (e.g., `_expression`) in the grammar, not just their own
+    pub supertype_splicing: bool,
+    /// Like `unique`, but also skip outputs that only differ from earlier
+    /// ones in whitespace between nodes
+    pub structural_unique: bool,
+    pub tests: usize,
+    /// Stop mutating a file after this long, and output what was done so far.
+    /// Outputs may then differ between runs with the same seed.
+    pub timeout: Option<Duration>,
+    /// Stop before an output that would make the total size of the outputs
+    /// more than this many bytes, so fewer than `tests` outputs may be
+    /// produced
+    pub total_bytes_budget: Option<usize>,
+    /// Skip outputs identical to earlier ones, so fewer than `tests` outputs
+    /// may be produced
+    pub unique: bool,
+}
+
+/// The parts of a [`Config`] that can be saved to a file, see
+/// [`Config::settings`] and [`ConfigBuilder::with_settings`]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+    pub allow_repeats: bool,
+    pub anchor_kind: Option<String>,
+    pub batch_size: usize,
+    pub candidate_size_bias: CandidateSizeBias,
+    pub candidate_weighting: CandidateWeighting,
+    pub chaos_weighting: ChaosWeighting,
+    pub comment_kinds: BTreeSet<String>,
+    pub crossover: bool,
+    pub delete_chaos: u8,
+    pub deletions: u8,
+    pub direction: Direction,
+    pub drop_unchanged: bool,
+    pub duplications: u8,
+    pub exclude_kinds: BTreeSet<String>,
+    pub field_aware: bool,
+    pub file_selection: FileSelection,
+    pub honor_pragmas: bool,
+    pub include_kinds: Option<BTreeSet<String>>,
+    pub insertions: u8,
+    pub inter_splices: usize,
+    pub intra_splices: usize,
+    pub max_bytes: Option<usize>,
+    pub max_candidates_per_kind: Option<usize>,
+    pub max_depth: Option<usize>,
+    pub max_tree_depth: Option<usize>,
+    pub min_depth: usize,
+    pub min_splices: usize,
+    pub number_mutations: u8,
+    pub preserve_comments: bool,
+    pub preserve_prefix_bytes: usize,
+    pub query: Option<String>,
+    pub reject_errors: bool,
+    pub renames: u8,
+    pub reservoir_sampling: bool,
+    pub seed: u64,
+    pub sibling_swaps: u8,
+    pub splice_chaos: u8,
+    pub string_mutations: u8,
+    pub structural_unique: bool,
+    pub supertype_splicing: bool,
+    pub tests: usize,
+    /// See [`Config::timeout`]
+    pub timeout_ms: Option<u64>,
+    pub total_bytes_budget: Option<usize>,
+    pub unique: bool,
+}
+
+impl Config {
+    /// Everything but the language, node types, `candidate_filter`, custom
+    /// operators, `dictionary`, and `on_output`
+    pub fn settings(&self) -> Settings {
+        let kinds = |kinds: &HashSet<&str>| kinds.iter().map(|k| k.to_string()).collect();
+        Settings {
+            allow_repeats: self.allow_repeats,
+            anchor_kind: self.anchor_kind.map(String::from),
+            batch_size: self.batch_size,
+            candidate_size_bias: self.candidate_size_bias,
+            candidate_weighting: self.candidate_weighting,
+            chaos_weighting: self.chaos_weighting,
+            comment_kinds: kinds(&self.comment_kinds),
+            crossover: self.crossover,
+            delete_chaos: self.delete_chaos,
+            deletions: self.percent(Operator::Delete),
+            direction: self.direction,
+            drop_unchanged: self.drop_unchanged,
+            duplications: self.percent(Operator::Duplicate),
+            exclude_kinds: kinds(&self.exclude_kinds),
+            field_aware: self.field_aware,
+            file_selection: self.file_selection,
+            honor_pragmas: self.honor_pragmas,
+            include_kinds: self.include_kinds.as_ref().map(kinds),
+            insertions: self.percent(Operator::Insert),
+            inter_splices: self.inter_splices,
+            intra_splices: self.intra_splices,
+            max_bytes: self.max_bytes,
+            max_candidates_per_kind: self.max_candidates_per_kind,
+            max_depth: self.max_depth,
+            max_tree_depth: self.max_tree_depth,
+            min_depth: self.min_depth,
+            min_splices: self.min_splices,
+            number_mutations: self.percent(Operator::Number),
+            preserve_comments: self.preserve_comments,
+            preserve_prefix_bytes: self.preserve_prefix_bytes,
+            query: self.query.clone(),
+            reject_errors: self.reject_errors,
+            renames: self.percent(Operator::Rename),
+            reservoir_sampling: self.reservoir_sampling,
+            seed: self.seed,
+            sibling_swaps: self.percent(Operator::SiblingSwap),
+            splice_chaos: self.splice_chaos,
+            string_mutations: self.percent(Operator::String),
+            structural_unique: self.structural_unique,
+            supertype_splicing: self.supertype_splicing,
+            tests: self.tests,
+            timeout_ms: self.timeout.map(|t| t.as_millis() as u64),
+            total_bytes_budget: self.total_bytes_budget,
+            unique: self.unique,
+        }
+    }
+
+    /// The total weight of the operators in `operators` recorded as
+    /// `operator`, at most 100
+    pub fn percent(&self, operator: Operator) -> u8 {
+        weight_of(&self.operators, operator).min(100) as u8
+    }
+}
+
+fn weight_of(operators: &[Arc<dyn MutationOperator>], operator: Operator) -> u32 {
+    operators
+        .iter()
+        .filter(|op| op.operator() == operator)
+        .map(|op| op.weight())
+        .sum()
+}
+
+/// Builds a [`Config`], starting from defaults for everything but the
+/// language.
+///
+/// ```
+/// use tree_splicer::node_types::NodeTypes;
+/// use tree_splicer::splice::ConfigBuilder;
+///
+/// let node_types = NodeTypes::new(tree_sitter_rust::NODE_TYPES).unwrap();
+/// let config = ConfigBuilder::new(tree_sitter_rust::language(), node_types)
+///     .with_seed(0)
+///     .with_tests(8)
+///     .build()
+///     .unwrap();
+/// assert_eq!((config.seed, config.tests), (0, 8));
+/// ```
+#[derive(Debug)]
+pub struct ConfigBuilder {
+    config: Config,
+    /// Reported by [`ConfigBuilder::build`]
+    invalid: Option<String>,
+}
+
+/// Convert a probability to a percentage
+fn percent(name: &str, probability: f32) -> Result<u8, String> {
+    if (0.0..=1.0).contains(&probability) {
+        Ok((probability * 100.0).round() as u8)
+    } else {
+        Err(format!(
+            "{name} probability must be between 0 and 1, got {probability}"
+        ))
+    }
+}
+
+impl ConfigBuilder {
+    /// Defaults to no chaos or deletions, up to 16 splices per test, 4 tests,
+    /// and a random seed.
+    pub fn new(language: Language, node_types: NodeTypes) -> Self {
+        ConfigBuilder {
+            config: Config {
+                allow_repeats: false,
+                anchor_kind: None,
+                candidate_filter: None,
+                candidate_size_bias: CandidateSizeBias::None,
+                candidate_weighting: CandidateWeighting::Uniform,
+                chaos_weighting: ChaosWeighting::Uniform,
+                comment_kinds: HashSet::from(["block_comment", "comment", "line_comment"]),
+                crossover: false,
+                delete_chaos: 0,
+                dictionary: Vec::new(),
+                direction: Direction::Any,
+                drop_unchanged: false,
+                exclude_kinds: HashSet::new(),
+                field_aware: false,
+                file_selection: FileSelection::Uniform,
+                honor_pragmas: false,
+                include_kinds: None,
+                language,
+                max_bytes: None,
+                max_candidates_per_kind: None,
+                max_depth: None,
+                max_tree_depth: None,
+                min_depth: 0,
+                min_splices: 0,
+                batch_size: 1,
+                intra_splices: 0,
+                inter_splices: 16,
+                node_types,
+                on_output: None,
+                operators: vec![
+                    Arc::new(Deletions(0)),
+                    Arc::new(Duplications(0)),
+                    Arc::new(Insertions(0)),
+                    Arc::new(SiblingSwaps(0)),
+                    Arc::new(Renames(0)),
+                    Arc::new(NumberMutations(0)),
+                    Arc::new(StringMutations(0)),
+                    Arc::new(Splices(100)),
+                ],
+                preserve_comments: false,
+                preserve_prefix_bytes: 0,
+                query: None,
+                reject_errors: false,
+                reservoir_sampling: false,
+                seed: rand::random(),
+                splice_chaos: 0,
+                supertype_splicing: false,
+                structural_unique: false,
+                tests: 4,
+                timeout: None,
+                total_bytes_budget: None,
+                unique: false,
+            },
+            invalid: None,
+        }
+    }
+
+    /// The settings so far, see [`Config::settings`]
+    pub fn settings(&self) -> Settings {
+        self.config.settings()
+    }
+
+    /// Replace everything but the language, node types, `candidate_filter`,
+    /// custom operators, `dictionary`, and `on_output`.
+    ///
+    /// Node kinds must be `'static`, so the ones in `settings` are leaked.
+    pub fn with_settings(mut self, settings: Settings) -> Self {
+        let leak = |kinds: BTreeSet<String>| -> HashSet<&'static str> {
+            kinds
+                .into_iter()
+                .map(|k| &*Box::leak(k.into_boxed_str()))
+                .collect()
+        };
+        let config = &mut self.config;
+        config.allow_repeats = settings.allow_repeats;
+        config.anchor_kind = settings
+            .anchor_kind
+            .map(|k| &*Box::leak(k.into_boxed_str()));
+        config.batch_size = settings.batch_size;
+        config.candidate_size_bias = settings.candidate_size_bias;
+        config.candidate_weighting = settings.candidate_weighting;
+        config.chaos_weighting = settings.chaos_weighting;
+        config.comment_kinds = leak(settings.comment_kinds);
+        config.crossover = settings.crossover;
+        config.delete_chaos = settings.delete_chaos;
+        config.direction = settings.direction;
+        config.drop_unchanged = settings.drop_unchanged;
+        config.exclude_kinds = leak(settings.exclude_kinds);
+        config.field_aware = settings.field_aware;
+        config.file_selection = settings.file_selection;
+        config.honor_pragmas = settings.honor_pragmas;
+        config.include_kinds = settings.include_kinds.map(leak);
+        config.inter_splices = settings.inter_splices;
+        config.intra_splices = settings.intra_splices;
+        config.max_bytes = settings.max_bytes;
+        config.max_candidates_per_kind = settings.max_candidates_per_kind;
+        config.max_depth = settings.max_depth;
+        config.max_tree_depth = settings.max_tree_depth;
+        config.min_depth = settings.min_depth;
+        config.min_splices = settings.min_splices;
+        config.preserve_comments = settings.preserve_comments;
+        config.preserve_prefix_bytes = settings.preserve_prefix_bytes;
+        config.query = settings.query;
+        config.reject_errors = settings.reject_errors;
+        config.reservoir_sampling = settings.reservoir_sampling;
+        config.seed = settings.seed;
+        config.splice_chaos = settings.splice_chaos;
+        config.structural_unique = settings.structural_unique;
+        config.supertype_splicing = settings.supertype_splicing;
+        config.tests = settings.tests;
+        config.timeout = settings.timeout_ms.map(Duration::from_millis);
+        config.total_bytes_budget = settings.total_bytes_budget;
+        config.unique = settings.unique;
+        self.with_deletions(settings.deletions)
+            .with_duplications(settings.duplications)
+            .with_insertions(settings.insertions)
+            .with_number_mutations(settings.number_mutations)
+            .with_renames(settings.renames)
+            .with_sibling_swaps(settings.sibling_swaps)
+            .with_string_mutations(settings.string_mutations)
+    }
+
+    pub fn with_allow_repeats(mut self, allow_repeats: bool) -> Self {
+        self.config.allow_repeats = allow_repeats;
+        self
+    }
+
+    pub fn with_anchor_kind(mut self, anchor_kind: &'static str) -> Self {
+        self.config.anchor_kind = Some(anchor_kind);
+        self
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.config.batch_size = batch_size;
+        self
+    }
+
+    pub fn with_candidate_filter(
+        mut self,
+        candidate_filter: impl Fn(&[u8], &str) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.config.candidate_filter = Some(CandidateFilter(Arc::new(candidate_filter)));
+        self
+    }
+
+    pub fn with_candidate_size_bias(mut self, candidate_size_bias: CandidateSizeBias) -> Self {
+        self.config.candidate_size_bias = candidate_size_bias;
+        self
+    }
+
+    pub fn with_candidate_weighting(mut self, candidate_weighting: CandidateWeighting) -> Self {
+        self.config.candidate_weighting = candidate_weighting;
+        self
+    }
+
+    /// Sets both [`Config::splice_chaos`] and [`Config::delete_chaos`]
+    #[deprecated(note = "use `with_splice_chaos` and `with_delete_chaos`")]
+    pub fn with_chaos(mut self, chaos: u8) -> Self {
+        self.config.splice_chaos = chaos;
+        self.config.delete_chaos = chaos;
+        self
+    }
+
+    pub fn with_chaos_weighting(mut self, chaos_weighting: ChaosWeighting) -> Self {
+        self.config.chaos_weighting = chaos_weighting;
+        self
+    }
+
+    /// Sets both [`Config::splice_chaos`] and [`Config::delete_chaos`], from
+    /// a probability
+    #[deprecated(note = "use `with_splice_chaos` and `with_delete_chaos`")]
+    pub fn with_chaos_probability(mut self, probability: f32) -> Self {
+        match percent("chaos", probability) {
+            Ok(chaos) => {
+                self.config.splice_chaos = chaos;
+                self.config.delete_chaos = chaos;
+            }
+            Err(e) => self.invalid = Some(e),
+        }
+        self
+    }
+
+    pub fn with_comment_kinds(mut self, kinds: impl IntoIterator<Item = &'static str>) -> Self {
+        self.config.comment_kinds = kinds.into_iter().collect();
+        self
+    }
+
+    pub fn with_crossover(mut self, crossover: bool) -> Self {
+        self.config.crossover = crossover;
+        self
+    }
+
+    /// Add to [`Config::operators`]
+    pub fn with_custom_operator(mut self, operator: impl MutationOperator + 'static) -> Self {
+        self.config.operators.push(Arc::new(operator));
+        self
+    }
+
+    /// Replace the operator in [`Config::operators`] recorded like
+    /// `operator`, or add it, and give [`Splices`] the rest of 100 percent
+    fn with_builtin(mut self, operator: impl MutationOperator + 'static) -> Self {
+        let operators = &mut self.config.operators;
+        let recorded = operator.operator();
+        match operators.iter().position(|op| op.operator() == recorded) {
+            Some(i) => operators[i] = Arc::new(operator),
+            None => operators.push(Arc::new(operator)),
+        }
+        let percentages: u32 = PERCENTAGES.iter().map(|op| weight_of(operators, *op)).sum();
+        if let Some(i) = operators
+            .iter()
+            .position(|op| op.operator() == Operator::Splice)
+        {
+            operators[i] = Arc::new(Splices(100u32.saturating_sub(percentages)));
+        }
+        self
+    }
+
+    pub fn with_delete_chaos(mut self, delete_chaos: u8) -> Self {
+        self.config.delete_chaos = delete_chaos;
+        self
+    }
+
+    /// Set the percentage of [`Deletions`]
+    pub fn with_deletions(self, deletions: u8) -> Self {
+        self.with_builtin(Deletions(deletions.into()))
+    }
+
+    /// Like [`ConfigBuilder::with_deletions`], but takes a probability
+    pub fn with_deletion_probability(mut self, probability: f32) -> Self {
+        match percent("deletion", probability) {
+            Ok(deletions) => self.with_deletions(deletions),
+            Err(e) => {
+                self.invalid = Some(e);
+                self
+            }
+        }
+    }
+
+    /// Add `tokens` to [`Config::dictionary`], for nodes of kind `kind`. The
+    /// tokens are leaked.
+    pub fn with_dictionary(mut self, kind: &'static str, tokens: Vec<Vec<u8>>) -> Self {
+        self.config.dictionary.extend(
+            tokens
+                .into_iter()
+                .map(|token| (kind, &*Box::leak(token.into_boxed_slice()))),
+        );
+        self
+    }
+
+    pub fn with_direction(mut self, direction: Direction) -> Self {
+        self.config.direction = direction;
+        self
+    }
+
+    pub fn with_drop_unchanged(mut self, drop_unchanged: bool) -> Self {
+        self.config.drop_unchanged = drop_unchanged;
+        self
+    }
+
+    /// Set the percentage of [`Duplications`]
+    pub fn with_duplications(self, duplications: u8) -> Self {
+        self.with_builtin(Duplications(duplications.into()))
+    }
+
+    pub fn with_exclude_kinds(mut self, kinds: impl IntoIterator<Item = &'static str>) -> Self {
+        self.config.exclude_kinds = kinds.into_iter().collect();
+        self
+    }
+
+    pub fn with_field_aware(mut self, field_aware: bool) -> Self {
+        self.config.field_aware = field_aware;
+        self
+    }
+
+    pub fn with_file_selection(mut self, file_selection: FileSelection) -> Self {
+        self.config.file_selection = file_selection;
+        self
+    }
+
+    pub fn with_honor_pragmas(mut self, honor_pragmas: bool) -> Self {
+        self.config.honor_pragmas = honor_pragmas;
+        self
+    }
+
+    pub fn with_include_kinds(mut self, kinds: impl IntoIterator<Item = &'static str>) -> Self {
+        self.config.include_kinds = Some(kinds.into_iter().collect());
+        self
+    }
+
+    /// Set the percentage of [`Insertions`]
+    pub fn with_insertions(self, insertions: u8) -> Self {
+        self.with_builtin(Insertions(insertions.into()))
+    }
+
+    pub fn with_intra_splices(mut self, intra_splices: usize) -> Self {
+        self.config.intra_splices = intra_splices;
+        self
+    }
+
+    pub fn with_inter_splices(mut self, inter_splices: usize) -> Self {
+        self.config.inter_splices = inter_splices;
+        self
+    }
+
+    pub fn with_max_bytes(mut self, max_bytes: usize) -> Self {
+        self.config.max_bytes = Some(max_bytes);
+        self
+    }
+
+    pub fn with_max_candidates_per_kind(mut self, max_candidates_per_kind: usize) -> Self {
+        self.config.max_candidates_per_kind = Some(max_candidates_per_kind);
+        self
+    }
+
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.config.max_depth = Some(max_depth);
+        self
+    }
+
+    pub fn with_max_tree_depth(mut self, max_tree_depth: usize) -> Self {
+        self.config.max_tree_depth = Some(max_tree_depth);
+        self
+    }
+
+    pub fn with_min_depth(mut self, min_depth: usize) -> Self {
+        self.config.min_depth = min_depth;
+        self
+    }
+
+    pub fn with_min_splices(mut self, min_splices: usize) -> Self {
+        self.config.min_splices = min_splices;
+        self
+    }
+
+    pub fn with_on_output(
+        mut self,
+        on_output: impl FnMut(usize) -> ControlFlow<()> + Send + 'static,
+    ) -> Self {
+        self.config.on_output = Some(OnOutput(Box::new(on_output)));
+        self
+    }
+
+    /// Replace [`Config::operators`], e.g., to leave out splices
+    pub fn with_operators(mut self, operators: Vec<Arc<dyn MutationOperator>>) -> Self {
+        self.config.operators = operators;
+        self
+    }
+
+    /// Set the percentage of [`NumberMutations`]
+    pub fn with_number_mutations(self, number_mutations: u8) -> Self {
+        self.with_builtin(NumberMutations(number_mutations.into()))
+    }
+
+    pub fn with_preserve_comments(mut self, preserve_comments: bool) -> Self {
+        self.config.preserve_comments = preserve_comments;
+        self
+    }
+
+    pub fn with_preserve_prefix_bytes(mut self, preserve_prefix_bytes: usize) -> Self {
+        self.config.preserve_prefix_bytes = preserve_prefix_bytes;
+        self
+    }
+
+    pub fn with_query(mut self, query: impl Into<String>) -> Self {
+        self.config.query = Some(query.into());
+        self
+    }
+
+    pub fn with_reject_errors(mut self, reject_errors: bool) -> Self {
+        self.config.reject_errors = reject_errors;
+        self
+    }
+
+    /// Set the percentage of [`Renames`]
+    pub fn with_renames(self, renames: u8) -> Self {
+        self.with_builtin(Renames(renames.into()))
+    }
+
+    pub fn with_reservoir_sampling(mut self, reservoir_sampling: bool) -> Self {
+        self.config.reservoir_sampling = reservoir_sampling;
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.config.seed = seed;
+        self
+    }
+
+    pub fn with_splice_chaos(mut self, splice_chaos: u8) -> Self {
+        self.config.splice_chaos = splice_chaos;
+        self
+    }
+
+    /// Set the percentage of [`SiblingSwaps`]
+    pub fn with_sibling_swaps(self, sibling_swaps: u8) -> Self {
+        self.with_builtin(SiblingSwaps(sibling_swaps.into()))
+    }
+
+    /// Set the percentage of [`StringMutations`]
+    pub fn with_string_mutations(self, string_mutations: u8) -> Self {
+        self.with_builtin(StringMutations(string_mutations.into()))
+    }
+
+    pub fn with_supertype_splicing(mut self, supertype_splicing: bool) -> Self {
+        self.config.supertype_splicing = supertype_splicing;
+        self
+    }
+
+    pub fn with_structural_unique(mut self, structural_unique: bool) -> Self {
+        self.config.structural_unique = structural_unique;
+        self
+    }
+
+    pub fn with_tests(mut self, tests: usize) -> Self {
+        self.config.tests = tests;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.config.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_total_bytes_budget(mut self, total_bytes_budget: usize) -> Self {
+        self.config.total_bytes_budget = Some(total_bytes_budget);
+        self
+    }
+
+    pub fn with_unique(mut self, unique: bool) -> Self {
+        self.config.unique = unique;
+        self
+    }
+
+    pub fn build(self) -> Result<Config, SpliceError> {
+        if let Some(msg) = self.invalid {
+            return Err(SpliceError::InvalidConfig(msg));
+        }
+        let chaos = [
+            ("delete chaos", self.config.delete_chaos),
+            ("splice chaos", self.config.splice_chaos),
+        ];
+        for (name, chaos) in chaos {
+            if chaos > 100 {
+                return Err(SpliceError::InvalidConfig(format!(
+                    "{name} must be a percentage, got {chaos}"
+                )));
+            }
+        }
+        let percentages: Vec<_> = self
+            .config
+            .operators
+            .iter()
+            .filter(|op| PERCENTAGES.contains(&op.operator()))
+            .collect();
+        if let Some(op) = percentages.iter().find(|op| op.weight() > 100) {
+            return Err(SpliceError::InvalidConfig(format!(
+                "{} must be a percentage, got {}",
+                op.name(),
+                op.weight()
+            )));
+        }
+        if percentages.iter().map(|op| op.weight()).sum::<u32>() > 100 {
+            let got: Vec<_> = percentages
+                .iter()
+                .map(|op| format!("{}% {}", op.weight(), op.name()))
+                .collect();
+            return Err(SpliceError::InvalidConfig(format!(
+                "mutation percentages must add up to at most 100, got {}",
+                got.join(", ")
+            )));
+        }
+        if let CandidateSizeBias::Similar { ratio } = self.config.candidate_size_bias {
+            if ratio.is_nan() || ratio < 1.0 {
+                return Err(SpliceError::InvalidConfig(format!(
+                    "candidate size ratio must be at least 1, got {ratio}"
+                )));
+            }
+        }
+        if let Some(query) = &self.config.query {
+            compile_query(self.config.language, query)?;
+        }
+        if self.config.max_candidates_per_kind == Some(0) {
+            return Err(SpliceError::InvalidConfig(
+                "max candidates per kind must be at least 1".to_string(),
+            ));
+        }
+        if let Some(max_depth) = self.config.max_depth {
+            if max_depth < self.config.min_depth {
+                return Err(SpliceError::InvalidConfig(format!(
+                    "max depth {max_depth} is less than min depth {}",
+                    self.config.min_depth
+                )));
+            }
+        }
+        if self.config.batch_size == 0 {
+            return Err(SpliceError::InvalidConfig(
+                "batch size must be at least 1".to_string(),
+            ));
+        }
+        Ok(self.config)
+    }
+}
+
+/// Makes outputs one at a time, for when [`splice`] doesn't give enough
+/// control, e.g., to look at each output before making the next.
+///
+/// Iterating over a `Splicer` yields the bytes of each output.
+///
+/// ```
+/// use std::collections::HashMap;
+///
+/// use tree_splicer::node_types::NodeTypes;
+/// use tree_splicer::splice::{splice, ConfigBuilder, Splicer};
+///
+/// let mut parser = tree_sitter::Parser::new();
+/// parser.set_language(tree_sitter_rust::language()).unwrap();
+/// let text = "fn f() { let x = 1; g(x, 2); }";
+/// let tree = parser.parse(text, None).unwrap();
+/// let files = HashMap::from([("f.rs".to_string(), (text.as_bytes().to_vec(), tree))]);
+/// let config = || {
+///     let node_types = NodeTypes::new(tree_sitter_rust::NODE_TYPES).unwrap();
+///     ConfigBuilder::new(tree_sitter_rust::language(), node_types)
+///         .with_seed(1)
+///         .with_tests(4)
+///         .build()
+///         .unwrap()
+/// };
+/// let mut splicer = Splicer::new(config(), &files).unwrap();
+/// let mut outputs = Vec::new();
+/// while splicer.remaining() > 0 {
+///     // E.g., run the output before asking for the next one
+///     let Some(output) = splicer.next_output() else {
+///         break;
+///     };
+///     assert_eq!(output.source_file, "f.rs");
+///     outputs.push(output.bytes);
+/// }
+/// let spliced: Vec<_> = splice(config(), &files).unwrap().collect();
+/// assert_eq!(outputs, spliced);
+/// ```
+#[derive(Clone)]
+pub struct Splicer<'a, R = StdRng> {
+    /// Files from [`Splicer::add_input`], which `names`, `trees`, and
+    /// `branches` borrow from. Clones share them, so they're around for as
+    /// long as the borrows are.
+    added: Vec<AddedFile>,
+    anchor_kind: Option<&'static str>,
+    language: Language,
+    batch_size: usize,
+    /// Shared between threads in [`par_splice`]
+    branches: Arc<Branches<'a>>,
+    candidate_filter: Option<CandidateFilter>,
+    candidate_size_bias: CandidateSizeBias,
+    candidate_weighting: CandidateWeighting,
+    chaos_weighting: ChaosWeighting,
+    comment_kinds: FxHashSet<&'static str>,
+    crossover: bool,
+    delete_chaos: u8,
+    direction: Direction,
+    drop_unchanged: bool,
+    exclude_kinds: FxHashSet<&'static str>,
+    field_aware: bool,
+    file_selection: FileSelection,
+    /// Number of nodes in each of `trees` if they're picked by size, or their
+    /// energy if they're picked by that
+    file_weights: Vec<usize>,
+    honor_pragmas: bool,
+    include_kinds: Option<FxHashSet<&'static str>>,
+    kinds: Vec<Key>,
+    /// Number of candidates of each of `kinds`
+    kind_weights: Vec<usize>,
+    intra_splices: usize,
+    inter_splices: usize,
+    max_bytes: Option<usize>,
+    max_candidates_per_kind: Option<usize>,
+    max_depth: Option<usize>,
+    max_tree_depth: Option<usize>,
+    min_depth: usize,
+    min_splices: usize,
+    /// Names of the files in `trees`
+    names: Vec<&'a str>,
+    node_types: NodeTypes,
+    operators: Vec<Arc<dyn MutationOperator>>,
+    parser: ReusedParser,
+    preserve_comments: bool,
+    preserve_prefix_bytes: usize,
+    query: Option<Arc<Query>>,
+    /// Whether to fill in [`Output::edits`]
+    record_edits: bool,
+    /// Panic if re-parsing after a mutation goes wrong, see
+    /// [`Splicer::check_reparse`]. Only in tests.
+    check_invariants: bool,
+    reject_errors: bool,
+    reservoir_sampling: bool,
+    trees: Vec<(&'a [u8], &'a Tree)>,
+    /// Index of the next output, from which its random seed is derived
+    index: usize,
+    /// Index of the next file with [`FileSelection::RoundRobin`], which is
+    /// `index` except in [`PersistentSplicer::mutate_one`]
+    turn: usize,
+    remaining: usize,
+    rng: R,
+    /// Replaces `rng` before each output, so each can be made on its own. Not
+    /// possible for all random number generators.
+    reseed: Option<fn(u64) -> R>,
+    seed: u64,
+    splice_chaos: u8,
+    /// Kinds of candidates for nodes of each kind, if `supertype_splicing`
+    supertype_pools: FxHashMap<&'static str, Vec<&'static str>>,
+    supertype_splicing: bool,
+    timeout: Option<Duration>,
+    /// What's left of `total_bytes_budget`
+    bytes_left: Option<usize>,
+    /// Hashes of outputs so far, if `unique` or `structural_unique`
+    seen: FxHashSet<u64>,
+    structural_unique: bool,
+    unique: bool,
+}
+
+impl<'a> Splicer<'a> {
+    /// Make the same outputs as [`splice`]. `config.on_output` is ignored.
+    ///
+    /// Fails if the language is incompatible with the linked tree-sitter.
+    pub fn new(
+        config: Config,
+        files: &'a HashMap<String, (Vec<u8>, Tree)>,
+    ) -> Result<Self, SpliceError> {
+        splicer(config, files)
+    }
+
+    /// A copy of this splicer that produces the `index`th of `jobs` shares of
+    /// the outputs.
+    #[cfg(feature = "parallel")]
+    fn worker(&self, index: usize, jobs: usize) -> Self {
+        let mut worker = self.clone();
+        let share = self.remaining / jobs;
+        let extra = self.remaining % jobs;
+        worker.index = self.index + index * share + std::cmp::min(index, extra);
+        worker.turn = self.turn + worker.index - self.index;
+        worker.remaining = share + usize::from(index < extra);
+        worker.bytes_left = self
+            .bytes_left
+            .map(|left| left / jobs + usize::from(index < left % jobs));
+        worker.seen = FxHashSet::default();
+        worker
+    }
+}
+
+impl<'a, R: Rng> Splicer<'a, R> {
+    fn pick_usize(&mut self, n: usize) -> usize {
+        debug_assert!(n > 0);
+        self.rng.gen_range(0..n)
+    }
+
+    fn pick_idx<T>(&mut self, v: &[T]) -> usize {
+        self.pick_usize(v.len())
+    }
+
+    fn key(&self, node: Node) -> Key {
+        let field = if self.field_aware {
+            field_name(node)
+        } else {
+            None
+        };
+        (field, node.kind())
+    }
+
+    /// Whether nodes of this kind may be mutated or spliced in
+    fn allowed(&self, kind: &str) -> bool {
+        allowed(&self.include_kinds, &self.exclude_kinds, kind)
+    }
+
+    /// Whether a node of this kind at this depth (the root is at 0) may be
+    /// mutated
+    fn eligible(&self, kind: &str, depth: usize) -> bool {
+        self.min_depth <= depth
+            && self.max_depth.is_none_or(|max| depth <= max)
+            && self.allowed(kind)
+    }
+
+    /// Whether `node` is [`Splicer::eligible`], isn't `ERROR`, `MISSING`, or
+    /// empty, and doesn't overlap any of `kept`
+    fn eligible_node(&self, node: &Node, depth: usize, kept: &[Range<usize>]) -> bool {
+        self.eligible(node.kind(), depth)
+            && !node.is_error()
+            && !node.is_missing()
+            && !node.byte_range().is_empty()
+            && !overlaps_any(&node.byte_range(), kept)
+    }
+
+    /// Whether `node` is one of [`Nodes::Walk`], see [`outermost`]
+    fn walked(&self, node: &Node, depth: usize, kept: &[Range<usize>], within: &Within) -> bool {
+        let included =
+            |node: &Node, depth| self.eligible_node(node, depth, kept) && within.contains(node);
+        included(node, depth)
+            && !node.parent().is_some_and(|parent| {
+                parent.byte_range() == node.byte_range() && included(&parent, depth - 1)
+            })
+    }
+
+    /// Byte ranges to leave alone: the preserved prefix, comments to preserve,
+    /// and spans protected by pragmas
+    fn kept(&self, text: &[u8], tree: &Tree) -> Vec<Range<usize>> {
+        let mut kept = Vec::new();
+        if self.preserve_prefix_bytes > 0 {
+            kept.push(0..self.preserve_prefix_bytes);
+        }
+        if self.preserve_comments {
+            kept.extend(self.comments(tree).into_iter().map(|c| c.byte_range()));
+        }
+        if self.honor_pragmas {
+            kept.extend(self.pragma_spans(text, tree));
+        }
+        kept
+    }
+
+    /// See [`Config::honor_pragmas`]
+    fn pragma_spans(&self, text: &[u8], tree: &Tree) -> Vec<Range<usize>> {
+        let mut spans = Vec::new();
+        let mut start = None;
+        for comment in self.comments(tree) {
+            let range = comment.byte_range();
+            match pragma(&text[range.clone()]) {
+                Some(Pragma::Start) => {
+                    start.get_or_insert(range.start);
+                }
+                Some(Pragma::End) => {
+                    if let Some(start) = start.take() {
+                        spans.push(start..range.end);
+                    }
+                }
+                Some(Pragma::Next) => {
+                    let mut next = comment.next_named_sibling();
+                    while let Some(n) = next.filter(|n| self.comment_kinds.contains(n.kind())) {
+                        next = n.next_named_sibling();
+                    }
+                    // With the pragma, so that mutating it doesn't expose the node
+                    spans.extend(next.map(|n| range.start..n.end_byte()));
+                }
+                None => (),
+            }
+        }
+        if let Some(start) = start {
+            spans.push(start..text.len());
+        }
+        spans
+    }
+
+    /// All nodes that are [`Splicer::eligible_node`], including the root
+    fn all_nodes<'b>(&self, text: &[u8], tree: &'b Tree) -> Vec<Node<'b>> {
+        self.eligible_nodes(tree, &self.kept(text, tree))
+    }
+
+    /// Like [`Splicer::all_nodes`], given what to keep
+    fn eligible_nodes<'b>(&self, tree: &'b Tree, kept: &[Range<usize>]) -> Vec<Node<'b>> {
+        let mut all = Vec::with_capacity(16); // min
+        let mut nodes = vec![tree.root_node()];
+        // Reused for every level and node, rather than allocated for each
+        let mut next = Vec::new();
+        let mut child_cursor = tree.walk();
+        let mut depth = 0;
+        while !nodes.is_empty() && self.max_depth.is_none_or(|max| depth <= max) {
+            for node in nodes.drain(..) {
+                if self.eligible_node(&node, depth, kept) {
+                    all.push(node);
+                }
+                for child in node.children(&mut child_cursor) {
+                    debug_assert!(child.id() != node.id());
+                    next.push(child);
+                }
+            }
+            std::mem::swap(&mut nodes, &mut next);
+            depth += 1;
+        }
+        all
+    }
+
+    /// Nodes to mutate in `tree`, collected up front unless
+    /// `reservoir_sampling` is set. Only nodes [`Splicer::within`] limits.
+    fn nodes<'t>(&mut self, text: &[u8], tree: &'t Tree) -> Nodes<'t> {
+        let within = match self.within(text, tree) {
+            Some(within) => within,
+            None => return Nodes::Collected(Vec::new()),
+        };
+        if self.reservoir_sampling {
+            Nodes::Walk {
+                tree,
+                kept: self.kept(text, tree),
+                within,
+            }
+        } else {
+            let mut nodes = self.all_nodes(text, tree);
+            nodes.retain(|node| within.contains(node));
+            Nodes::Collected(outermost(nodes))
+        }
+    }
+
+    /// Nodes captured by `query`, and a random node of kind `anchor_kind`, if
+    /// they're set. `None` if there's no such node.
+    fn within<'t>(&mut self, text: &[u8], tree: &'t Tree) -> Option<Within<'t>> {
+        let captured = self.query.as_ref().map(|query| {
+            let mut cursor = QueryCursor::new();
+            cursor
+                .captures(query, tree.root_node(), text)
+                .flat_map(|(m, _)| m.captures.iter().map(|c| c.node.id()))
+                .collect()
+        });
+        let anchor = match self.anchor_kind {
+            Some(kind) => {
+                let anchors: Vec<_> = Descendants::new(tree, None)
+                    .map(|(node, _)| node)
+                    .filter(|node| node.kind() == kind)
+                    .collect();
+                if anchors.is_empty() {
+                    return None;
+                }
+                Some(anchors[self.pick_idx(&anchors)])
+            }
+            None => None,
+        };
+        Some(Within { captured, anchor })
+    }
+
+    fn no_nodes(&self, nodes: &Nodes) -> bool {
+        match nodes {
+            Nodes::Collected(nodes) => nodes.is_empty(),
+            Nodes::Walk { tree, kept, within } => !Descendants::new(tree, self.max_depth)
+                .any(|(node, depth)| self.walked(&node, depth, kept, within)),
+        }
+    }
+
+    /// Pick one of `nodes` for which `pred` holds, uniformly at random
+    fn pick_where<'t>(
+        &self,
+        rng: &mut dyn RngCore,
+        nodes: &Nodes<'t>,
+        pred: impl Fn(&Self, &Node<'t>) -> bool,
+    ) -> Option<Node<'t>> {
+        match nodes {
+            // Count the matches, then find the picked one, rather than
+            // collecting them
+            Nodes::Collected(nodes) => {
+                let matching = nodes.iter().filter(|n| pred(self, n)).count();
+                if matching == 0 {
+                    return None;
+                }
+                let idx = rng.gen_range(0..matching);
+                nodes.iter().filter(|n| pred(self, n)).nth(idx).copied()
+            }
+            // Reservoir sampling, see "Algorithm R"
+            Nodes::Walk { tree, kept, within } => {
+                let mut picked = None;
+                let mut seen = 0;
+                for (node, depth) in Descendants::new(tree, self.max_depth) {
+                    if !self.walked(&node, depth, kept, within) || !pred(self, &node) {
+                        continue;
+                    }
+                    seen += 1;
+                    if rng.gen_range(0..seen) == 0 {
+                        picked = Some(node);
+                    }
+                }
+                picked
+            }
+        }
+    }
+
+    /// All comments in the tree, in order
+    fn comments<'t>(&self, tree: &'t Tree) -> Vec<Node<'t>> {
+        let mut comments = Vec::new();
+        let mut cursor = tree.walk();
+        'walk: loop {
+            let node = cursor.node();
+            if self.comment_kinds.contains(node.kind()) {
+                comments.push(node);
+            } else if cursor.goto_first_child() {
+                continue;
+            }
+            while !cursor.goto_next_sibling() {
+                if !cursor.goto_parent() {
+                    break 'walk;
+                }
+            }
+        }
+        comments
+    }
+
+    fn pick_node<'t>(&self, rng: &mut dyn RngCore, tree: &'t Tree, nodes: &Nodes<'t>) -> Node<'t> {
+        let node = match nodes {
+            Nodes::Collected(nodes) if nodes.is_empty() => None,
+            Nodes::Collected(nodes) => Some(nodes[rng.gen_range(0..nodes.len())]),
+            Nodes::Walk { .. } => self.pick_where(rng, nodes, |_, _| true),
+        };
+        node.unwrap_or_else(|| tree.root_node())
+    }
+
+    /// Keys of the texts that could replace `node`, see
+    /// [`Splicer::candidates`]
+    fn candidate_keys(&self, node: Node) -> Vec<Key> {
+        let (field, kind) = self.key(node);
+        match self.supertype_pools.get(kind) {
+            Some(pool) if pool.len() > 1 => pool.iter().map(|k| (field, *k)).collect(),
+            _ => vec![(field, kind)],
+        }
+    }
+
+    /// Texts that could replace `node`, each once
+    fn candidates<'b>(&self, branches: &'b Branches<'a>, node: Node) -> Cow<'b, [&'a [u8]]> {
+        let (field, kind) = self.key(node);
+        match self.supertype_pools.get(kind) {
+            Some(pool) if pool.len() > 1 => {
+                // Nodes of different kinds may have the same text
+                let mut candidates: Vec<_> = pool
+                    .iter()
+                    .filter_map(|k| branches.0.get(&(field, *k)))
+                    .flatten()
+                    .copied()
+                    .collect();
+                candidates.sort_unstable();
+                candidates.dedup();
+                Cow::Owned(candidates)
+            }
+            _ => branches
+                .0
+                .get(&(field, kind))
+                .map_or(Cow::Borrowed(&[]), |c| Cow::Borrowed(c.as_slice())),
+        }
+    }
+}
+
+/// The built-in operators, which need more of the [`Splicer`] than a
+/// [`MutationContext`] offers
+trait Builtins {
+    fn collected<'n, 't>(&self, nodes: &'n Nodes<'t>) -> Cow<'n, [Node<'t>]>;
+
+    fn delete_node<'t>(
+        &self,
+        rng: &mut dyn RngCore,
+        tree: &'t Tree,
+        nodes: &Nodes<'t>,
+    ) -> (Node<'t>, Vec<u8>);
+
+    fn splice_node<'t>(
+        &self,
+        rng: &mut dyn RngCore,
+        text: &[u8],
+        tree: &'t Tree,
+        nodes: &Nodes<'t>,
+    ) -> Option<(Node<'t>, Vec<u8>)>;
+
+    fn mutate_number<'t>(
+        &self,
+        rng: &mut dyn RngCore,
+        text: &[u8],
+        nodes: &Nodes<'t>,
+    ) -> Option<(Node<'t>, Vec<u8>)>;
+
+    fn mutate_string<'t>(
+        &self,
+        rng: &mut dyn RngCore,
+        text: &[u8],
+        nodes: &Nodes<'t>,
+    ) -> Option<(Node<'t>, Vec<u8>)>;
+
+    fn duplicate_node<'t>(
+        &self,
+        rng: &mut dyn RngCore,
+        text: &[u8],
+        nodes: &Nodes<'t>,
+    ) -> Option<(Node<'t>, Vec<u8>)>;
+
+    fn insert_node<'t>(
+        &self,
+        rng: &mut dyn RngCore,
+        text: &[u8],
+        nodes: &Nodes<'t>,
+    ) -> Option<(Node<'t>, Vec<u8>)>;
+
+    fn rename<'t>(
+        &self,
+        rng: &mut dyn RngCore,
+        text: &[u8],
+        tree: &'t Tree,
+        nodes: &Nodes<'t>,
+    ) -> Option<Vec<(Node<'t>, Vec<u8>)>>;
+
+    fn swap_siblings<'t>(
+        &self,
+        rng: &mut dyn RngCore,
+        text: &[u8],
+        tree: &'t Tree,
+        nodes: &Nodes<'t>,
+    ) -> Option<Vec<(Node<'t>, Vec<u8>)>>;
+}
+
+impl<R: Rng> Builtins for Splicer<'_, R> {
+    /// All of `nodes`, walking the tree if they weren't collected
+    fn collected<'n, 't>(&self, nodes: &'n Nodes<'t>) -> Cow<'n, [Node<'t>]> {
+        match nodes {
+            Nodes::Collected(nodes) => Cow::Borrowed(nodes),
+            Nodes::Walk { tree, kept, within } => {
+                let mut nodes = self.eligible_nodes(tree, kept);
+                nodes.retain(|node| within.contains(node));
+                Cow::Owned(outermost(nodes))
+            }
+        }
+    }
+
+    fn delete_node<'t>(
+        &self,
+        rng: &mut dyn RngCore,
+        tree: &'t Tree,
+        nodes: &Nodes<'t>,
+    ) -> (Node<'t>, Vec<u8>) {
+        let chaotic = rng.gen_range(0..100) < self.delete_chaos;
+        if chaotic {
+            return (self.pick_node(rng, tree, nodes), Vec::new());
+        }
+        // Deleting the root would leave nothing
+        let optional = |s: &Self, n: &Node| n.parent().is_some() && s.node_types.optional_node(n);
+        match self.pick_where(rng, nodes, optional) {
+            Some(optional) => (optional, Vec::new()),
+            None => (self.pick_node(rng, tree, nodes), Vec::new()),
+        }
+    }
+
+    fn splice_node<'t>(
+        &self,
+        rng: &mut dyn RngCore,
+        text: &[u8],
+        tree: &'t Tree,
+        nodes: &Nodes<'t>,
+    ) -> Option<(Node<'t>, Vec<u8>)> {
+        let chaotic = !self.kinds.is_empty() && rng.gen_range(0..100) < self.splice_chaos;
+
+        let branches = &self.branches;
+        let mut node = tree.root_node();
+        let mut candidates: Cow<[&[u8]]> = Cow::Borrowed(&[]);
+        // The kind chaotic splices picked candidates from
+        let mut chaos_key = None;
+        // When modified trees are re-parsed, their nodes may have novel kinds
+        // not in Branches (candidates.len() == 0). Also, avoid not mutating
+        // (candidates.len() == 1). The tree may have no node of a kind with
+        // several candidates, so give up eventually.
+        let mut attempts = 0;
+        while candidates.len() <= 1 {
+            if attempts == MAX_ATTEMPTS {
+                return None;
+            }
+            attempts += 1;
+            node = self.pick_node(rng, tree, nodes);
+            candidates = if chaotic {
+                let kind_idx = match self.chaos_weighting {
+                    ChaosWeighting::Uniform => rng.gen_range(0..self.kinds.len()),
+                    ChaosWeighting::ByFrequency => pick_weighted(rng, &self.kind_weights),
+                };
+                let kind = self.kinds.get(kind_idx).unwrap();
+                chaos_key = Some(*kind);
+                Cow::Borrowed(branches.0.get(kind).unwrap().as_slice())
+            } else {
+                self.candidates(branches, node)
+            };
+        }
+
+        let node_text = &text[node.byte_range()];
+        if self.direction != Direction::Any {
+            let direction = self.direction;
+            candidates
+                .to_mut()
+                .retain(|c| direction.allows(node_text.len(), c.len()));
+            if candidates.is_empty() {
+                return None;
+            }
+        }
+        if let CandidateSizeBias::Similar { ratio } = self.candidate_size_bias {
+            let similar: Vec<_> = candidates
+                .iter()
+                .copied()
+                .filter(|c| *c != node_text && similar_size(c.len(), node_text.len(), ratio))
+                .collect();
+            if !similar.is_empty() {
+                candidates = Cow::Owned(similar);
+            }
+        }
+
+        let weights: Option<Vec<usize>> = match self.candidate_weighting {
+            CandidateWeighting::Uniform => None,
+            CandidateWeighting::ByFrequency => {
+                let keys = match chaos_key {
+                    Some(key) => vec![key],
+                    None => self.candidate_keys(node),
+                };
+                let weight = |c: &[u8]| keys.iter().map(|k| branches.count(k, c)).sum::<usize>();
+                // Dictionary tokens and the like may not occur at all
+                Some(candidates.iter().map(|c| weight(c).max(1)).collect())
+            }
+        };
+        let pick = |rng: &mut dyn RngCore| match &weights {
+            None => rng.gen_range(0..candidates.len()),
+            Some(weights) => pick_weighted(rng, weights),
+        };
+
+        let mut attempts = 0;
+        let candidate = loop {
+            let mut candidate = candidates[pick(rng)];
+            // Try to avoid not mutating
+            let mut retries = 0;
+            while candidates.len() > 1 && candidate == node_text {
+                // Every candidate may have the same text
+                retries += 1;
+                if retries == MAX_ATTEMPTS {
+                    return None;
+                }
+                candidate = candidates[pick(rng)];
+            }
+            if self
+                .candidate_filter
+                .as_ref()
+                .is_none_or(|filter| (filter.0)(candidate, node.kind()))
+            {
+                break candidate;
+            }
+            // The filter may reject every candidate
+            attempts += 1;
+            if attempts == MAX_ATTEMPTS {
+                return None;
+            }
+        };
+        // eprintln!(
+        //     "Replacing '{}' with '{}'",
+        //     std::str::from_utf8(&text[node.byte_range()]).unwrap(),
+        //     std::str::from_utf8(candidate).unwrap(),
+        // );
+        Some((node, Vec::from(candidate)))
+    }
+
+    /// Replace a number literal with a nearby or boundary value
+    fn mutate_number<'t>(
+        &self,
+        rng: &mut dyn RngCore,
+        text: &[u8],
+        nodes: &Nodes<'t>,
+    ) -> Option<(Node<'t>, Vec<u8>)> {
+        let node = self.pick_where(rng, nodes, |_, n| is_number_literal(n))?;
+        let old = String::from_utf8_lossy(&text[node.byte_range()]);
+        let mut values: Vec<String> = if old.contains(['.', 'e', 'E']) && !old.starts_with("0x") {
+            BOUNDARY_FLOATS.iter().map(|v| v.to_string()).collect()
+        } else {
+            BOUNDARY_INTEGERS.iter().map(|v| v.to_string()).collect()
+        };
+        if let Ok(n) = old.parse::<u128>() {
+            values.push(n.saturating_add(1).to_string());
+            values.push(n.saturating_sub(1).to_string());
+        }
+        values.retain(|v| *v != old);
+        if values.is_empty() {
+            return None;
+        }
+        let value = values.swap_remove(rng.gen_range(0..values.len()));
+        Some((node, value.into_bytes()))
+    }
+
+    /// Replace the contents of a string literal with something empty, long,
+    /// or full of escapes
+    fn mutate_string<'t>(
+        &self,
+        rng: &mut dyn RngCore,
+        text: &[u8],
+        nodes: &Nodes<'t>,
+    ) -> Option<(Node<'t>, Vec<u8>)> {
+        let node = self.pick_where(rng, nodes, |_, n| is_string_literal(text, n))?;
+        let old = &text[node.byte_range()];
+        let quote = *old.last()?;
+        // Keep prefixes like `b` or `r#`
+        let start = old.iter().position(|b| *b == quote)?;
+        let end = old.iter().rposition(|b| *b == quote)?;
+        if start == end {
+            return None;
+        }
+        let long = "A".repeat(4096);
+        let escaped_quote = format!("\\{}", char::from(quote));
+        let contents: Vec<&str> = STRING_CONTENTS
+            .iter()
+            .copied()
+            .chain([long.as_str(), escaped_quote.as_str()])
+            .collect();
+        let content = contents[rng.gen_range(0..contents.len())];
+        let mut new = Vec::from(&old[..=start]);
+        new.extend(content.as_bytes());
+        new.extend(&old[end..]);
+        Some((node, new))
+    }
+
+    /// Pick a node in a list (e.g., a statement or an argument) and insert a
+    /// copy of it after itself.
+    fn duplicate_node<'t>(
+        &self,
+        rng: &mut dyn RngCore,
+        text: &[u8],
+        nodes: &Nodes<'t>,
+    ) -> Option<(Node<'t>, Vec<u8>)> {
+        let node = self.pick_where(rng, nodes, |s, n| {
+            s.node_types.repeatable_node(n) && !n.byte_range().is_empty()
+        })?;
+        Some((node, insert_after(text, node, &text[node.byte_range()])))
+    }
+
+    /// Pick a node in a list (e.g., a statement or an argument) and insert a
+    /// candidate of a kind that `node-types.json` allows in that list after
+    /// it, or of the same kind if it doesn't say.
+    ///
+    /// Returns the node along with the text to replace it with, i.e., its own
+    /// text followed by the new one.
+    fn insert_node<'t>(
+        &self,
+        rng: &mut dyn RngCore,
+        text: &[u8],
+        nodes: &Nodes<'t>,
+    ) -> Option<(Node<'t>, Vec<u8>)> {
+        for _ in 0..MAX_ATTEMPTS {
+            let node = self.pick_where(rng, nodes, |s, n| s.node_types.repeatable_node(n))?;
+            let branches = &self.branches;
+            let (field, kind) = self.key(node);
+            let siblings = self.node_types.legal_siblings(&node, field_name(node));
+            let candidates: Vec<&[u8]> = if siblings.is_empty() {
+                branches.0.get(&(field, kind)).cloned().unwrap_or_default()
+            } else {
+                siblings
+                    .iter()
+                    .filter(|kind| self.allowed(kind))
+                    .filter_map(|kind| branches.0.get(&(field, kind.as_str())))
+                    .flatten()
+                    .copied()
+                    .collect()
+            };
+            if candidates.is_empty() {
+                continue;
+            }
+            let candidate = candidates[rng.gen_range(0..candidates.len())];
+            return Some((node, insert_after(text, node, candidate)));
+        }
+        None
+    }
+
+    /// Pick an identifier and replace every occurrence of it (i.e., every
+    /// identifier of the same kind with the same text) with another identifier
+    /// of that kind.
+    fn rename<'t>(
+        &self,
+        rng: &mut dyn RngCore,
+        text: &[u8],
+        tree: &'t Tree,
+        nodes: &Nodes<'t>,
+    ) -> Option<Vec<(Node<'t>, Vec<u8>)>> {
+        let node = self.pick_where(rng, nodes, |_, n| is_identifier(n))?;
+        let name = &text[node.byte_range()];
+        let branches = &self.branches;
+        let candidates: Vec<_> = branches
+            .0
+            .get(&self.key(node))?
+            .iter()
+            .filter(|c| **c != name)
+            .collect();
+        if candidates.is_empty() {
+            return None;
+        }
+        let new_name = candidates[rng.gen_range(0..candidates.len())];
+        // Occurrences that must be kept would have to be renamed too
+        let kept = self.kept(text, tree);
+        let mut occurrences = Vec::new();
+        let mut cursor = tree.walk();
+        'walk: loop {
+            let n = cursor.node();
+            if n.kind() == node.kind() && is_identifier(&n) && &text[n.byte_range()] == name {
+                if overlaps_any(&n.byte_range(), &kept) {
+                    return None;
+                }
+                occurrences.push((n, new_name.to_vec()));
+            } else if cursor.goto_first_child() {
+                continue;
+            }
+            while !cursor.goto_next_sibling() {
+                if !cursor.goto_parent() {
+                    break 'walk;
+                }
+            }
+        }
+        Some(occurrences)
+    }
+
+    /// Pick two adjacent siblings of the same kind, ignoring anonymous nodes
+    /// between them (e.g., commas), and swap their text.
+    fn swap_siblings<'t>(
+        &self,
+        rng: &mut dyn RngCore,
+        text: &[u8],
+        tree: &'t Tree,
+        nodes: &Nodes<'t>,
+    ) -> Option<Vec<(Node<'t>, Vec<u8>)>> {
+        let kept = self.kept(text, tree);
+        let a = self.pick_where(rng, nodes, |s, n| {
+            s.swappable_sibling(text, *n)
+                .is_some_and(|b| !overlaps_any(&b.byte_range(), &kept))
+        })?;
+        let b = self.swappable_sibling(text, a)?;
+        Some(vec![
+            (a, text[b.byte_range()].to_vec()),
+            (b, text[a.byte_range()].to_vec()),
+        ])
+    }
+}
+
+impl<'a, R: Rng> Splicer<'a, R> {
+    /// The next named sibling of `node`, if it's of the same kind and has
+    /// different text
+    fn swappable_sibling<'n>(&self, text: &[u8], node: Node<'n>) -> Option<Node<'n>> {
+        let mut sibling = node.next_sibling();
+        while let Some(s) = sibling.filter(|s| !s.is_named()) {
+            sibling = s.next_sibling();
+        }
+        sibling.filter(|s| {
+            self.key(*s) == self.key(node) && text[node.byte_range()] != text[s.byte_range()]
+        })
+    }
+
+    /// Pick two distinct, non-overlapping nodes of the same kind and swap
+    /// their text.
+    fn swap_nodes<'t, 'n>(
+        &mut self,
+        text: &'t [u8],
+        nodes: &Nodes<'n>,
+    ) -> Option<[(Node<'n>, &'t [u8]); 2]> {
+        let mut groups: BTreeMap<Key, Vec<Node>> = BTreeMap::new();
+        for node in self.collected(nodes).iter().copied() {
+            if !node.byte_range().is_empty() {
+                groups.entry(self.key(node)).or_default().push(node);
+            }
+        }
+        let groups: Vec<_> = groups.into_values().filter(|ns| ns.len() > 1).collect();
+        if groups.is_empty() {
+            return None;
+        }
+        for _ in 0..MAX_ATTEMPTS {
+            let group = &groups[self.pick_idx(&groups)];
+            let a = group[self.pick_idx(group)];
+            let b = group[self.pick_idx(group)];
+            let (a_range, b_range) = (a.byte_range(), b.byte_range());
+            if overlaps(&a_range, &b_range) {
+                continue;
+            }
+            let (a_text, b_text) = (&text[a_range], &text[b_range]);
+            if a_text == b_text {
+                continue;
+            }
+            return Some([(a, b_text), (b, a_text)]);
+        }
+        None
+    }
+
+    fn render(
+        &mut self,
+        text: &[u8],
+        tree: &Tree,
+        edits: &[(Node, &[u8])],
+    ) -> Option<(Vec<u8>, Tree)> {
+        let editor = Edits(
+            edits
+                .iter()
+                .map(|(node, bytes)| (node.id(), *bytes))
+                .collect(),
+        );
+        let mut result = Vec::with_capacity(text.len() / 4); // low guesstimate
+        tree_sitter_edit::render(&mut result, tree, text, &editor).ok()?;
+        let old_tree = input_edits(text.len(), result.len(), edits).map(|input_edits| {
+            let mut old_tree = tree.clone();
+            for input_edit in &input_edits {
+                old_tree.edit(input_edit);
+            }
+            old_tree
+        });
+        let Some(tree) = self.parser.0.parse(&result, old_tree.as_ref()) else {
+            // Otherwise the next parse would resume this one, on other text
+            self.parser.0.reset();
+            return None;
+        };
+        Some((result, tree))
+    }
+
+    /// Panic unless `tree`, re-parsed incrementally after rendering
+    /// mutations, is the same as parsing `text` from scratch. Error recovery
+    /// may differ between the two, so only error-free parses are compared.
+    fn check_reparse(&mut self, text: &[u8], tree: &Tree) {
+        let fresh = self
+            .parser
+            .0
+            .parse(text, None)
+            .expect("Rendered text parses");
+        if !fresh.root_node().has_error() {
+            assert_eq!(
+                tree.root_node().to_sexp(),
+                fresh.root_node().to_sexp(),
+                "Incremental re-parse of {:?}",
+                String::from_utf8_lossy(text)
+            );
+        }
+    }
+
+    /// Replace a node in the `a_idx`th file with a node of the same kind from
+    /// the `b_idx`th file, see [`Splicer::crossover`].
+    /// Returns the new text and tree, the replaced node, and what replaced it
+    fn cross(&mut self, a_idx: usize, b_idx: usize) -> Option<(Vec<u8>, Tree, Node<'a>, &'a [u8])> {
+        let (a_text, a_tree) = self.trees[a_idx];
+        let (b_text, b_tree) = self.trees[b_idx];
+        let mut donors: BTreeMap<Key, Vec<Node>> = BTreeMap::new();
+        for node in self.all_nodes(b_text, b_tree) {
+            donors.entry(self.key(node)).or_default().push(node);
+        }
+        let within = self.within(a_text, a_tree)?;
+        let targets: Vec<_> = self
+            .all_nodes(a_text, a_tree)
+            .into_iter()
+            .filter(|n| within.contains(n) && donors.contains_key(&self.key(*n)))
+            .collect();
+        if targets.is_empty() {
+            return None;
+        }
+        for _ in 0..MAX_ATTEMPTS {
+            let target = targets[self.pick_idx(&targets)];
+            let group = &donors[&self.key(target)];
+            let donor = &b_text[group[self.pick_idx(group)].byte_range()];
+            if donor == &a_text[target.byte_range()] {
+                continue;
+            }
+            let (text, tree) = self.render(a_text, a_tree, &[(target, donor)])?;
+            return Some((text, tree, target, donor));
+        }
+        None
+    }
+
+    /// Pick a mutation operator and apply it. Each operator is picked with its
+    /// percentage, and the rest of the time nodes are spliced.
+    fn mutate<'t, 'n>(
+        &mut self,
+        text: &'t [u8],
+        tree: &'n Tree,
+        nodes: &Nodes<'n>,
+    ) -> Option<(Operator, Vec<NodeEdit<'n, 't>>)> {
+        let (operator, edits) = self.mutate_any(text, tree, nodes)?;
+        let old: usize = edits.iter().map(|(node, _)| node.byte_range().len()).sum();
+        let new: usize = edits.iter().map(|(_, bytes)| bytes.len()).sum();
+        if !self.direction.allows(old, new) {
+            return None;
+        }
+        Some((operator, edits))
+    }
+
+    /// Like [`Splicer::mutate`], but ignoring `direction`
+    fn mutate_any<'t, 'n>(
+        &mut self,
+        text: &'t [u8],
+        tree: &'n Tree,
+        nodes: &Nodes<'n>,
+    ) -> Option<(Operator, Vec<NodeEdit<'n, 't>>)> {
+        let total: u64 = self.operators.iter().map(|op| u64::from(op.weight())).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut roll = self.rng.gen_range(0..total);
+        let operator = self
+            .operators
+            .iter()
+            .find(|op| {
+                let weight = u64::from(op.weight());
+                if roll < weight {
+                    return true;
+                }
+                roll -= weight;
+                false
+            })?
+            .clone();
+        // The context borrows the splicer, so the operator gets a random
+        // number generator of its own
+        let mut rng = StdRng::seed_from_u64(self.rng.gen());
+        let ctx = MutationContext {
+            nodes,
+            collected: OnceCell::new(),
+            splicer: &*self,
+            branches: &self.branches,
+            field_aware: self.field_aware,
+        };
+        let edits = operator.apply_all(&mut rng, text, tree, &ctx)?;
+        Some((
+            operator.operator(),
+            edits
+                .into_iter()
+                .map(|(node, bytes)| (node, Cow::Owned(bytes)))
+                .collect(),
+        ))
+    }
+
+    /// Mutate `text0`, see [`Spliced`]
+    fn splice_tree<'t>(&mut self, text0: &[u8], mut tree: Cow<'t, Tree>) -> Spliced<'t> {
+        // Both bounds are inclusive, see `Config::inter_splices`
+        let mut inter = self.rng.gen_range(0..=self.inter_splices);
+        let mut intra = self.rng.gen_range(0..=self.intra_splices);
+        let mut text = Vec::from(text0);
+        let mut operators = Vec::new();
+        let mut kinds = Vec::new();
+        let mut render_failures = 0;
+        let mut degraded_splices = 0;
+        let mut script = Vec::new();
+        // Extra mutations to make up for ones that didn't happen or didn't
+        // change anything, see `min_splices`
+        let mut retries = MAX_ATTEMPTS * self.min_splices;
+        // Only read the clock if needed, it panics on wasm32-unknown-unknown
+        let start = self.timeout.map(|timeout| (Instant::now(), timeout));
+        let errors0 = if self.check_invariants {
+            errors(&tree)
+        } else {
+            0
+        };
+        loop {
+            if inter + intra == 0 {
+                if operators.len() >= self.min_splices || retries == 0 {
+                    break;
+                }
+                retries -= 1;
+                inter = 1;
+            }
+            if start.is_some_and(|(start, timeout)| start.elapsed() > timeout) {
+                break;
+            }
+            if self.max_bytes.is_some_and(|max| text.len() >= max) {
+                break;
+            }
+            // Walk the tree once per re-parse, rather than once per operation
+            let nodes = self.nodes(&text, &tree);
+            // Outside of captures or anchors, only the root could be mutated
+            if self.no_nodes(&nodes)
+                && (self.query.is_some()
+                    || self.anchor_kind.is_some()
+                    || !self.eligible(tree.root_node().kind(), 0)
+                    || !self.kept(&text, &tree).is_empty())
+            {
+                break;
+            }
+            // Interleave the two kinds of splices
+            let intra_step =
+                inter == 0 || (intra > 0 && self.rng.gen_range(0..inter + intra) < intra);
+            let mut batch_operators = Vec::new();
+            let mut batch_kinds = Vec::new();
+            let batch: Vec<NodeEdit> = if intra_step {
+                intra -= 1;
+                // Swaps don't change the size
+                if self.direction != Direction::Any {
+                    continue;
+                }
+                match self.swap_nodes(text.as_slice(), &nodes) {
+                    Some(swap) => {
+                        batch_operators.push(Operator::Swap);
+                        batch_kinds.push(swap[0].0.kind());
+                        swap.into_iter()
+                            .map(|(node, bytes)| (node, Cow::Borrowed(bytes)))
+                            .collect()
+                    }
+                    None => continue,
+                }
+            } else {
+                let batch_size = std::cmp::min(std::cmp::max(self.batch_size, 1), inter);
+                inter -= batch_size;
+                let mut batch: Vec<NodeEdit> = Vec::with_capacity(batch_size);
+                // Length of the text once the batch is applied
+                let mut len = text.len();
+                for _ in 0..batch_size {
+                    let (operator, edits) = match self.mutate(text.as_slice(), &tree, &nodes) {
+                        Some(mutation) => mutation,
+                        None => continue,
+                    };
+                    // Edits to nested nodes would be lost when rendering
+                    if edits.iter().any(|(node, _)| {
+                        batch
+                            .iter()
+                            .any(|(n, _)| overlaps(&n.byte_range(), &node.byte_range()))
+                    }) {
+                        continue;
+                    }
+                    let new_len = edits.iter().fold(len, |len, (node, bytes)| {
+                        len - node.byte_range().len() + bytes.len()
+                    });
+                    if self.max_bytes.is_some_and(|max| new_len > max) {
+                        continue;
+                    }
+                    len = new_len;
+                    batch_operators.push(operator);
+                    batch_kinds.push(edits[0].0.kind());
+                    batch.extend(edits);
+                }
+                batch
+            };
+            if batch.is_empty() {
+                continue;
+            }
+            let batch: Vec<_> = batch
+                .iter()
+                .map(|(node, bytes)| (*node, bytes.as_ref()))
+                .collect();
+            let (new_text, new_tree) = match self.render(text.as_slice(), &tree, &batch) {
+                Some(rendered) => rendered,
+                None => {
+                    render_failures += 1;
+                    for (node, _) in &batch {
+                        debug!(
+                            "Failed to render mutation of {} at bytes {:?}",
+                            node.kind(),
+                            node.byte_range()
+                        );
+                    }
+                    continue;
+                }
+            };
+            if self.check_invariants {
+                self.check_reparse(&new_text, &new_tree);
+            }
+            let degraded = errors(&new_tree) > errors(&tree);
+            if degraded {
+                degraded_splices += 1;
+                for (node, _) in &batch {
+                    debug!(
+                        "Mutation of {} at bytes {:?} may have introduced parse errors",
+                        node.kind(),
+                        node.byte_range()
+                    );
+                }
+            }
+            if self.reject_errors && degraded {
+                continue;
+            }
+            // Drop this batch and stop, rather than hope later ones undo it
+            if self
+                .max_tree_depth
+                .is_some_and(|max| tree_depth(&new_tree) > max)
+            {
+                break;
+            }
+            if new_text == text {
+                continue;
+            }
+            // Not a mutation at all as far as the output is concerned
+            if self.min_splices > 0
+                && (new_text == text0 || self.trees.iter().any(|(t, _)| *t == new_text.as_slice()))
+            {
+                continue;
+            }
+            if self.record_edits {
+                script.extend(sequential_edits(&text, &batch));
+            }
+            text = new_text;
+            tree = Cow::Owned(new_tree);
+            operators.extend(batch_operators);
+            kinds.extend(batch_kinds);
+        }
+        if self.check_invariants && self.reject_errors {
+            assert!(
+                errors(&tree) <= errors0,
+                "Mutations introduced parse errors despite `reject_errors`"
+            );
+        }
+        Spliced {
+            text,
+            tree,
+            operators,
+            kinds,
+            render_failures,
+            degraded_splices,
+            edits: script,
+        }
+    }
+}
+
+impl<'a, R: Rng> Splicer<'a, R> {
+    /// Like [`candidate_counts`], including files from [`Splicer::add_input`]
+    pub fn candidate_counts(&self) -> CandidateCounts<'_> {
+        self.branches.by_kind()
+    }
+
+    /// Replace a node in the `a_idx`th file with a node of the same kind from
+    /// the `b_idx`th file, as [`Config::crossover`] does before mutating. Files
+    /// are numbered in order of their names. Like other mutations, only nodes
+    /// allowed by e.g. `query`, `anchor_kind`, and `exclude_kinds` are
+    /// replaced.
+    ///
+    /// The output isn't counted in [`Splicer::remaining`], and its `index` is
+    /// that of the next output. `None` if either file doesn't exist, or they
+    /// have no nodes of the same kind to swap.
+    pub fn crossover(&mut self, a_idx: usize, b_idx: usize) -> Option<Output> {
+        if std::cmp::max(a_idx, b_idx) >= self.trees.len() {
+            return None;
+        }
+        let (text, tree) = self.trees[a_idx];
+        let (bytes, crossed, target, donor) = self.cross(a_idx, b_idx)?;
+        let edits = if self.record_edits {
+            sequential_edits(text, &[(target, donor)])
+        } else {
+            Vec::new()
+        };
+        Some(Output {
+            bytes,
+            source_file: self.names[a_idx].to_string(),
+            seed: self.seed,
+            index: self.index,
+            splices_applied: 1,
+            operators: vec![Operator::Crossover],
+            kinds: vec![target.kind()],
+            render_failures: 0,
+            degraded_splices: usize::from(errors(&crossed) > errors(tree)),
+            edits,
+        })
+    }
+
+    /// How many more outputs may be made. Fewer may actually be made, e.g.,
+    /// due to `config.unique`.
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    /// The random number generator as it is between outputs. Splicers made
+    /// with [`Splicer::new`] reseed it before each output, from the seed and
+    /// the index of the output.
+    pub fn rng(&self) -> &R {
+        &self.rng
+    }
+
+    /// The energy of the file named `file`, see [`FileSelection::Energy`].
+    /// `None` if there's no such file or files aren't picked by energy.
+    pub fn energy(&self, file: &str) -> Option<usize> {
+        if self.file_selection != FileSelection::Energy {
+            return None;
+        }
+        let idx = self.names.iter().position(|name| *name == file)?;
+        Some(self.file_weights[idx])
+    }
+
+    /// Set the energy of the file named `file`, see [`FileSelection::Energy`].
+    /// Returns `false` if there's no such file or files aren't picked by
+    /// energy.
+    pub fn set_energy(&mut self, file: &str, energy: usize) -> bool {
+        if self.file_selection != FileSelection::Energy {
+            return false;
+        }
+        match self.names.iter().position(|name| *name == file) {
+            Some(idx) => {
+                self.file_weights[idx] = energy;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Add `amount` to the energy of the file named `file`, see
+    /// [`Splicer::set_energy`]
+    pub fn bump_energy(&mut self, file: &str, amount: usize) -> bool {
+        match self.energy(file) {
+            Some(energy) => self.set_energy(file, energy.saturating_add(amount)),
+            None => false,
+        }
+    }
+
+    /// Add a file to those to mutate and splice candidates from, e.g., an
+    /// output that a fuzzer found interesting. It's kept for as long as this
+    /// splicer or a clone of it is.
+    ///
+    /// Outputs made after this differ from those made with the same seed and
+    /// index before it.
+    ///
+    /// Fails if `bytes` can't be parsed, or don't look like they're in the
+    /// language of the grammar.
+    pub fn add_input(
+        &mut self,
+        name: impl Into<String>,
+        bytes: Vec<u8>,
+    ) -> Result<(), SpliceError> {
+        let tree = self
+            .parser
+            .0
+            .parse(&bytes, None)
+            .ok_or(SpliceError::Parse)?;
+        if !in_language(&bytes, &tree) {
+            return Err(SpliceError::WrongLanguage);
+        }
+        let name: Arc<str> = name.into().into();
+        let bytes: Arc<[u8]> = bytes.into();
+        let tree = Arc::new(tree);
+        let file = (Arc::as_ptr(&name), Arc::as_ptr(&bytes), Arc::as_ptr(&tree));
+        self.added.push((name, bytes, tree));
+        // SAFETY: Candidates borrow from the file, so it must outlive them.
+        // It's in `self.added` until this splicer and its clones are dropped,
+        // which don't hand out borrows that outlive them, and it doesn't move
+        // when more files are added.
+        let (name, text, tree): (&'a str, &'a [u8], &'a Tree) =
+            unsafe { (&*file.0, &*file.1, &*file.2) };
+        let added = Branches::new(
+            vec![(text, tree)],
+            |kind| allowed(&self.include_kinds, &self.exclude_kinds, kind),
+            self.field_aware,
+            None,
+            &mut self.rng,
+        );
+        // Copies the candidates if they're shared with clones of this splicer
+        Arc::make_mut(&mut self.branches).merge(added, self.max_candidates_per_kind);
+        self.kinds = self.branches.0.keys().copied().collect();
+        self.kind_weights = self.branches.0.values().map(Vec::len).collect();
+        if self.supertype_splicing {
+            self.supertype_pools = supertype_pools(&self.branches, &self.node_types);
+        }
+        match self.file_selection {
+            FileSelection::WeightedBySize => self.file_weights.push(node_count(tree)),
+            FileSelection::Energy => self.file_weights.push(1),
+            FileSelection::Uniform | FileSelection::RoundRobin => (),
+        }
+        self.names.push(name);
+        self.trees.push((text, tree));
+        Ok(())
+    }
+
+    /// Make the next output, and describe how it was made
+    pub fn next_output(&mut self) -> Option<Output> {
+        loop {
+            if self.remaining == 0 || self.trees.is_empty() {
+                return None;
+            }
+            self.remaining -= 1;
+            let index = self.index;
+            self.index += 1;
+            let turn = self.turn;
+            self.turn += 1;
+            if let Some(reseed) = self.reseed {
+                self.rng = reseed(output_seed(self.seed, index));
+            }
+
+            let tree_idx = match self.file_selection {
+                FileSelection::Uniform => self.pick_usize(self.trees.len()),
+                FileSelection::RoundRobin => turn % self.trees.len(),
+                FileSelection::WeightedBySize | FileSelection::Energy
+                    if self.file_weights.iter().any(|w| *w > 0) =>
+                {
+                    pick_weighted(&mut self.rng, &self.file_weights)
+                }
+                // Every file is out of energy
+                FileSelection::WeightedBySize | FileSelection::Energy => {
+                    self.pick_usize(self.trees.len())
+                }
+            };
+            let (text, tree) = *self.trees.get(tree_idx).unwrap();
+            let crossed = if self.crossover && self.trees.len() > 1 {
+                let mut other_idx = self.pick_usize(self.trees.len() - 1);
+                if other_idx >= tree_idx {
+                    other_idx += 1;
+                }
+                self.cross(tree_idx, other_idx)
+                    .filter(|(crossed, _, _, _)| self.direction.allows(text.len(), crossed.len()))
+            } else {
+                None
+            };
+            let spliced = match crossed {
+                Some((crossed, tree, target, donor)) => {
+                    let mut spliced = self.splice_tree(&crossed, Cow::Owned(tree));
+                    spliced.operators.insert(0, Operator::Crossover);
+                    spliced.kinds.insert(0, target.kind());
+                    if self.record_edits {
+                        let crossover = sequential_edits(text, &[(target, donor)]);
+                        spliced.edits.splice(0..0, crossover);
+                    }
+                    spliced
+                }
+                None => self.splice_tree(text, Cow::Borrowed(tree)),
+            };
+            let Spliced {
+                text: out,
+                tree: out_tree,
+                operators,
+                kinds,
+                render_failures,
+                degraded_splices,
+                edits,
+            } = spliced;
+            // The file may have started out too big
+            if self.max_bytes.is_some_and(|max| out.len() > max) {
+                continue;
+            }
+            if self.drop_unchanged && self.trees.iter().any(|(text, _)| *text == out.as_slice()) {
+                continue;
+            }
+            let key = if self.structural_unique {
+                Some(structural_hash(&out, &out_tree))
+            } else if self.unique {
+                Some(hash(&out))
+            } else {
+                None
+            };
+            if key.is_some_and(|key| !self.seen.insert(key)) {
+                continue;
+            }
+            if let Some(left) = self.bytes_left {
+                if out.len() > left {
+                    debug!("Stopping, {} bytes of the budget are left", left);
+                    self.remaining = 0;
+                    return None;
+                }
+                self.bytes_left = Some(left - out.len());
+            }
+            return Some(Output {
+                bytes: out,
+                source_file: self.names[tree_idx].to_string(),
+                seed: self.seed,
+                index,
+                splices_applied: operators.len(),
+                operators,
+                kinds,
+                render_failures,
+                degraded_splices,
+                edits,
+            });
+        }
+    }
+}
+
+impl<'a, R: Rng> Iterator for Splicer<'a, R> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_output().map(|output| output.bytes)
+    }
+}
+
+/// Mutates inputs one at a time with candidates from a fixed corpus, e.g.,
+/// as the custom mutator of a coverage-guided fuzzer.
+///
+/// The corpus is only read once, when this is made, rather than once per
+/// call. Clones share the candidates, so threads can each cheaply have their
+/// own.
+///
+/// It's `Send`, but not `Sync`, since it re-parses with a tree-sitter
+/// `Parser`: rather than sharing one between threads, give each its own clone,
+/// e.g., in a `thread_local!`.
+#[derive(Clone)]
+pub struct PersistentSplicer<'a> {
+    splicer: Splicer<'a>,
+}
+
+const _: () = {
+    const fn send<T: Send>() {}
+    send::<PersistentSplicer<'static>>();
+};
+
+impl<'a> PersistentSplicer<'a> {
+    /// Read candidates from `files`. `config.tests`, `config.unique`,
+    /// `config.structural_unique`, `config.total_bytes_budget`, and
+    /// `config.on_output` are ignored, since they're about a whole run, and
+    /// each call to [`PersistentSplicer::mutate_one`] is one on its own.
+    ///
+    /// Fails like [`splice`].
+    pub fn new(
+        config: Config,
+        files: &'a HashMap<String, (Vec<u8>, Tree)>,
+    ) -> Result<Self, SpliceError> {
+        let mut splicer = splicer(config, files)?;
+        splicer.unique = false;
+        splicer.structural_unique = false;
+        splicer.bytes_left = None;
+        Ok(PersistentSplicer { splicer })
+    }
+
+    /// Mutate a file from the corpus. The same as the first output of
+    /// [`splice`] with `config.seed` set to `seed`, except that with
+    /// [`FileSelection::RoundRobin`], each call mutates the file after the one
+    /// the last call did.
+    ///
+    /// Returns `None` if no output could be made, e.g., due to
+    /// `config.max_bytes`.
+    pub fn mutate_one(&mut self, seed: u64) -> Option<Vec<u8>> {
+        self.splicer.seed = seed;
+        self.splicer.index = 0;
+        self.splicer.remaining = 1;
+        self.splicer.next()
+    }
+
+    /// See [`Splicer::add_input`]
+    pub fn add_input(
+        &mut self,
+        name: impl Into<String>,
+        bytes: Vec<u8>,
+    ) -> Result<(), SpliceError> {
+        self.splicer.add_input(name, bytes)
+    }
+
+    /// Mutate `input`, which needn't be in the corpus. The result only
+    /// depends on `input`, `seed`, and the corpus.
+    ///
+    /// Leaves `input` as it is if it can't be parsed.
+    pub fn mutate_in_place(&mut self, input: &mut Vec<u8>, seed: u64) {
+        self.splicer.rng = StdRng::seed_from_u64(seed);
+        let Some(tree) = self.splicer.parser.0.parse(input.as_slice(), None) else {
+            return;
+        };
+        *input = self.splicer.splice_tree(input, Cow::Owned(tree)).text;
+    }
+}
+
+/// For each kind in `branches`, the kinds in `branches` that are
+/// [`NodeTypes::interchangeable`] with it
+fn supertype_pools(
+    branches: &Branches,
+    node_types: &NodeTypes,
+) -> FxHashMap<&'static str, Vec<&'static str>> {
+    let kinds: BTreeSet<&'static str> = branches.0.keys().map(|(_, kind)| *kind).collect();
+    kinds
+        .iter()
+        .map(|kind| {
+            let pool = node_types.interchangeable(kind);
+            let pool = kinds.iter().copied().filter(|k| pool.contains(k)).collect();
+            (*kind, pool)
+        })
+        .collect()
+}
+
+/// The names and contents of `files`, sorted by name so that the results
+/// don't depend on the order of the map
+fn sorted_trees(files: &HashMap<String, (Vec<u8>, Tree)>) -> (Vec<&str>, Vec<(&[u8], &Tree)>) {
+    let mut files: Vec<_> = files.iter().collect();
+    files.sort_unstable_by_key(|(path, _)| *path);
+    files
+        .into_iter()
+        .map(|(path, (txt, tree))| (path.as_str(), (txt.as_slice(), tree)))
+        .unzip()
+}
+
+/// The candidates in `trees`, plus those in `config.dictionary`, sampled with
+/// `rng` if there are more than `config.max_candidates_per_kind`
+fn branches<'a>(
+    config: &Config,
+    trees: Vec<(&'a [u8], &'a Tree)>,
+    rng: &mut impl Rng,
+) -> Branches<'a> {
+    // Not the whole config, which needn't be `Sync`
+    let (include, exclude) = (&config.include_kinds, &config.exclude_kinds);
+    let mut branches = Branches::new(
+        trees,
+        |kind| allowed(include, exclude, kind),
+        config.field_aware,
+        config.max_candidates_per_kind,
+        rng,
+    );
+    for (kind, token) in &config.dictionary {
+        if allowed(&config.include_kinds, &config.exclude_kinds, kind) {
+            branches.add(kind, token);
+        }
+    }
+    branches
+}
+
+fn splicer(
+    config: Config,
+    files: &HashMap<String, (Vec<u8>, Tree)>,
+) -> Result<Splicer<'_>, SpliceError> {
+    let rng = StdRng::seed_from_u64(config.seed);
+    splicer_with_rng(config, files, rng, Some(StdRng::seed_from_u64))
+}
+
+fn splicer_with_rng<R: Rng>(
+    mut config: Config,
+    files: &HashMap<String, (Vec<u8>, Tree)>,
+    mut rng: R,
+    reseed: Option<fn(u64) -> R>,
+) -> Result<Splicer<'_, R>, SpliceError> {
+    if !files
+        .values()
+        .any(|(txt, tree)| !txt.is_empty() && !tree.root_node().is_error())
+    {
+        return Err(SpliceError::EmptyCorpus);
+    }
+    // Checked by `ConfigBuilder::build`, but not if the `Config` was made
+    // without one
+    if config.max_candidates_per_kind == Some(0) {
+        return Err(SpliceError::InvalidConfig(
+            "max candidates per kind must be at least 1".to_string(),
+        ));
+    }
+    let parser = parser(config.language)?;
+    let query = match &config.query {
+        Some(query) => Some(Arc::new(compile_query(config.language, query)?)),
+        None => None,
+    };
+    if config.preserve_comments {
+        let comment_kinds = config.comment_kinds.iter().copied();
+        config.exclude_kinds.extend(comment_kinds);
+    }
+    let (names, trees) = sorted_trees(files);
+    let branches = Arc::new(branches(&config, trees.clone(), &mut rng));
+    let possible = branches.possible();
+    if possible < config.tests {
+        warn!("Only {possible} possible mutations");
+    }
+    let kinds = branches.0.keys().copied().collect();
+    let supertype_pools = if config.supertype_splicing {
+        supertype_pools(&branches, &config.node_types)
+    } else {
+        FxHashMap::default()
+    };
+    let file_weights = match config.file_selection {
+        FileSelection::WeightedBySize => trees.iter().map(|(_, tree)| node_count(tree)).collect(),
+        FileSelection::Energy => vec![1; trees.len()],
+        FileSelection::Uniform | FileSelection::RoundRobin => Vec::new(),
+    };
+    let kind_weights = branches.0.values().map(Vec::len).collect();
+    Ok(Splicer {
+        added: Vec::new(),
+        anchor_kind: config.anchor_kind,
+        candidate_filter: config.candidate_filter,
+        candidate_size_bias: config.candidate_size_bias,
+        candidate_weighting: config.candidate_weighting,
+        chaos_weighting: config.chaos_weighting,
+        comment_kinds: config.comment_kinds.into_iter().collect(),
+        crossover: config.crossover,
+        delete_chaos: config.delete_chaos,
+        direction: config.direction,
+        drop_unchanged: config.drop_unchanged,
+        exclude_kinds: config.exclude_kinds.into_iter().collect(),
+        field_aware: config.field_aware,
+        file_selection: config.file_selection,
+        file_weights,
+        honor_pragmas: config.honor_pragmas,
+        include_kinds: config
+            .include_kinds
+            .map(|kinds| kinds.into_iter().collect()),
+        language: config.language,
+        batch_size: config.batch_size,
+        branches,
+        kinds,
+        kind_weights,
+        max_bytes: config.max_bytes,
+        max_candidates_per_kind: config.max_candidates_per_kind,
+        max_depth: config.max_depth,
+        max_tree_depth: config.max_tree_depth,
+        min_depth: config.min_depth,
+        min_splices: config.min_splices,
+        names,
+        intra_splices: config.intra_splices,
+        inter_splices: config.inter_splices,
+        node_types: config.node_types,
+        operators: config.operators,
+        parser: ReusedParser(parser),
+        preserve_comments: config.preserve_comments,
+        preserve_prefix_bytes: config.preserve_prefix_bytes,
+        query,
+        record_edits: false,
+        check_invariants: cfg!(test),
+        reject_errors: config.reject_errors,
+        reservoir_sampling: config.reservoir_sampling,
+        index: 0,
+        turn: 0,
+        remaining: if config.allow_repeats {
+            config.tests
+        } else {
+            std::cmp::min(config.tests, possible)
+        },
+        rng,
+        reseed,
+        seed: config.seed,
+        splice_chaos: config.splice_chaos,
+        supertype_pools,
+        supertype_splicing: config.supertype_splicing,
+        timeout: config.timeout,
+        bytes_left: config.total_bytes_budget,
+        seen: FxHashSet::default(),
+        trees,
+        structural_unique: config.structural_unique,
+        unique: config.unique,
+    })
+}
+
+/// How many distinct splices `files` allow, which bounds how many outputs
+/// [`splice`] makes.
+///
+/// ```
+/// use std::collections::HashMap;
+///
+/// use tree_splicer::node_types::NodeTypes;
+/// use tree_splicer::splice::{possible_mutations, ConfigBuilder};
+///
+/// let mut parser = tree_sitter::Parser::new();
+/// parser.set_language(tree_sitter_rust::language()).unwrap();
+/// let mut files = HashMap::new();
+/// for (name, text) in [("a.rs", "fn f() { g(1); }"), ("b.rs", "fn h() { g(2); g(3); }")] {
+///     let tree = parser.parse(text, None).unwrap();
+///     files.insert(name.to_string(), (text.as_bytes().to_vec(), tree));
+/// }
+/// let node_types = NodeTypes::new(tree_sitter_rust::NODE_TYPES).unwrap();
+/// let config = ConfigBuilder::new(tree_sitter_rust::language(), node_types)
+///     .with_include_kinds(["identifier", "integer_literal"])
+///     .build()
+///     .unwrap();
+/// // `f`, `g`, and `h` may replace one another, and so may `1`, `2`, and `3`
+/// assert_eq!(possible_mutations(&files, &config), 4);
+/// ```
+pub fn possible_mutations(files: &HashMap<String, (Vec<u8>, Tree)>, config: &Config) -> usize {
+    let rng = &mut StdRng::seed_from_u64(config.seed);
+    branches(config, sorted_trees(files).1, rng).possible()
+}
+
+/// The texts that splices may replace nodes of each kind with, and how many
+/// times each occurs in `files`. Follows `config.include_kinds`,
+/// `config.exclude_kinds`, `config.dictionary`, and
+/// `config.max_candidates_per_kind`; if the latter is set, texts are only
+/// counted from when they're picked to be kept.
+pub fn candidate_counts<'a>(
+    files: &'a HashMap<String, (Vec<u8>, Tree)>,
+    config: &Config,
+) -> CandidateCounts<'a> {
+    let rng = &mut StdRng::seed_from_u64(config.seed);
+    branches(config, sorted_trees(files).1, rng).by_kind()
+}
+
+/// How often a node kind appears in a corpus, see [`analyze`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct KindStats {
+    /// Number of nodes of the kind
+    pub count: usize,
+    /// Number of distinct texts of nodes of the kind, which are what get
+    /// spliced in
+    pub distinct: usize,
+}
+
+/// Count the nodes of each kind in `files`, except `ERROR` and `MISSING`
+/// nodes.
+pub fn analyze(files: &HashMap<String, (Vec<u8>, Tree)>) -> BTreeMap<&'static str, KindStats> {
+    let mut texts: BTreeMap<&'static str, (usize, FxHashSet<&[u8]>)> = BTreeMap::new();
+    for (text, tree) in files.values() {
+        let mut cursor = tree.walk();
+        'walk: loop {
+            let node = cursor.node();
+            if !node.is_error() && !node.is_missing() {
+                let (count, distinct) = texts.entry(node.kind()).or_default();
+                *count += 1;
+                distinct.insert(&text[node.byte_range()]);
+            }
+            if cursor.goto_first_child() {
+                continue;
+            }
+            while !cursor.goto_next_sibling() {
+                if !cursor.goto_parent() {
+                    break 'walk;
+                }
+            }
+        }
+    }
+    texts
+        .into_iter()
+        .map(|(kind, (count, distinct))| {
+            let distinct = distinct.len();
+            (kind, KindStats { count, distinct })
+        })
+        .collect()
+}
+
+/// Names of a small subset of `files` with the same texts of each node kind
+/// as all of them, so that splicing them allows the same mutations. Files are
+/// picked greedily, by how many texts they add, and returned in sorted order.
+pub fn minimize_corpus(files: &HashMap<String, (Vec<u8>, Tree)>) -> Vec<String> {
+    let mut contributed: Vec<_> = files
+        .iter()
+        .map(|(name, (text, tree))| {
+            let branches = Branches::new(
+                vec![(text.as_slice(), tree)],
+                |_| true,
+                false,
+                None,
+                &mut StdRng::seed_from_u64(0),
+            );
+            let pairs: FxHashSet<_> = branches
+                .0
+                .into_iter()
+                .flat_map(|((_, kind), texts)| texts.into_iter().map(move |text| (kind, text)))
+                .collect();
+            (name, pairs)
+        })
+        .collect();
+    contributed.sort_unstable_by_key(|(name, _)| *name);
+    let mut covered = FxHashSet::default();
+    let mut kept = Vec::new();
+    loop {
+        // Ties go to the file with the first name
+        let best = contributed
+            .iter()
+            .enumerate()
+            .map(|(i, (_, pairs))| (pairs.difference(&covered).count(), i))
+            .max_by_key(|(new, i)| (*new, std::cmp::Reverse(*i)));
+        match best {
+            Some((new, i)) if new > 0 => {
+                let (name, pairs) = contributed.remove(i);
+                covered.extend(pairs);
+                kept.push(name.clone());
+            }
+            _ => break,
+        }
+    }
+    kept.sort_unstable();
+    kept
+}
+
+/// A part of a file that differs between two versions of it, see
+/// [`structural_diff`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Change<'a> {
+    /// Kind of the changed node in the original, or of their parent if
+    /// several siblings changed
+    pub kind: &'static str,
+    /// Byte range in the original
+    pub before: Range<usize>,
+    /// Byte range in the new version
+    pub after: Range<usize>,
+    pub before_text: &'a [u8],
+    pub after_text: &'a [u8],
+}
+
+/// The smallest subtrees that differ between `before` and `after` (e.g., an
+/// output and the file it was made from), found by aligning the trees.
+/// Changes to whitespace between nodes are ignored.
+pub fn structural_diff<'a>(
+    before_text: &'a [u8],
+    before: &Tree,
+    after_text: &'a [u8],
+    after: &Tree,
+) -> Vec<Change<'a>> {
+    let same = |a: &Node, b: &Node| {
+        a.kind() == b.kind() && before_text[a.byte_range()] == after_text[b.byte_range()]
+    };
+    let change = |kind, before: Range<usize>, after: Range<usize>| Change {
+        kind,
+        before_text: &before_text[before.clone()],
+        after_text: &after_text[after.clone()],
+        before,
+        after,
+    };
+    let mut changes = Vec::new();
+    let mut pairs = vec![(before.root_node(), after.root_node())];
+    while let Some((a, b)) = pairs.pop() {
+        if same(&a, &b) {
+            continue;
+        }
+        if a.kind() != b.kind() || (a.child_count() == 0 && b.child_count() == 0) {
+            changes.push(change(a.kind(), a.byte_range(), b.byte_range()));
+            continue;
+        }
+        let mut a_cursor = a.walk();
+        let mut b_cursor = b.walk();
+        let a_children: Vec<_> = a.children(&mut a_cursor).collect();
+        let b_children: Vec<_> = b.children(&mut b_cursor).collect();
+        let prefix = a_children
+            .iter()
+            .zip(&b_children)
+            .take_while(|(x, y)| same(x, y))
+            .count();
+        let suffix = a_children[prefix..]
+            .iter()
+            .rev()
+            .zip(b_children[prefix..].iter().rev())
+            .take_while(|(x, y)| same(x, y))
+            .count();
+        let a_rest = &a_children[prefix..a_children.len() - suffix];
+        let b_rest = &b_children[prefix..b_children.len() - suffix];
+        if a_rest.len() == b_rest.len() {
+            // Only whitespace changed if both are empty
+            pairs.extend(a_rest.iter().copied().zip(b_rest.iter().copied()).rev());
+            continue;
+        }
+        // Where the differing children are, or would be
+        let span = |node: &Node, children: &[Node], rest: &[Node]| {
+            if let (Some(first), Some(last)) = (rest.first(), rest.last()) {
+                return first.start_byte()..last.end_byte();
+            }
+            let at = children[..prefix]
+                .last()
+                .map_or(node.start_byte(), |n| n.end_byte());
+            at..at
+        };
+        let kind = match a_rest {
+            [only] => only.kind(),
+            _ => a.kind(),
+        };
+        changes.push(change(
+            kind,
+            span(&a, &a_children, a_rest),
+            span(&b, &b_children, b_rest),
+        ));
+    }
+    changes
+}
+
+/// Longest token AFL accepts in a dictionary
+const MAX_DICTIONARY_TOKEN: usize = 128;
+
+/// The distinct texts of the leaves of `files` (e.g., keywords, operators, and
+/// identifiers), in the dictionary format of AFL and libFuzzer: one quoted
+/// token per line, in sorted order. Tokens too long for AFL are left out.
+pub fn dictionary(files: &HashMap<String, (Vec<u8>, Tree)>) -> String {
+    let mut tokens: BTreeSet<&[u8]> = BTreeSet::new();
+    for (text, tree) in files.values() {
+        let mut cursor = tree.walk();
+        'walk: loop {
+            let node = cursor.node();
+            if cursor.goto_first_child() {
+                continue;
+            }
+            let token = &text[node.byte_range()];
+            if !node.is_error()
+                && !node.is_missing()
+                && !token.is_empty()
+                && token.len() <= MAX_DICTIONARY_TOKEN
+            {
+                tokens.insert(token);
+            }
+            while !cursor.goto_next_sibling() {
+                if !cursor.goto_parent() {
+                    break 'walk;
+                }
+            }
+        }
+    }
+    let mut dict = String::new();
+    for token in tokens {
+        dict.push('"');
+        for byte in token {
+            match byte {
+                b'"' | b'\\' => {
+                    dict.push('\\');
+                    dict.push(char::from(*byte));
+                }
+                b' '..=b'~' => dict.push(char::from(*byte)),
+                _ => dict.push_str(&format!("\\x{byte:02x}")),
+            }
+        }
+        dict.push_str("\"\n");
+    }
+    dict
+}
+
+/// Read the tokens in a dictionary in the format of AFL and libFuzzer, e.g.,
+/// one made by [`dictionary`]. The tokens may be named (`name="token"`), and
+/// lines starting with `#` are ignored.
+pub fn parse_dictionary(dict: &str) -> Result<Vec<Vec<u8>>, SpliceError> {
+    let mut tokens = Vec::new();
+    for (i, line) in dict.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid =
+            |msg: &str| SpliceError::InvalidConfig(format!("dictionary line {}: {msg}", i + 1));
+        let quoted = match line.find('"') {
+            Some(start) if line.len() > start + 1 && line.ends_with('"') => {
+                &line[start + 1..line.len() - 1]
+            }
+            _ => return Err(invalid("expected a quoted token")),
+        };
+        let mut token = Vec::with_capacity(quoted.len());
+        let mut bytes = quoted.bytes();
+        while let Some(byte) = bytes.next() {
+            if byte != b'\\' {
+                token.push(byte);
+                continue;
+            }
+            match bytes.next() {
+                Some(b'x') => {
+                    let hex = [bytes.next(), bytes.next()];
+                    let hex: Option<Vec<u8>> = hex.into_iter().collect();
+                    let byte = hex
+                        .and_then(|hex| String::from_utf8(hex).ok())
+                        .and_then(|hex| u8::from_str_radix(&hex, 16).ok())
+                        .ok_or_else(|| invalid("bad \\x escape"))?;
+                    token.push(byte);
+                }
+                Some(byte) => token.push(byte),
+                None => return Err(invalid("unfinished escape")),
+            }
+        }
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// Generate up to `config.tests` new files by splicing together `files`.
+///
+/// Files are considered in order of their names, so the output is
+/// reproducible given the same files, names, and seed.
+///
+/// Outputs are made of the texts of whole nodes (and UTF-8 literals), so they
+/// are valid UTF-8 if `files` are, unless tokens in `config.dictionary` or the
+/// outputs of custom operators in `config.operators` aren't. Bytes that aren't valid UTF-8
+/// are copied as they are.
+///
+/// Fails if the language is incompatible with the linked tree-sitter, or if
+/// `files` has no non-empty file that parses.
+#[allow(clippy::needless_lifetimes)]
+pub fn splice<'a>(
+    mut config: Config,
+    files: &'a HashMap<String, (Vec<u8>, Tree)>,
+) -> Result<impl Iterator<Item = Vec<u8>> + 'a, SpliceError> {
+    let on_output = config.on_output.take();
+    Ok(observe(splicer(config, files)?, on_output))
+}
+
+/// Stop after the output for which `on_output` breaks
+fn observe<T>(
+    mut outputs: impl Iterator<Item = T>,
+    mut on_output: Option<OnOutput>,
+) -> impl Iterator<Item = T> {
+    let mut count = 0;
+    let mut stopped = false;
+    std::iter::from_fn(move || {
+        if stopped {
+            return None;
+        }
+        let out = outputs.next()?;
+        count += 1;
+        if let Some(OnOutput(on_output)) = &mut on_output {
+            stopped = on_output(count).is_break();
+        }
+        Some(out)
+    })
+}
+
+/// Like [`splice`], but for a single file that hasn't been parsed yet.
+///
+/// The outputs are all made before this returns.
+///
+/// ```
+/// use tree_splicer::node_types::NodeTypes;
+/// use tree_splicer::splice::{splice_str, ConfigBuilder};
+///
+/// let node_types = NodeTypes::new(tree_sitter_rust::NODE_TYPES).unwrap();
+/// let config = ConfigBuilder::new(tree_sitter_rust::language(), node_types)
+///     .with_include_kinds(["integer_literal"])
+///     .with_seed(0)
+///     .with_tests(2)
+///     .build()
+///     .unwrap();
+/// let source = "fn f() -> u8 { 1 + 2 }";
+/// let variants = [
+///     "fn f() -> u8 { 1 + 2 }",
+///     "fn f() -> u8 { 1 + 1 }",
+///     "fn f() -> u8 { 2 + 1 }",
+///     "fn f() -> u8 { 2 + 2 }",
+/// ];
+/// for variant in splice_str(source, config).unwrap() {
+///     println!("{variant}");
+///     assert!(variants.contains(&variant.as_str()));
+/// }
+/// ```
+pub fn splice_str(
+    source: &str,
+    config: Config,
+) -> Result<impl Iterator<Item = String>, SpliceError> {
+    let tree = parse(config.language, source.as_bytes(), None)?;
+    let mut files = HashMap::with_capacity(1);
+    files.insert(String::new(), (source.as_bytes().to_vec(), tree));
+    let outputs: Vec<_> = splice(config, &files)?
+        .map(|out| match String::from_utf8(out) {
+            Ok(s) => s,
+            Err(e) => String::from_utf8_lossy(e.as_bytes()).into_owned(),
+        })
+        .collect();
+    Ok(outputs.into_iter())
+}
+
+/// Like [`splice`], but use `rng` for all random choices.
+///
+/// `config.seed` is ignored, and the outputs don't have their own random seeds,
+/// so [`reproduce`] can't make them.
+#[allow(clippy::needless_lifetimes)]
+pub fn splice_with_rng<'a, R: Rng + 'a>(
+    mut config: Config,
+    files: &'a HashMap<String, (Vec<u8>, Tree)>,
+    rng: R,
+) -> Result<impl Iterator<Item = Vec<u8>> + 'a, SpliceError> {
+    let on_output = config.on_output.take();
+    Ok(observe(
+        splicer_with_rng(config, files, rng, None)?,
+        on_output,
+    ))
+}
+
+/// Like [`splice`], but describe how each output was made.
+#[allow(clippy::needless_lifetimes)]
+pub fn splice_with_provenance<'a>(
+    mut config: Config,
+    files: &'a HashMap<String, (Vec<u8>, Tree)>,
+) -> Result<impl Iterator<Item = Output> + 'a, SpliceError> {
+    let on_output = config.on_output.take();
+    let mut splicer = splicer(config, files)?;
+    Ok(observe(
+        std::iter::from_fn(move || splicer.next_output()),
+        on_output,
+    ))
+}
+
+/// Like [`splice_with_provenance`], but also record the edits that turn each
+/// source file into the output, see [`Output::edits`].
+#[allow(clippy::needless_lifetimes)]
+pub fn splice_with_edits<'a>(
+    mut config: Config,
+    files: &'a HashMap<String, (Vec<u8>, Tree)>,
+) -> Result<impl Iterator<Item = Output> + 'a, SpliceError> {
+    let on_output = config.on_output.take();
+    let mut splicer = splicer(config, files)?;
+    splicer.record_edits = true;
+    Ok(observe(
+        std::iter::from_fn(move || splicer.next_output()),
+        on_output,
+    ))
+}
+
+/// Make the `index`th output of [`splice`] without making the ones before it.
+///
+/// Each output is made with its own random number generator, seeded from
+/// `config.seed` and its index. `config.tests` is ignored. If
+/// `config.unique` or `config.structural_unique` is set, [`splice`] skips
+/// duplicates, so its outputs may come from later indices; see
+/// [`Output::index`].
+///
+/// Returns `None` if no output could be made.
+pub fn reproduce(
+    mut config: Config,
+    files: &HashMap<String, (Vec<u8>, Tree)>,
+    index: usize,
+) -> Result<Option<Vec<u8>>, SpliceError> {
+    config.unique = false;
+    config.structural_unique = false;
+    let mut splicer = splicer(config, files)?;
+    splicer.index = index;
+    splicer.turn = index;
+    splicer.remaining = 1;
+    Ok(splicer.next())
+}
+
+/// `text` with the bytes in `range` replaced by `bytes`
+fn replace_range(text: &[u8], range: Range<usize>, bytes: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(text.len() - range.len() + bytes.len());
+    out.extend_from_slice(&text[..range.start]);
+    out.extend_from_slice(bytes);
+    out.extend_from_slice(&text[range.end..]);
+    out
+}
+
+/// Describe the replacements of non-overlapping nodes of `text` as edits
+/// that can be applied one after another
+fn sequential_edits(text: &[u8], edits: &[(Node, &[u8])]) -> Vec<Edit> {
+    let mut edits = edits.to_vec();
+    edits.sort_by_key(|(node, _)| node.start_byte());
+    // Lengths of the bytes replaced so far, and of their replacements
+    let mut old_len = 0;
+    let mut new_len = 0;
+    edits
+        .into_iter()
+        .map(|(node, bytes)| {
+            let range = node.byte_range();
+            let start = range.start - old_len + new_len;
+            old_len += range.len();
+            new_len += bytes.len();
+            Edit {
+                node_id: node.id(),
+                byte_range: start..start + range.len(),
+                old_bytes: text[range].to_vec(),
+                new_bytes: bytes.to_vec(),
+            }
+        })
+        .collect()
+}
+
+/// Apply `edits` to `source` in order, e.g., to turn a source file into the
+/// output of [`splice_with_edits`] that [`Output::edits`] came from.
+///
+/// Panics if an edit is out of bounds.
+pub fn apply_edits(source: &[u8], edits: &[Edit]) -> Vec<u8> {
+    let mut text = source.to_vec();
+    for edit in edits {
+        debug_assert_eq!(text[edit.byte_range.clone()], edit.old_bytes);
+        text.splice(edit.byte_range.clone(), edit.new_bytes.iter().copied());
+    }
+    text
+}
+
+/// Make the output that `trace` came from again.
+///
+/// Fails if `files` has no file named `trace.source_file`, or if the edits
+/// don't apply to it, e.g., because it changed.
+pub fn replay(
+    trace: &MutationTrace,
+    files: &HashMap<String, (Vec<u8>, Tree)>,
+) -> Result<Vec<u8>, SpliceError> {
+    let (source, _tree) = files
+        .get(&trace.source_file)
+        .ok_or_else(|| SpliceError::Replay(format!("no file named {}", trace.source_file)))?;
+    let mut text = source.clone();
+    for (i, edit) in trace.edits.iter().enumerate() {
+        if text.get(edit.byte_range.clone()) != Some(edit.old_bytes.as_slice()) {
+            return Err(SpliceError::Replay(format!(
+                "edit {i} expected different bytes at {:?} of {}",
+                edit.byte_range, trace.source_file
+            )));
+        }
+        text.splice(edit.byte_range.clone(), edit.new_bytes.iter().copied());
+    }
+    Ok(text)
+}
+
+/// Shrink `input` while `predicate` holds, e.g., to minimize a crashing input.
+///
+/// Tries deleting optional nodes and replacing nodes with smaller nodes of the
+/// same kind nested inside them, keeping each reduction for which `predicate`
+/// returns `true`. Stops when no single reduction does. Respects
+/// `config.include_kinds` and `config.exclude_kinds`; other settings are
+/// ignored.
+pub fn shrink(
+    config: &Config,
+    input: &[u8],
+    mut predicate: impl FnMut(&[u8]) -> bool,
+) -> Result<Vec<u8>, SpliceError> {
+    let mut text = input.to_vec();
+    let mut tree = parse(config.language, &text, None)?;
+    'shrink: loop {
+        // Parents come before their children, so big reductions are tried
+        // first
+        let mut nodes = vec![tree.root_node()];
+        let mut i = 0;
+        while i < nodes.len() {
+            let node = nodes[i];
+            i += 1;
+            let mut cursor = tree.walk();
+            nodes.extend(node.children(&mut cursor));
+            if !allowed(&config.include_kinds, &config.exclude_kinds, node.kind()) {
+                continue;
+            }
+            let mut replacements: Vec<&[u8]> = Vec::new();
+            if config.node_types.optional_node(&node) {
+                replacements.push(b"");
+            }
+            let mut descendants = vec![node];
+            while let Some(descendant) = descendants.pop() {
+                let mut cursor = tree.walk();
+                for child in descendant.children(&mut cursor) {
+                    if child.kind() == node.kind() {
+                        replacements.push(&text[child.byte_range()]);
+                    }
+                    descendants.push(child);
+                }
+            }
+            for replacement in replacements {
+                let candidate = replace_range(&text, node.byte_range(), replacement);
+                if candidate.len() < text.len() && predicate(&candidate) {
+                    tree = parse(config.language, &candidate, None)?;
+                    text = candidate;
+                    continue 'shrink;
+                }
+            }
+        }
+        return Ok(text);
+    }
+}
+
+/// Mutate `data` in place with candidates from the corpus of `splicer`,
+/// e.g., as a libFuzzer custom mutator. See the examples of the
+/// `tree-splicer-rust` crate.
+///
+/// Never makes `data` longer than `max_size`. If no mutation fits, `data` is
+/// left unchanged. Returns the new length of `data`.
+pub fn mutate(
+    splicer: &mut PersistentSplicer,
+    data: &mut Vec<u8>,
+    max_size: usize,
+    seed: u32,
+) -> usize {
+    for attempt in 0..4 {
+        let mut mutated = data.clone();
+        splicer.mutate_in_place(&mut mutated, output_seed(u64::from(seed), attempt));
+        if mutated.len() <= max_size && mutated != *data {
+            *data = mutated;
+            break;
+        }
+    }
+    data.len()
+}
+
+/// Like [`splice`], but split the work between `jobs` threads.
+///
+/// Each thread makes a contiguous range of the outputs, so the outputs are the
+/// same as those of [`splice`], except that `config.unique` only applies
+/// within each thread, and each thread gets an even share of
+/// `config.total_bytes_budget`.
+#[cfg(feature = "parallel")]
+pub fn par_splice(
+    mut config: Config,
+    files: &HashMap<String, (Vec<u8>, Tree)>,
+    jobs: usize,
+) -> Result<Vec<Vec<u8>>, SpliceError> {
+    let progress = Mutex::new((0, config.on_output.take()));
+    let stop = AtomicBool::new(false);
+    let splicer = splicer(config, files)?;
+    let jobs = std::cmp::max(jobs, 1);
+    let workers: Vec<_> = (0..jobs).map(|i| splicer.worker(i, jobs)).collect();
+    let (progress, stop) = (&progress, &stop);
+    Ok(workers
+        .into_par_iter()
+        .flat_map_iter(|mut worker| {
+            std::iter::from_fn(move || {
+                if stop.load(Ordering::Relaxed) {
+                    return None;
+                }
+                let out = worker.next()?;
+                let mut progress = progress.lock().unwrap();
+                progress.0 += 1;
+                let count = progress.0;
+                if let Some(OnOutput(on_output)) = &mut progress.1 {
+                    if on_output(count).is_break() {
+                        stop.store(true, Ordering::Relaxed);
+                    }
+                }
+                Some(out)
+            })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use proptest::prelude::{
+        any, prop, prop_assert, prop_assert_eq, prop_oneof, proptest, ProptestConfig, Strategy,
+    };
+
+    use super::*;
+
+    const EXAMPLE: &str = "fn f(x: u8) -> u8 {\n    let y = x + 1;\n    g(x, y);\n    y\n}\n";
+
+    fn builder() -> ConfigBuilder {
+        let node_types = NodeTypes::new(tree_sitter_rust::NODE_TYPES).unwrap();
+        ConfigBuilder::new(tree_sitter_rust::language(), node_types).with_seed(0)
+    }
+
+    fn files(sources: &[&str]) -> HashMap<String, (Vec<u8>, Tree)> {
+        sources
+            .iter()
+            .enumerate()
+            .map(|(i, source)| {
+                let tree = parse(tree_sitter_rust::language(), source.as_bytes(), None).unwrap();
+                (format!("{i}.rs"), (source.as_bytes().to_vec(), tree))
+            })
+            .collect()
+    }
+
+    /// The node of kind `kind` that comes first in `tree`
+    fn first<'t>(tree: &'t Tree, kind: &str) -> Node<'t> {
+        Descendants::new(tree, None)
+            .map(|(node, _)| node)
+            .find(|node| node.kind() == kind)
+            .unwrap()
+    }
+
+    /// Small Rust files, with identifiers and strings that aren't ASCII
+    fn program() -> impl Strategy<Value = String> {
+        let ident = prop::sample::select(vec!["x", "y", "größe", "名前", "_z2"]);
+        let literal = prop_oneof![
+            any::<u16>().prop_map(|n| n.to_string()),
+            "[a-zé❤ ]{0,6}".prop_map(|s| format!("{s:?}")),
+        ];
+        let statement = (ident, literal)
+            .prop_map(|(ident, literal)| format!("    let {ident} = f({literal}, {ident});\n"));
+        prop::collection::vec(statement, 1..6)
+            .prop_map(|body| format!("fn main() {{\n{}}}\n", body.concat()))
+    }
+
+    /// Whether `needle` occurs in `haystack`
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        needle.is_empty() || haystack.windows(needle.len()).any(|w| w == needle)
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn spliced_texts_come_from_the_inputs(
+            sources in prop::collection::vec(program(), 1..4),
+            seed in any::<u64>(),
+        ) {
+            let sources: Vec<&str> = sources.iter().map(String::as_str).collect();
+            let files = files(&sources);
+            let config = builder()
+                .with_seed(seed)
+                .with_inter_splices(4)
+                .with_batch_size(2)
+                .build()
+                .unwrap();
+            for out in splice_with_edits(config, &files).unwrap() {
+                prop_assert!(std::str::from_utf8(&out.bytes).is_ok());
+                for edit in &out.edits {
+                    prop_assert!(sources
+                        .iter()
+                        .any(|source| contains(source.as_bytes(), &edit.new_bytes)));
+                }
+            }
+        }
+
+        #[test]
+        fn outputs_are_deterministic(
+            sources in prop::collection::vec(program(), 1..5),
+            seed in any::<u64>(),
+            rotation in 0..4usize,
+        ) {
+            let sources: Vec<&str> = sources.iter().map(String::as_str).collect();
+            let files = files(&sources);
+            // The same files, inserted in another order
+            let mut entries: Vec<_> = files.clone().into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            let rotation = rotation % entries.len();
+            entries.rotate_left(rotation);
+            let mut shuffled = HashMap::new();
+            for (name, file) in entries.into_iter().rev() {
+                shuffled.insert(name, file);
+            }
+            let config = || {
+                builder()
+                    .with_seed(seed)
+                    .with_crossover(true)
+                    .with_deletions(10)
+                    .with_renames(10)
+                    .with_inter_splices(4)
+                    .with_intra_splices(2)
+                    .with_batch_size(2)
+                    .with_file_selection(FileSelection::WeightedBySize)
+                    .with_supertype_splicing(true)
+                    .with_unique(true)
+                    .with_tests(8)
+                    .build()
+                    .unwrap()
+            };
+            let outputs: Vec<_> = splice(config(), &files).unwrap().collect();
+            let again: Vec<_> = splice(config(), &files).unwrap().collect();
+            prop_assert_eq!(&outputs, &again);
+            let reordered: Vec<_> = splice(config(), &shuffled).unwrap().collect();
+            prop_assert_eq!(&outputs, &reordered);
+        }
+    }
+
+    #[test]
+    fn insert_after_copies_separators() {
+        let language = tree_sitter_rust::language();
+        for (source, i, expected) in [
+            ("fn f() { g(a, b); }", 0, "fn f() { g(a, x, b); }"),
+            ("fn f() { g(a, b); }", 1, "fn f() { g(a, b, x); }"),
+        ] {
+            let tree = parse(language, source.as_bytes(), None).unwrap();
+            let args = first(&tree, "arguments");
+            let node = args.named_child(i).unwrap();
+            let mut text = source.as_bytes().to_vec();
+            text.splice(
+                node.byte_range(),
+                insert_after(source.as_bytes(), node, b"x"),
+            );
+            assert_eq!(String::from_utf8_lossy(&text), expected);
+            let tree = parse(language, &text, None).unwrap();
+            assert_eq!(errors(&tree), 0);
+            let count = first(&tree, "arguments").named_child_count();
+            assert_eq!(count, args.named_child_count() + 1);
+        }
+    }
+
+    #[test]
+    fn crossover_respects_query_and_exclusions() {
+        let files = files(&["fn f() { g(1); }", "fn h() { k(2); }"]);
+        let expected = "fn f() { g(2); }";
+        let config = builder()
+            .with_query("(integer_literal) @lit")
+            .build()
+            .unwrap();
+        let mut splicer = Splicer::new(config, &files).unwrap();
+        let out = splicer.crossover(0, 1).unwrap();
+        assert_eq!(String::from_utf8_lossy(&out.bytes), expected);
+        assert_eq!(out.source_file, "0.rs");
+        assert_eq!(out.operators, [Operator::Crossover]);
+        assert_eq!(out.kinds, ["integer_literal"]);
+        assert!(splicer.crossover(0, 2).is_none());
+
+        let mut crossed = 0;
+        for seed in 0..16 {
+            let config = builder()
+                .with_seed(seed)
+                .with_exclude_kinds([
+                    "source_file",
+                    "function_item",
+                    "identifier",
+                    "block",
+                    "expression_statement",
+                    "call_expression",
+                    "arguments",
+                ])
+                .build()
+                .unwrap();
+            let mut splicer = Splicer::new(config, &files).unwrap();
+            if let Some(out) = splicer.crossover(0, 1) {
+                assert_eq!(String::from_utf8_lossy(&out.bytes), expected);
+                crossed += 1;
+            }
+        }
+        assert!(crossed > 0);
+    }
+
+    #[test]
+    fn mutate_fits_max_size() {
+        let files = files(&[EXAMPLE]);
+        let config = builder().with_inter_splices(4).build().unwrap();
+        let mut splicer = PersistentSplicer::new(config, &files).unwrap();
+        let mut changed = 0;
+        for seed in 0..16 {
+            let mut data = EXAMPLE.as_bytes().to_vec();
+            let max_size = data.len() + 8;
+            let len = mutate(&mut splicer, &mut data, max_size, seed);
+            assert_eq!(len, data.len());
+            assert!(len <= max_size);
+            changed += usize::from(data != EXAMPLE.as_bytes());
+        }
+        assert!(changed > 0);
+    }
+
+    #[test]
+    fn supertype_candidates_are_distinct() {
+        let files = files(&["fn f(x: u8) -> u8 { let y = x; g(x, y, 1, 1); y }"]);
+        let config = builder().with_supertype_splicing(true).build().unwrap();
+        let splicer = Splicer::new(config, &files).unwrap();
+        let (_, tree) = splicer.trees[0];
+        for (node, _) in Descendants::new(tree, None) {
+            let candidates = splicer.candidates(&splicer.branches, node);
+            let distinct: BTreeSet<_> = candidates.iter().collect();
+            assert_eq!(distinct.len(), candidates.len());
+        }
+    }
+
+    #[test]
+    fn splice_counts_are_inclusive() {
+        let files = files(&[EXAMPLE]);
+        let config = builder()
+            .with_allow_repeats(true)
+            .with_inter_splices(1)
+            .with_tests(32)
+            .build()
+            .unwrap();
+        let applied: Vec<_> = splice_with_provenance(config, &files)
+            .unwrap()
+            .map(|out| out.splices_applied)
+            .collect();
+        assert!(applied.iter().all(|n| *n <= 1));
+        assert!(applied.contains(&1));
+
+        let config = builder()
+            .with_allow_repeats(true)
+            .with_inter_splices(0)
+            .with_intra_splices(1)
+            .with_tests(32)
+            .build()
+            .unwrap();
+        let swaps: Vec<_> = splice_with_provenance(config, &files)
+            .unwrap()
+            .map(|out| out.operators)
+            .collect();
+        assert!(swaps.iter().all(|ops| ops.len() <= 1));
+        assert!(swaps.iter().any(|ops| ops == &[Operator::Swap]));
+    }
+
+    #[test]
+    fn mutate_one_takes_turns() {
+        let sources = ["fn a() { f(1); }", "fn b() { g(2); }", "fn c() { h(3); }"];
+        let files = files(&sources);
+        let config = builder()
+            .with_file_selection(FileSelection::RoundRobin)
+            .with_inter_splices(0)
+            .build()
+            .unwrap();
+        let mut splicer = PersistentSplicer::new(config, &files).unwrap();
+        for source in sources.iter().cycle().take(6) {
+            let out = splicer.mutate_one(0).unwrap();
+            assert_eq!(out, source.as_bytes());
+        }
+    }
+
+    #[test]
+    fn mutate_one_ignores_the_byte_budget() {
+        let files = files(&[EXAMPLE]);
+        // Enough for a few outputs, but not for all of them
+        let config = builder()
+            .with_max_bytes(2 * EXAMPLE.len())
+            .with_total_bytes_budget(4 * EXAMPLE.len())
+            .build()
+            .unwrap();
+        let mut splicer = PersistentSplicer::new(config, &files).unwrap();
+        for seed in 0..64 {
+            assert!(splicer.mutate_one(seed).is_some());
+        }
+    }
+
+    #[test]
+    fn stable_ids_survive_edits_before_them() {
+        let language = tree_sitter_rust::language();
+        let old = "fn f() { a(); b(); c(); }";
+        let tree = parse(language, old.as_bytes(), None).unwrap();
+        let node = Descendants::new(&tree, None)
+            .map(|(node, _)| node)
+            .find(|node| &old[node.byte_range()] == "c();")
+            .unwrap();
+        let id = StableId::new(node);
+        assert_eq!(id.resolve(&tree), Some(node));
+
+        // One sibling fewer before it, in the same number of bytes
+        let new = "fn f() { { ab(); } c(); }";
+        let tree = parse(language, new.as_bytes(), None).unwrap();
+        let resolved = id.resolve(&tree).unwrap();
+        assert_eq!(&new[resolved.byte_range()], "c();");
+        assert_eq!(resolved.kind(), "expression_statement");
+
+        // Not there at all
+        let tree = parse(language, b"fn f() {}", None).unwrap();
+        assert_eq!(id.resolve(&tree), None);
+    }
+
+    #[test]
+    fn stable_ids_follow_edits_that_change_lengths() {
+        let language = tree_sitter_rust::language();
+        let old = "fn f() { a(); b(); c(); }";
+        let tree = parse(language, old.as_bytes(), None).unwrap();
+        let node = Descendants::new(&tree, None)
+            .map(|(node, _)| node)
+            .find(|node| &old[node.byte_range()] == "c();")
+            .unwrap();
+        let mut id = StableId::new(node);
+
+        // Rename `a` to `aaaaa`, which puts `b();` where `c();` was
+        let new = "fn f() { aaaaa(); b(); c(); }";
+        let edit = InputEdit {
+            start_byte: 9,
+            old_end_byte: 10,
+            new_end_byte: 14,
+            start_position: Point::new(0, 9),
+            old_end_position: Point::new(0, 10),
+            new_end_position: Point::new(0, 14),
+        };
+        id.edit(&edit);
+        assert_eq!(&new[id.byte_range.clone()], "c();");
+        let tree = parse(language, new.as_bytes(), None).unwrap();
+        let resolved = id.resolve(&tree).unwrap();
+        assert_eq!(&new[resolved.byte_range()], "c();");
+        assert_eq!(resolved.kind(), "expression_statement");
+
+        // And back again, shrinking the text
+        id.edit(&InputEdit {
+            start_byte: 9,
+            old_end_byte: 14,
+            new_end_byte: 10,
+            start_position: Point::new(0, 9),
+            old_end_position: Point::new(0, 14),
+            new_end_position: Point::new(0, 10),
+        });
+        let tree = parse(language, old.as_bytes(), None).unwrap();
+        let resolved = id.resolve(&tree).unwrap();
+        assert_eq!(&old[resolved.byte_range()], "c();");
+    }
+
+    /// The files in `tests/corpus`
+    fn corpus() -> HashMap<String, (Vec<u8>, Tree)> {
+        files(&[
+            include_str!("../tests/corpus/shapes.rs"),
+            include_str!("../tests/corpus/stack.rs"),
+            include_str!("../tests/corpus/words.rs"),
+        ])
+    }
+
+    #[test]
+    fn corpus_outputs_have_no_new_errors() {
+        let files = corpus();
+        for seed in 0..4 {
+            let config = builder()
+                .with_seed(seed)
+                .with_reject_errors(true)
+                .with_batch_size(2)
+                .with_inter_splices(8)
+                .with_intra_splices(2)
+                .with_deletions(10)
+                .with_duplications(10)
+                .with_insertions(10)
+                .with_renames(10)
+                .with_sibling_swaps(10)
+                .with_tests(16)
+                .build()
+                .unwrap();
+            for out in splice_with_provenance(config, &files).unwrap() {
+                let (_, source) = &files[&out.source_file];
+                let tree = parse(tree_sitter_rust::language(), &out.bytes, None).unwrap();
+                assert!(errors(&tree) <= errors(source));
+            }
+        }
+    }
+
+    #[test]
+    fn builtin_weights_are_percentages() {
+        let config = builder()
+            .with_deletions(20)
+            .with_renames(10)
+            .with_deletions(30)
+            .build()
+            .unwrap();
+        assert_eq!(config.percent(Operator::Delete), 30);
+        assert_eq!(config.percent(Operator::Rename), 10);
+        assert_eq!(config.percent(Operator::Splice), 60);
+        let settings = config.settings();
+        assert_eq!((settings.deletions, settings.renames), (30, 10));
+        let config = builder().with_settings(settings).build().unwrap();
+        assert_eq!(config.percent(Operator::Splice), 60);
+        assert_eq!(config.operators.len(), 8);
+
+        let err = builder().with_deletions(60).with_insertions(50).build();
+        assert!(matches!(err, Err(SpliceError::InvalidConfig(_))));
+    }
+
+    /// Replaces an integer literal with `0`
+    struct Zero;
+
+    impl MutationOperator for Zero {
+        fn apply<'t>(
+            &self,
+            _rng: &mut dyn RngCore,
+            text: &[u8],
+            _tree: &'t Tree,
+            ctx: &MutationContext<'_, 't>,
+        ) -> Option<(Node<'t>, Vec<u8>)> {
+            let node = ctx
+                .nodes()
+                .iter()
+                .find(|n| n.kind() == "integer_literal" && text[n.byte_range()] != *b"0")?;
+            Some((*node, b"0".to_vec()))
+        }
+
+        fn weight(&self) -> u32 {
+            1
+        }
+
+        fn name(&self) -> &'static str {
+            "zero"
+        }
+    }
+
+    #[test]
+    fn operators_are_picked_by_weight() {
+        let files = files(&[EXAMPLE]);
+        let config = builder()
+            .with_operators(vec![Arc::new(Zero)])
+            .with_inter_splices(1)
+            .with_allow_repeats(true)
+            .with_tests(8)
+            .build()
+            .unwrap();
+        let zeroed = EXAMPLE.replace('1', "0");
+        for out in splice_with_provenance(config, &files).unwrap() {
+            assert!(out
+                .operators
+                .iter()
+                .all(|op| *op == Operator::Custom("zero")));
+            assert!(out.bytes == EXAMPLE.as_bytes() || out.bytes == zeroed.as_bytes());
+        }
+
+        let config = builder()
+            .with_operators(vec![Arc::new(Renames(1)), Arc::new(Splices(0))])
+            .with_inter_splices(1)
+            .with_allow_repeats(true)
+            .with_tests(8)
+            .build()
+            .unwrap();
+        for out in splice_with_provenance(config, &files).unwrap() {
+            assert!(out.operators.iter().all(|op| *op == Operator::Rename));
+        }
+    }
+
+    #[test]
+    fn no_splices_yields_the_inputs() {
+        let sources = [EXAMPLE, "fn g() {}\n"];
+        let files = files(&sources);
+        let config = builder()
+            .with_inter_splices(0)
+            .with_allow_repeats(true)
+            .with_tests(8)
+            .build()
+            .unwrap();
+        let outputs: Vec<_> = splice(config, &files).unwrap().collect();
+        assert_eq!(outputs.len(), 8);
+        for out in outputs {
+            assert!(sources.iter().any(|source| out == source.as_bytes()));
+        }
+    }
+
+    #[test]
+    fn deletions_pick_rare_optional_nodes() {
+        let files = files(&["fn f() {}"]);
+        // Of these, only the function is optional
+        let config = builder()
+            .with_include_kinds(["source_file", "function_item", "identifier", "block"])
+            .build()
+            .unwrap();
+        let mut splicer = Splicer::new(config, &files).unwrap();
+        let (text, tree) = splicer.trees[0];
+        let nodes = splicer.nodes(text, tree);
+        for seed in 0..32 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let (node, bytes) = splicer.delete_node(&mut rng, tree, &nodes);
+            assert_eq!(node.kind(), "function_item");
+            assert!(bytes.is_empty());
+        }
+
+        // Nothing is optional, so the root is all there is
+        let config = builder()
+            .with_include_kinds(["source_file"])
+            .build()
+            .unwrap();
+        let mut splicer = Splicer::new(config, &files).unwrap();
+        let nodes = splicer.nodes(text, tree);
+        let mut rng = StdRng::seed_from_u64(0);
+        let (node, _) = splicer.delete_node(&mut rng, tree, &nodes);
+        assert_eq!(node.id(), tree.root_node().id());
+    }
+
+    #[test]
+    fn candidates_are_sorted() {
+        let files = corpus();
+        let config = || builder().with_seed(7).with_tests(8).build().unwrap();
+        let splicer = Splicer::new(config(), &files).unwrap();
+        for (candidates, counts) in splicer.branches.0.values().zip(splicer.branches.1.values()) {
+            assert!(candidates.windows(2).all(|w| w[0] < w[1]));
+            assert_eq!(candidates.len(), counts.len());
+        }
+        let outputs: Vec<_> = splice(config(), &files).unwrap().collect();
+        assert_eq!(outputs.len(), 8);
+        assert_eq!(
+            outputs,
+            splice(config(), &files).unwrap().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn files_are_sorted_by_name() {
+        let sources = ["fn a() { f(1); }", "fn b() { g(2); }", "fn c() { h(3); }"];
+        let forward = files(&sources);
+        let mut backward = HashMap::new();
+        for name in ["2.rs", "1.rs", "0.rs"] {
+            backward.insert(name.to_string(), forward[name].clone());
+        }
+        let (names, _) = sorted_trees(&backward);
+        assert_eq!(names, ["0.rs", "1.rs", "2.rs"]);
+        let config = || builder().with_seed(3).with_tests(8).build().unwrap();
+        let outputs: Vec<_> = splice(config(), &forward).unwrap().collect();
+        assert_eq!(
+            outputs,
+            splice(config(), &backward).unwrap().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn incremental_reparses_match_fresh_ones() {
+        let language = tree_sitter_rust::language();
+        let old = "fn f() {\n    g(1, 2);\n}\n";
+        let tree = parse(language, old.as_bytes(), None).unwrap();
+        let one = first(&tree, "integer_literal");
+        let args = first(&tree, "arguments");
+        let two = args.named_child(1).unwrap();
+        let edits: [(Node, &[u8]); 2] = [(two, b"h(\n3)"), (one, b"10")];
+        let new = "fn f() {\n    g(10, h(\n3));\n}\n";
+        assert_eq!(end_point(two.start_position(), b"h(\n3)"), Point::new(2, 2));
+        let mut edited = tree.clone();
+        for input_edit in &input_edits(old.len(), new.len(), &edits).unwrap() {
+            edited.edit(input_edit);
+        }
+        let incremental = parse(language, new.as_bytes(), Some(&edited)).unwrap();
+        let fresh = parse(language, new.as_bytes(), None).unwrap();
+        assert_eq!(
+            incremental.root_node().to_sexp(),
+            fresh.root_node().to_sexp()
+        );
+
+        // Nested edits, or a length that doesn't add up
+        let nested: [(Node, &[u8]); 2] = [(args, b"()"), (one, b"3")];
+        assert!(input_edits(old.len(), old.len() - 3, &nested).is_none());
+        assert!(input_edits(old.len(), old.len(), &edits).is_none());
+    }
+
+    #[test]
+    fn errors_are_returned() -> Result<(), Box<dyn std::error::Error>> {
+        let outputs: Vec<_> = splice_str(EXAMPLE, builder().with_tests(2).build()?)?.collect();
+        assert_eq!(outputs.len(), 2);
+        let err = builder().with_batch_size(0).build().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid configuration: batch size must be at least 1"
+        );
+        assert!(std::error::Error::source(&err).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn rejected_errors_stay_out() {
+        let files = files(&[EXAMPLE]);
+        let config = |reject_errors| {
+            builder()
+                .with_splice_chaos(100)
+                .with_reject_errors(reject_errors)
+                .with_inter_splices(4)
+                .with_allow_repeats(true)
+                .with_tests(16)
+                .build()
+                .unwrap()
+        };
+        let degraded: usize = splice_with_provenance(config(false), &files)
+            .unwrap()
+            .map(|out| out.degraded_splices)
+            .sum();
+        assert!(degraded > 0);
+        for out in splice_with_provenance(config(true), &files).unwrap() {
+            let tree = parse(tree_sitter_rust::language(), &out.bytes, None).unwrap();
+            assert_eq!(errors(&tree), 0);
+        }
+    }
+
+    #[test]
+    fn unique_outputs_are_distinct() {
+        let files = files(&["fn f() { a(); }", "fn g() { b(); }"]);
+        let config = builder()
+            .with_unique(true)
+            .with_allow_repeats(true)
+            .with_tests(64)
+            .build()
+            .unwrap();
+        let outputs: Vec<_> = splice(config, &files).unwrap().collect();
+        let distinct: HashSet<_> = outputs.iter().collect();
+        assert!(!outputs.is_empty());
+        assert!(outputs.len() < 64);
+        assert_eq!(distinct.len(), outputs.len());
+    }
+
+    #[test]
+    fn percentages_are_validated() {
+        for percent in [0, 100] {
+            let config = builder()
+                .with_splice_chaos(percent)
+                .with_delete_chaos(percent)
+                .with_deletions(percent)
+                .build()
+                .unwrap();
+            assert_eq!(config.splice_chaos, percent);
+            assert_eq!(config.delete_chaos, percent);
+            assert_eq!(config.percent(Operator::Delete), percent);
+            assert_eq!(config.percent(Operator::Splice), 100 - percent);
+        }
+        let invalid = [
+            builder().with_splice_chaos(101).build(),
+            builder().with_delete_chaos(101).build(),
+            builder().with_deletions(101).build(),
+            builder().with_deletion_probability(1.5).build(),
+            builder().with_deletion_probability(-0.1).build(),
+        ];
+        for result in invalid {
+            assert!(matches!(result, Err(SpliceError::InvalidConfig(_))));
+        }
+        let config = builder().with_deletion_probability(0.25).build().unwrap();
+        assert_eq!(config.percent(Operator::Delete), 25);
+    }
+
+    #[test]
+    fn chaos_only_changes_its_own_operator() {
+        let files = files(&[EXAMPLE]);
+        // What splicing and deleting pick for each seed
+        let picks = |builder: ConfigBuilder| {
+            let mut splicer = Splicer::new(builder.build().unwrap(), &files).unwrap();
+            let (text, tree) = splicer.trees[0];
+            let nodes = splicer.nodes(text, tree);
+            let picks: (Vec<_>, Vec<_>) = (0..64)
+                .map(|seed| {
+                    let mut rng = StdRng::seed_from_u64(seed);
+                    let spliced = splicer
+                        .splice_node(&mut rng, text, tree, &nodes)
+                        .map(|(node, new)| (node.kind(), node.byte_range(), new));
+                    let mut rng = StdRng::seed_from_u64(seed);
+                    let (deleted, _) = splicer.delete_node(&mut rng, tree, &nodes);
+                    (spliced, (deleted.kind(), deleted.byte_range()))
+                })
+                .unzip();
+            picks
+        };
+        let (spliced, deleted) = picks(builder());
+        let (chaotic_splices, unchanged_deletions) = picks(builder().with_splice_chaos(100));
+        let (unchanged_splices, chaotic_deletions) = picks(builder().with_delete_chaos(100));
+        assert_eq!(unchanged_splices, spliced);
+        assert_eq!(unchanged_deletions, deleted);
+        assert_ne!(chaotic_splices, spliced);
+        assert_ne!(chaotic_deletions, deleted);
+    }
+
+    #[test]
+    fn chaos_by_frequency_favors_common_kinds() {
+        // Many identifiers, and few of every other kind
+        let args: Vec<_> = (0..40).map(|i| format!("a{i}")).collect();
+        let source = format!("fn f() {{ g({}); h(1); h(2); }}", args.join(", "));
+        let files = files(&[&source]);
+        let identifiers = |chaos_weighting| {
+            let config = builder()
+                .with_splice_chaos(100)
+                .with_chaos_weighting(chaos_weighting)
+                .build()
+                .unwrap();
+            let mut splicer = Splicer::new(config, &files).unwrap();
+            let (text, tree) = splicer.trees[0];
+            let nodes = splicer.nodes(text, tree);
+            (0..256)
+                .filter(|seed| {
+                    let mut rng = StdRng::seed_from_u64(*seed);
+                    matches!(
+                        splicer.splice_node(&mut rng, text, tree, &nodes),
+                        Some((_, new)) if new[0].is_ascii_alphabetic()
+                            && new.iter().all(u8::is_ascii_alphanumeric)
+                    )
+                })
+                .count()
+        };
+        // About 1 in 5 and 4 in 5
+        let uniform = identifiers(ChaosWeighting::Uniform);
+        let by_frequency = identifiers(ChaosWeighting::ByFrequency);
+        assert!(uniform < 256 / 3, "{uniform}");
+        assert!(by_frequency > 2 * 256 / 3, "{by_frequency}");
+    }
+
+    #[test]
+    fn excluded_kinds_are_left_alone() {
+        let files = files(&[EXAMPLE, "fn g(z: u8) { h(z, 2); }"]);
+        let config = |builder: ConfigBuilder| {
+            builder
+                .with_deletions(20)
+                .with_allow_repeats(true)
+                .with_tests(32)
+                .build()
+                .unwrap()
+        };
+        let excluding = || config(builder().with_exclude_kinds(["identifier"]));
+        let splicer = Splicer::new(excluding(), &files).unwrap();
+        assert!(splicer
+            .branches
+            .0
+            .keys()
+            .all(|(_, kind)| *kind != "identifier"));
+        let mut applied = 0;
+        for out in splice_with_provenance(excluding(), &files).unwrap() {
+            assert!(!out.kinds.contains(&"identifier"));
+            applied += out.splices_applied;
+        }
+        assert!(applied > 0);
+
+        // Exclusions apply after inclusions
+        let including = config(
+            builder()
+                .with_include_kinds(["integer_literal", "identifier"])
+                .with_exclude_kinds(["identifier"]),
+        );
+        let mut applied = 0;
+        for out in splice_with_provenance(including, &files).unwrap() {
+            assert!(out.kinds.iter().all(|kind| *kind == "integer_literal"));
+            applied += out.splices_applied;
+        }
+        assert!(applied > 0);
+    }
+
+    #[test]
+    fn field_aware_splices_keep_fields() {
+        let source = "fn f(a: u8) -> u8 { let b = c; b }";
+        let files = files(&[source]);
+        let fields = |field_aware| {
+            let config = builder()
+                .with_include_kinds(["identifier"])
+                .with_field_aware(field_aware)
+                .build()
+                .unwrap();
+            let mut splicer = Splicer::new(config, &files).unwrap();
+            let (text, tree) = splicer.trees[0];
+            let nodes = splicer.nodes(text, tree);
+            let mut spliced = BTreeSet::new();
+            for seed in 0..64 {
+                let mut rng = StdRng::seed_from_u64(seed);
+                if let Some((node, new)) = splicer.splice_node(&mut rng, text, tree, &nodes) {
+                    spliced.insert((field_name(node), String::from_utf8(new).unwrap()));
+                }
+            }
+            spliced
+        };
+        // Only patterns have more than one candidate
+        let expected = BTreeSet::from([
+            (Some("pattern"), "a".to_string()),
+            (Some("pattern"), "b".to_string()),
+        ]);
+        assert_eq!(fields(true), expected);
+        assert!(!fields(false).is_subset(&expected));
+    }
+
+    #[test]
+    fn sibling_swaps_exchange_text() {
+        let source = "fn f() { g(1, 2); }";
+        let files = files(&[source]);
+        let mut splicer = Splicer::new(builder().build().unwrap(), &files).unwrap();
+        let (text, tree) = splicer.trees[0];
+        let nodes = splicer.nodes(text, tree);
+        let mut rng = StdRng::seed_from_u64(0);
+        let swap = splicer.swap_siblings(&mut rng, text, tree, &nodes).unwrap();
+        let [(a, a_new), (b, b_new)] = swap.as_slice() else {
+            panic!("Expected two edits, got {swap:?}");
+        };
+        assert_eq!(a.next_named_sibling(), Some(*b));
+        assert_eq!(
+            (a_new.as_slice(), b_new.as_slice()),
+            (b"2".as_slice(), b"1".as_slice())
+        );
+        let (node, new) = combined(text, tree, swap).unwrap();
+        let swapped = replace_range(text, node.byte_range(), &new);
+        assert_eq!(swapped, b"fn f() { g(2, 1); }");
+        let tree = parse(tree_sitter_rust::language(), &swapped, None).unwrap();
+        assert_eq!(errors(&tree), 0);
+    }
+
+    #[test]
+    fn duplications_repeat_nodes() {
+        let source = "fn f() { g(1, 2); }\n";
+        let files = files(&[source]);
+        let mut splicer = Splicer::new(builder().build().unwrap(), &files).unwrap();
+        let (text, tree) = splicer.trees[0];
+        let nodes = splicer.nodes(text, tree);
+        let mut kinds = BTreeSet::new();
+        for seed in 0..32 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let (node, new) = splicer.duplicate_node(&mut rng, text, &nodes).unwrap();
+            let old = &text[node.byte_range()];
+            assert!(new.starts_with(old) && new.ends_with(old));
+            // Plus a separator
+            assert!(new.len() > 2 * old.len() && new.len() <= 2 * old.len() + 2);
+            let duplicated = replace_range(text, node.byte_range(), &new);
+            let tree = parse(tree_sitter_rust::language(), &duplicated, None).unwrap();
+            assert_eq!(errors(&tree), 0);
+            kinds.insert(node.kind());
+        }
+        assert!(kinds.contains("integer_literal"));
+    }
+
+    #[test]
+    fn outputs_record_their_provenance() {
+        let sources = [EXAMPLE, "fn g(z: u8) { h(z, 2); }"];
+        let files = files(&sources);
+        let config = builder()
+            .with_seed(11)
+            .with_deletions(30)
+            .with_allow_repeats(true)
+            .with_tests(32)
+            .build()
+            .unwrap();
+        let mut operators = Vec::new();
+        for (i, out) in splice_with_provenance(config, &files).unwrap().enumerate() {
+            assert!(files.contains_key(&out.source_file));
+            assert_eq!((out.seed, out.index), (11, i));
+            assert_eq!(out.splices_applied, out.operators.len());
+            assert_eq!(out.kinds.len(), out.operators.len());
+            if out.operators.is_empty() {
+                assert_eq!(out.bytes, files[&out.source_file].0);
+            }
+            operators.extend(out.operators);
+        }
+        assert!(operators.contains(&Operator::Splice) && operators.contains(&Operator::Delete));
+        assert!(operators
+            .iter()
+            .all(|op| [Operator::Splice, Operator::Delete].contains(op)));
+    }
+
+    #[test]
+    fn reproduce_makes_the_same_output() {
+        let files = corpus();
+        for file_selection in [FileSelection::Uniform, FileSelection::RoundRobin] {
+            let config = || {
+                builder()
+                    .with_seed(42)
+                    .with_deletions(10)
+                    .with_intra_splices(2)
+                    .with_file_selection(file_selection)
+                    .with_tests(12)
+                    .build()
+                    .unwrap()
+            };
+            let outputs: Vec<_> = splice(config(), &files).unwrap().collect();
+            assert_eq!(outputs.len(), 12);
+            for index in [0, 5, 11] {
+                let reproduced = reproduce(config(), &files, index).unwrap();
+                assert_eq!(reproduced.as_ref(), Some(&outputs[index]));
+            }
+        }
+    }
+
+    #[test]
+    fn shrinking_keeps_the_predicate() {
+        let config = builder().build().unwrap();
+        let mut kept = Vec::new();
+        let shrunk = shrink(&config, EXAMPLE.as_bytes(), |candidate| {
+            let holds = contains(candidate, b"g(");
+            if holds {
+                kept.push(candidate.len());
+            }
+            holds
+        })
+        .unwrap();
+        assert!(contains(&shrunk, b"g("));
+        assert!(shrunk.len() < EXAMPLE.len() / 2);
+        assert!(kept.windows(2).all(|w| w[1] < w[0]));
+        assert_eq!(kept.last(), Some(&shrunk.len()));
+    }
+
+    #[test]
+    fn nodes_are_picked_within_the_depth_window() {
+        let files = files(&[EXAMPLE]);
+        let depth = |node: Node| std::iter::successors(node.parent(), Node::parent).count();
+        for reservoir_sampling in [false, true] {
+            let config = builder()
+                .with_min_depth(2)
+                .with_max_depth(4)
+                .with_reservoir_sampling(reservoir_sampling)
+                .build()
+                .unwrap();
+            let mut splicer = Splicer::new(config, &files).unwrap();
+            let (text, tree) = splicer.trees[0];
+            assert!(splicer
+                .all_nodes(text, tree)
+                .into_iter()
+                .all(|node| (2..=4).contains(&depth(node))));
+            let nodes = splicer.nodes(text, tree);
+            for seed in 0..64 {
+                let node = splicer.pick_node(&mut StdRng::seed_from_u64(seed), tree, &nodes);
+                assert!((2..=4).contains(&depth(node)), "{}", node.kind());
+            }
+        }
+    }
+
+    #[test]
+    fn outputs_stay_under_max_bytes() {
+        let statements: String = (0..40)
+            .map(|i| format!("    let y{i} = x + {i};\n"))
+            .collect();
+        let large = format!("fn h(x: u8) {{\n{statements}}}\n");
+        let files = files(&[EXAMPLE, &large]);
+        let config = |builder: ConfigBuilder| {
+            builder
+                .with_duplications(50)
+                .with_inter_splices(32)
+                .with_tests(64)
+                .build()
+                .unwrap()
+        };
+        let uncapped: Vec<_> = splice(config(builder()), &files).unwrap().collect();
+        assert!(uncapped.iter().any(|out| out.len() > 200));
+        let capped: Vec<_> = splice(config(builder().with_max_bytes(200)), &files)
+            .unwrap()
+            .collect();
+        assert!(!capped.is_empty());
+        assert!(capped.iter().all(|out| out.len() <= 200));
+    }
+
+    #[test]
+    fn timeouts_still_make_outputs() {
+        let files = corpus();
+        let config = builder()
+            .with_inter_splices(1000)
+            .with_duplications(50)
+            .with_tests(16)
+            .with_timeout(std::time::Duration::ZERO)
+            .build()
+            .unwrap();
+        let start = std::time::Instant::now();
+        assert_eq!(splice(config, &files).unwrap().count(), 16);
+        assert!(start.elapsed() < std::time::Duration::from_secs(10));
+    }
+
+    #[test]
+    fn breaking_stops_after_that_output() {
+        let files = corpus();
+        let config = || builder().with_seed(3).with_deletions(10).with_tests(16);
+        let all: Vec<_> = splice(config().build().unwrap(), &files).unwrap().collect();
+        let stopped = config()
+            .with_on_output(|count| {
+                if count == 5 {
+                    ControlFlow::Break(())
+                } else {
+                    ControlFlow::Continue(())
+                }
+            })
+            .build()
+            .unwrap();
+        let outputs: Vec<_> = splice(stopped, &files).unwrap().collect();
+        assert_eq!(outputs, all[..5]);
+    }
+
+    /// A linear congruential generator, as another tool might use
+    struct Lcg(u64);
+
+    impl RngCore for Lcg {
+        fn next_u32(&mut self) -> u32 {
+            (self.next_u64() >> 32) as u32
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            self.0 = self
+                .0
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            self.0
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            for chunk in dest.chunks_mut(8) {
+                let bytes = self.next_u64().to_le_bytes();
+                chunk.copy_from_slice(&bytes[..chunk.len()]);
+            }
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn injected_rngs_drive_the_outputs() {
+        let files = files(&[EXAMPLE]);
+        let config = builder()
+            .with_operators(vec![Arc::new(Zero)])
+            .with_inter_splices(1)
+            .with_allow_repeats(true)
+            .with_tests(4)
+            .build()
+            .unwrap();
+        let zeroed = EXAMPLE.replace('1', "0");
+        let outputs: Vec<_> = splice_with_rng(config, &files, Lcg(1)).unwrap().collect();
+        assert_eq!(outputs, vec![zeroed.into_bytes(); 4]);
+
+        let files = corpus();
+        let config = || builder().with_deletions(20).with_tests(8).build().unwrap();
+        let run = |rng| -> Vec<_> { splice_with_rng(config(), &files, rng).unwrap().collect() };
+        assert_eq!(run(Lcg(7)), run(Lcg(7)));
+        assert_ne!(run(Lcg(7)), run(Lcg(8)));
+    }
+
+    #[test]
+    fn injected_rngs_sample_the_candidates() {
+        let files = corpus();
+        // `config.seed` is ignored, even for which candidates are kept
+        let run = |seed| -> Vec<_> {
+            let config = builder()
+                .with_seed(seed)
+                .with_max_candidates_per_kind(2)
+                .with_tests(16)
+                .build()
+                .unwrap();
+            splice_with_rng(config, &files, Lcg(7)).unwrap().collect()
+        };
+        assert_eq!(run(0), run(1));
+    }
+
+    #[test]
+    fn preserved_comments_survive() {
+        let source = "// header\nfn f(x: u8) -> u8 {\n    /* inline */ let y = x + 1;\n    // last\n    g(x, y);\n    y\n}\n";
+        let files = files(&[source]);
+        let config = builder()
+            .with_preserve_comments(true)
+            .with_deletions(50)
+            .with_duplications(20)
+            .with_inter_splices(8)
+            .with_tests(32)
+            .build()
+            .unwrap();
+        for out in splice(config, &files).unwrap() {
+            for comment in ["// header", "/* inline */", "// last"] {
+                assert!(contains(&out, comment.as_bytes()), "{comment}");
+            }
+        }
+    }
+
+    #[test]
+    fn errors_are_not_spliced() {
+        // No other node's text is made of backticks
+        let broken = "fn f(x: u8) { g(x, 1); }\n`` ```\nfn h() {}\n";
+        let files = files(&[broken, EXAMPLE]);
+        let splicer = Splicer::new(builder().build().unwrap(), &files).unwrap();
+        let (text, tree) = splicer
+            .trees
+            .iter()
+            .copied()
+            .find(|(text, _)| *text == broken.as_bytes())
+            .unwrap();
+        let errors: Vec<_> = Descendants::new(tree, None)
+            .map(|(node, _)| node)
+            .filter(|node| node.is_error() || node.is_missing())
+            .collect();
+        assert!(!errors.is_empty());
+        assert!(splicer.branches.0.keys().all(|(_, kind)| *kind != "ERROR"));
+        for error in errors.iter().filter(|node| node.is_error()) {
+            let error_text = &text[error.byte_range()];
+            assert!(splicer
+                .branches
+                .0
+                .values()
+                .all(|candidates| !candidates.contains(&error_text)));
+        }
+        assert!(splicer
+            .all_nodes(text, tree)
+            .iter()
+            .all(|node| !node.is_error() && !node.is_missing()));
+    }
+
+    #[test]
+    fn renames_rename_every_occurrence() {
+        let files = files(&["fn f(x: u8) -> u8 {\n    let y = x + x;\n    g(x, y);\n    y\n}\n"]);
+        let mut splicer = Splicer::new(builder().build().unwrap(), &files).unwrap();
+        let (text, tree) = splicer.trees[0];
+        let nodes = splicer.nodes(text, tree);
+        let mut renamed = Vec::new();
+        for seed in 0..32 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let Some(edits) = splicer.rename(&mut rng, text, tree, &nodes) else {
+                continue;
+            };
+            let (first, new_name) = &edits[0];
+            let name = &text[first.byte_range()];
+            assert!(edits
+                .iter()
+                .all(|(node, new)| &text[node.byte_range()] == name && new == new_name));
+            let occurrences = Descendants::new(tree, None)
+                .filter(|(node, _)| node.kind() == first.kind() && &text[node.byte_range()] == name)
+                .count();
+            assert_eq!(edits.len(), occurrences);
+            renamed.push(name);
+        }
+        assert!(renamed.contains(&&b"x"[..]));
+    }
+
+    #[test]
+    fn walked_picks_are_uniform() {
+        let files = files(&[EXAMPLE]);
+        let config = builder().with_reservoir_sampling(true).build().unwrap();
+        let mut splicer = Splicer::new(config, &files).unwrap();
+        let (text, tree) = splicer.trees[0];
+        let nodes = splicer.nodes(text, tree);
+        let all = splicer.collected(&nodes).len();
+        let trials = 100 * all;
+        let mut counts: HashMap<usize, usize> = HashMap::new();
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..trials {
+            *counts
+                .entry(splicer.pick_node(&mut rng, tree, &nodes).id())
+                .or_default() += 1;
+        }
+        assert_eq!(counts.len(), all);
+        // About 100 each, give or take 10
+        assert!(
+            counts.values().all(|n| (50..=150).contains(n)),
+            "{counts:?}"
+        );
+    }
+
+    #[test]
+    fn candidates_are_capped_per_kind() {
+        let args: Vec<_> = (0..40).map(|i| format!("a{i}")).collect();
+        let source = format!("fn f() {{ g({}); }}", args.join(", "));
+        let files = files(&[&source, EXAMPLE]);
+        let capped = |seed| {
+            let config = builder()
+                .with_seed(seed)
+                .with_max_candidates_per_kind(8)
+                .build()
+                .unwrap();
+            Splicer::new(config, &files).unwrap().branches.0.clone()
+        };
+        let branches = capped(0);
+        assert!(branches.values().all(|candidates| candidates.len() <= 8));
+        assert_eq!(branches[&(None, "identifier")].len(), 8);
+        assert_eq!(branches, capped(0));
+    }
+
+    #[test]
+    fn configs_without_candidates_are_rejected() {
+        let files = files(&[EXAMPLE]);
+        // Not through `ConfigBuilder::build`, which rejects it
+        let config = || {
+            let mut config = builder().build().unwrap();
+            config.max_candidates_per_kind = Some(0);
+            config
+        };
+        assert_eq!(possible_mutations(&files, &config()), 0);
+        assert!(candidate_counts(&files, &config()).is_empty());
+        assert!(matches!(
+            Splicer::new(config(), &files),
+            Err(SpliceError::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn similar_candidates_are_close_in_size() {
+        // Calls of 4 to 56 bytes, and a few of hundreds of bytes
+        let call = |n: usize| {
+            let args: Vec<_> = (1..=n).map(|i| i.to_string()).collect();
+            format!("    g({});\n", args.join(", "))
+        };
+        let calls: String = (1..=16).chain([100, 150, 200, 300]).map(call).collect();
+        let files = files(&[&format!("fn f() {{\n{calls}}}\n")]);
+        let within = |candidate_size_bias| {
+            let config = builder()
+                .with_include_kinds(["call_expression"])
+                .with_candidate_size_bias(candidate_size_bias)
+                .build()
+                .unwrap();
+            let mut splicer = Splicer::new(config, &files).unwrap();
+            let (text, tree) = splicer.trees[0];
+            let nodes = splicer.nodes(text, tree);
+            let spliced: Vec<_> = (0..256)
+                .filter_map(|seed| {
+                    let mut rng = StdRng::seed_from_u64(seed);
+                    splicer.splice_node(&mut rng, text, tree, &nodes)
+                })
+                .collect();
+            assert!(spliced.len() > 200);
+            let similar = spliced
+                .iter()
+                .filter(|(node, new)| similar_size(new.len(), node.byte_range().len(), 2.0))
+                .count();
+            similar as f64 / spliced.len() as f64
+        };
+        // About 2 in 5 without the bias
+        let unbiased = within(CandidateSizeBias::None);
+        assert!(unbiased < 0.75, "{unbiased}");
+        assert_eq!(within(CandidateSizeBias::Similar { ratio: 2.0 }), 1.0);
+    }
+
+    #[test]
+    fn run_stats_count_the_operations() {
+        let files = corpus();
+        let config = builder().with_deletions(50).with_tests(16).build().unwrap();
+        let outputs: Vec<_> = splice_with_provenance(config, &files).unwrap().collect();
+        let mut stats = RunStats::new();
+        for output in &outputs {
+            stats.record(output);
+        }
+        let operators: Vec<_> = outputs.iter().flat_map(|out| &out.operators).collect();
+        let deletions = operators
+            .iter()
+            .filter(|op| ***op == Operator::Delete)
+            .count();
+        let distinct: HashSet<_> = outputs.iter().map(|out| &out.bytes).collect();
+        assert_eq!(stats.outputs, 16);
+        assert_eq!(stats.unique_outputs, distinct.len());
+        assert_eq!(stats.splices_applied, operators.len());
+        assert_eq!(stats.deletions, deletions);
+        assert!(0 < deletions && deletions < operators.len());
+        assert_eq!(stats.kinds.values().sum::<usize>(), operators.len());
+        assert_eq!(
+            stats.bytes,
+            outputs.iter().map(|out| out.bytes.len()).sum::<usize>()
+        );
+        let json: serde_json::Value = serde_json::from_str(&stats.to_json()).unwrap();
+        assert_eq!(json["deletions"], deletions);
+        assert!(json.get("seen").is_none());
+    }
+
+    #[test]
+    fn analysis_counts_kinds() {
+        let files = files(&["fn f() { g(1); g(2); }", "fn g() { g(1); }"]);
+        let stats = analyze(&files);
+        let expected = [
+            ("source_file", 2, 2),
+            ("function_item", 2, 2),
+            ("fn", 2, 1),
+            ("identifier", 5, 2),
+            ("call_expression", 3, 2),
+            ("integer_literal", 3, 2),
+        ];
+        for (kind, count, distinct) in expected {
+            assert_eq!(stats[kind], KindStats { count, distinct }, "{kind}");
+        }
+        assert!(stats.keys().all(|kind| *kind != "ERROR"));
+    }
+
+    /// Cancels the re-parse of every other mutation, so that it fails to
+    /// render
+    struct Flaky(AtomicUsize);
+
+    static CANCELLED: AtomicUsize = AtomicUsize::new(0);
+
+    impl MutationOperator for Flaky {
+        fn apply<'t>(
+            &self,
+            _rng: &mut dyn RngCore,
+            _text: &[u8],
+            _tree: &'t Tree,
+            ctx: &MutationContext<'_, 't>,
+        ) -> Option<(Node<'t>, Vec<u8>)> {
+            let calls = self.0.fetch_add(1, Ordering::SeqCst);
+            CANCELLED.store(usize::from(calls % 2 == 0), Ordering::SeqCst);
+            let node = ctx.nodes().iter().find(|n| n.kind() == "arguments")?;
+            // Enough new tokens that the parser checks for cancellation
+            let args = vec![calls.to_string(); 200].join(", ");
+            Some((*node, format!("({args})").into_bytes()))
+        }
+
+        fn weight(&self) -> u32 {
+            1
+        }
+
+        fn name(&self) -> &'static str {
+            "flaky"
+        }
+    }
+
+    #[test]
+    fn render_failures_skip_only_their_mutation() {
+        let source = "fn f() { g(1); }";
+        let files = files(&[source]);
+        let config = builder()
+            .with_operators(vec![Arc::new(Flaky(AtomicUsize::new(0)))])
+            .with_inter_splices(1)
+            .with_min_splices(2)
+            .with_tests(1)
+            .build()
+            .unwrap();
+        let mut splicer = Splicer::new(config, &files).unwrap();
+        // SAFETY: The flag is static, so it outlives the parser
+        unsafe { splicer.parser.0.set_cancellation_flag(Some(&CANCELLED)) };
+        let output = splicer.next_output().unwrap();
+        assert!(output.render_failures > 0);
+        assert!(output.splices_applied >= 2);
+        assert_ne!(output.bytes, source.as_bytes());
+        assert!(output.bytes.starts_with(b"fn f() { g(") && output.bytes.ends_with(b"); }"));
+    }
+
+    #[test]
+    fn min_splices_always_mutate() {
+        // Splicing in the root of one file copies it over another
+        let files = files(&[EXAMPLE, "fn g(z: u8) { h(z, 2); }", "fn h() { g(3); }"]);
+        let config = |min_splices| {
+            builder()
+                .with_inter_splices(1)
+                .with_min_splices(min_splices)
+                .with_allow_repeats(true)
+                .with_tests(64)
+                .build()
+                .unwrap()
+        };
+        let copy = |out: &Vec<u8>| files.values().any(|(text, _)| text == out);
+        assert!(splice(config(0), &files).unwrap().any(|out| copy(&out)));
+        let outputs: Vec<_> = splice(config(1), &files).unwrap().collect();
+        assert_eq!(outputs.len(), 64);
+        assert!(!outputs.iter().any(copy));
+    }
+
+    #[test]
+    fn round_robin_uses_each_file_in_turn() {
+        let files = files(&[EXAMPLE, "fn g(z: u8) { h(z, 2); }", "fn h() { g(3); }"]);
+        let config = builder()
+            .with_file_selection(FileSelection::RoundRobin)
+            .with_tests(9)
+            .build()
+            .unwrap();
+        let sources: Vec<_> = splice_with_provenance(config, &files)
+            .unwrap()
+            .map(|out| out.source_file)
+            .collect();
+        assert_eq!(sources.len(), 9);
+        let mut first: Vec<_> = sources[..3].to_vec();
+        first.sort();
+        assert_eq!(first, ["0.rs", "1.rs", "2.rs"]);
+        for (i, source) in sources.iter().enumerate().skip(3) {
+            assert_eq!(*source, sources[i - 3]);
+        }
+    }
+
+    #[test]
+    fn deletions_keep_sole_required_children() {
+        let files = files(&[EXAMPLE]);
+        let mut splicer = Splicer::new(builder().build().unwrap(), &files).unwrap();
+        let (text, tree) = splicer.trees[0];
+        // The call is optional in other parents, but a statement needs it
+        let call = first(tree, "call_expression");
+        assert_eq!(call.parent().unwrap().kind(), "expression_statement");
+        assert!(!splicer.node_types.optional_node(&call));
+        assert!(splicer.node_types.optional_node(&call.parent().unwrap()));
+        let nodes = splicer.nodes(text, tree);
+        for seed in 0..64 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let (deleted, _) = splicer.delete_node(&mut rng, tree, &nodes);
+            assert_ne!(deleted.id(), call.id());
+        }
+    }
+
+    #[test]
+    fn literals_get_boundary_values_and_escapes() {
+        let files = files(&["fn f() { g(41, \"hello\"); }"]);
+        let mut splicer = Splicer::new(builder().build().unwrap(), &files).unwrap();
+        let (text, tree) = splicer.trees[0];
+        let nodes = splicer.nodes(text, tree);
+        let (mut boundaries, mut escapes) = (0, 0);
+        for seed in 0..64 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let (node, new) = splicer.mutate_number(&mut rng, text, &nodes).unwrap();
+            assert_eq!(node.kind(), "integer_literal");
+            let new = String::from_utf8(new).unwrap();
+            assert!(new != "41");
+            if BOUNDARY_INTEGERS.contains(&new.as_str()) {
+                boundaries += 1;
+            } else {
+                assert!(new == "40" || new == "42", "{new}");
+            }
+
+            let (node, new) = splicer.mutate_string(&mut rng, text, &nodes).unwrap();
+            assert_eq!(node.kind(), "string_literal");
+            assert!(new.starts_with(b"\"") && new.ends_with(b"\""));
+            assert!(new != b"\"hello\"");
+            if contains(&new, b"\\") {
+                escapes += 1;
+            }
+        }
+        assert!(boundaries > 0);
+        assert!(escapes > 0);
+    }
+
+    #[test]
+    fn whitespace_variants_are_structurally_equal() {
+        let variants = ["fn f() { g(1 + 2); }", "fn  f( ) {\n    g( 1+2 );\n}\n"];
+        let files = files(&variants);
+        let structural = |source: &str| {
+            let tree = parse(tree_sitter_rust::language(), source.as_bytes(), None).unwrap();
+            structural_hash(source.as_bytes(), &tree)
+        };
+        assert_eq!(structural(variants[0]), structural(variants[1]));
+        assert_ne!(structural(variants[0]), structural("fn f() { g(1 + 3); }"));
+        // Unmutated copies of either file
+        let outputs = |builder: ConfigBuilder| {
+            let config = builder
+                .with_inter_splices(0)
+                .with_allow_repeats(true)
+                .with_tests(16)
+                .build()
+                .unwrap();
+            splice(config, &files).unwrap().count()
+        };
+        assert_eq!(outputs(builder().with_unique(true)), 2);
+        assert_eq!(outputs(builder().with_structural_unique(true)), 1);
+    }
+
+    #[test]
+    fn queries_restrict_mutations_to_captures() {
+        let source = "fn f(x: u8) -> u8 {\n    g(x, h(1));\n    x + 1\n}\n";
+        let files = files(&[source]);
+        let config = builder()
+            .with_query("(call_expression) @call")
+            .build()
+            .unwrap();
+        let mut splicer = Splicer::new(config, &files).unwrap();
+        let (text, tree) = splicer.trees[0];
+        let nodes = splicer.nodes(text, tree);
+        assert_eq!(splicer.collected(&nodes).len(), 2);
+        for seed in 0..32 {
+            let node = splicer.pick_node(&mut StdRng::seed_from_u64(seed), tree, &nodes);
+            assert_eq!(node.kind(), "call_expression");
+        }
+
+        // Nothing to mutate
+        let config = builder()
+            .with_query("(string_literal) @string")
+            .with_tests(8)
+            .build()
+            .unwrap();
+        for out in splice(config, &files).unwrap() {
+            assert_eq!(out, source.as_bytes());
+        }
+    }
+
+    #[test]
+    fn anchored_mutations_stay_inside_an_anchor() {
+        let source = "fn f(x: u8) { g(x, 1); }\nfn h(y: u8) { let z = y; }\n";
+        let files = files(&[source]);
+        let config = builder().with_anchor_kind("block").build().unwrap();
+        let mut splicer = Splicer::new(config, &files).unwrap();
+        let (text, tree) = splicer.trees[0];
+        let blocks: Vec<_> = Descendants::new(tree, None)
+            .map(|(node, _)| node)
+            .filter(|node| node.kind() == "block")
+            .collect();
+        let anchor = |node: Node| {
+            std::iter::successors(node.parent(), Node::parent)
+                .find(|parent| parent.kind() == "block")
+                .map(|block| block.id())
+        };
+        let mut anchors = Vec::new();
+        for seed in 0..32 {
+            let nodes = splicer.nodes(text, tree);
+            let collected = splicer.collected(&nodes);
+            let first = anchor(collected[0]).unwrap();
+            assert!(collected.iter().all(|node| anchor(*node) == Some(first)));
+            let node = splicer.pick_node(&mut StdRng::seed_from_u64(seed), tree, &nodes);
+            assert_eq!(anchor(node), Some(first));
+            let (deleted, _) = splicer.delete_node(&mut StdRng::seed_from_u64(seed), tree, &nodes);
+            assert_eq!(anchor(deleted), Some(first));
+            anchors.push(first);
+        }
+        assert!(blocks.iter().all(|block| anchors.contains(&block.id())));
+    }
+
+    #[test]
+    fn directions_only_grow_or_shrink() {
+        let files = files(&[EXAMPLE, "fn g(z: u8) { h(z, 2); }"]);
+        let inputs: HashMap<_, _> = files
+            .iter()
+            .map(|(name, (text, _))| (name.clone(), text.len()))
+            .collect();
+        let outputs = |direction| {
+            let config = builder()
+                .with_direction(direction)
+                .with_deletions(30)
+                .with_duplications(30)
+                .with_tests(32)
+                .build()
+                .unwrap();
+            splice_with_provenance(config, &files)
+                .unwrap()
+                .map(|out| (out.bytes.len(), inputs[&out.source_file]))
+                .collect::<Vec<_>>()
+        };
+        let grown = outputs(Direction::Grow);
+        assert!(grown.iter().all(|(len, input)| len >= input));
+        assert!(grown.iter().any(|(len, input)| len > input));
+        let shrunk = outputs(Direction::Shrink);
+        assert!(shrunk.iter().all(|(len, input)| len <= input));
+        assert!(shrunk.iter().any(|(len, input)| len < input));
+    }
+
+    #[test]
+    fn roots_are_selectable() {
+        let files = files(&["fn f() {}"]);
+        let root_picked = |builder: ConfigBuilder| {
+            let mut splicer = Splicer::new(builder.build().unwrap(), &files).unwrap();
+            let (text, tree) = splicer.trees[0];
+            let root = tree.root_node();
+            let listed = splicer.all_nodes(text, tree).contains(&root);
+            let nodes = splicer.nodes(text, tree);
+            let picked = (0..64).any(|seed| {
+                splicer.pick_node(&mut StdRng::seed_from_u64(seed), tree, &nodes) == root
+            });
+            assert_eq!(listed, picked);
+            listed
+        };
+        assert!(root_picked(builder()));
+        assert!(!root_picked(builder().with_min_depth(1)));
+        assert!(!root_picked(builder().with_exclude_kinds(["source_file"])));
+    }
+
+    #[test]
+    fn offsets_survive_bytes_that_are_not_utf8() {
+        let text = b"fn f(x: u8) { g(\"\xff\xfe\", x, 1); h(x, 2); }".to_vec();
+        let tree = parse(tree_sitter_rust::language(), &text, None).unwrap();
+        let files = HashMap::from([("0.rs".to_string(), (text, tree))]);
+        let config = builder()
+            .with_include_kinds(["identifier", "integer_literal"])
+            .with_tests(32)
+            .build()
+            .unwrap();
+        let names: [&[u8]; 6] = [b"f", b"g", b"h", b"x", b"1", b"2"];
+        for out in splice(config, &files).unwrap() {
+            assert!(contains(&out, b"\"\xff\xfe\""));
+            let tree = parse(tree_sitter_rust::language(), &out, None).unwrap();
+            for (node, _) in Descendants::new(&tree, None) {
+                if ["identifier", "integer_literal"].contains(&node.kind()) {
+                    assert!(names.contains(&&out[node.byte_range()]));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn empty_corpora_are_errors() {
+        for files in [HashMap::new(), files(&[""]), files(&["", ""])] {
+            let err = splice(builder().build().unwrap(), &files).err().unwrap();
+            assert!(matches!(err, SpliceError::EmptyCorpus));
+            assert!(err.to_string().contains("at least one non-empty file"));
+        }
+        assert!(splice(builder().build().unwrap(), &files(&["", EXAMPLE])).is_ok());
+    }
+
+    #[test]
+    fn repeats_make_every_test() {
+        let files = files(&["fn f() { g(1); h(2); }"]);
+        let config = builder().build().unwrap();
+        let possible = possible_mutations(&files, &config);
+        let count = |allow_repeats| {
+            let config = builder()
+                .with_allow_repeats(allow_repeats)
+                .with_tests(possible + 100)
+                .build()
+                .unwrap();
+            splice(config, &files).unwrap().count()
+        };
+        assert_eq!(count(false), possible);
+        assert_eq!(count(true), possible + 100);
+    }
+
+    #[test]
+    fn pragmas_protect_spans() {
+        let source = "fn f(x: u8) {\n    // splicer:ignore-start\n    let keep = x + 1;\n    // splicer:ignore-end\n    g(x, 2);\n    // splicer:ignore\n    h(x, 3);\n}\n";
+        let files = files(&[source, EXAMPLE]);
+        let config = builder()
+            .with_honor_pragmas(true)
+            .with_deletions(30)
+            .with_tests(64)
+            .build()
+            .unwrap();
+        let outputs: Vec<_> = splice_with_provenance(config, &files)
+            .unwrap()
+            .filter(|out| out.source_file == "0.rs")
+            .map(|out| out.bytes)
+            .collect();
+        assert!(!outputs.is_empty());
+        for out in &outputs {
+            assert!(contains(out, b"    let keep = x + 1;\n"));
+            assert!(contains(out, b"    h(x, 3);\n"));
+        }
+        assert!(outputs.iter().any(|out| !contains(out, b"    g(x, 2);\n")));
+    }
+
+    #[test]
+    fn prefixes_are_preserved() {
+        let header = "// Copyright\n// SPDX-License-Identifier: MIT\n";
+        let source = format!("{header}{EXAMPLE}");
+        let files = files(&[&source]);
+        let config = builder()
+            .with_preserve_prefix_bytes(header.len())
+            .with_deletions(30)
+            .with_duplications(20)
+            .with_tests(32)
+            .build()
+            .unwrap();
+        let outputs: Vec<_> = splice(config, &files).unwrap().collect();
+        assert!(outputs.iter().all(|out| out.starts_with(header.as_bytes())));
+        assert!(outputs.iter().any(|out| *out != source.as_bytes()));
+    }
+
+    #[test]
+    fn rejected_candidates_are_never_spliced_in() {
+        let files = files(&[EXAMPLE, "fn h(z: u8) { banned(z, 7); }"]);
+        let config = builder()
+            .with_candidate_filter(|candidate, _| !contains(candidate, b"banned"))
+            .with_tests(64)
+            .build()
+            .unwrap();
+        let outputs: Vec<_> = splice_with_provenance(config, &files)
+            .unwrap()
+            .filter(|out| out.source_file == "0.rs")
+            .collect();
+        assert!(outputs.iter().any(|out| out.splices_applied > 0));
+        assert!(outputs.iter().all(|out| !contains(&out.bytes, b"banned")));
+
+        // Rejecting everything doesn't hang
+        let config = builder()
+            .with_candidate_filter(|_, _| false)
+            .with_tests(8)
+            .build()
+            .unwrap();
+        for out in splice(config, &files).unwrap() {
+            assert!(files.values().any(|(text, _)| *text == out));
+        }
+    }
+
+    #[test]
+    fn dictionaries_have_escaped_leaves() {
+        let files = files(&["fn f() { g(\"a\\\"b\", 'é'); }"]);
+        let dict = dictionary(&files);
+        let lines: Vec<_> = dict.lines().collect();
+        for token in [r#""fn""#, r#""f""#, r#""g""#, r#""(""#, r#"";""#, r#""\"""#] {
+            assert!(lines.contains(&token), "{token}");
+        }
+        assert!(lines.contains(&r#""'\xc3\xa9'""#));
+        assert!(lines.iter().all(|line| line.is_ascii()));
+        let mut sorted = lines.clone();
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(sorted.len(), lines.len());
+        let tokens = parse_dictionary(&dict).unwrap();
+        assert!(tokens.contains(&"'é'".as_bytes().to_vec()));
+        assert!(tokens.contains(&b"\"".to_vec()));
+    }
+
+    #[test]
+    fn dictionary_tokens_are_spliced_in() {
+        let files = files(&[EXAMPLE]);
+        let tokens = parse_dictionary("# magic numbers\nmagic=\"0xdead\"\n\"1337\"\n").unwrap();
+        assert_eq!(tokens, [b"0xdead".to_vec(), b"1337".to_vec()]);
+        let config = builder()
+            .with_dictionary("integer_literal", tokens)
+            .with_include_kinds(["integer_literal"])
+            .with_allow_repeats(true)
+            .with_tests(32)
+            .build()
+            .unwrap();
+        let outputs: Vec<_> = splice(config, &files).unwrap().collect();
+        assert!(outputs.iter().any(|out| contains(out, b"x + 0xdead;")));
+        assert!(outputs.iter().any(|out| contains(out, b"x + 1337;")));
+    }
+
+    #[test]
+    fn minimized_corpora_drop_redundant_files() {
+        let files = files(&[EXAMPLE, "fn g(z: u8) { h(z, 2); }", EXAMPLE]);
+        assert_eq!(minimize_corpus(&files), ["0.rs", "1.rs"]);
+        let kept: HashMap<_, _> = minimize_corpus(&files)
+            .into_iter()
+            .map(|name| {
+                let file = files[&name].clone();
+                (name, file)
+            })
+            .collect();
+        let config = || builder().build().unwrap();
+        assert_eq!(
+            possible_mutations(&kept, &config()),
+            possible_mutations(&files, &config())
+        );
+    }
+
+    #[test]
+    fn structural_diffs_report_the_replaced_node() {
+        let after_text = EXAMPLE.replace("x + 1", "g(x)");
+        let language = tree_sitter_rust::language();
+        let before = parse(language, EXAMPLE.as_bytes(), None).unwrap();
+        let after = parse(language, after_text.as_bytes(), None).unwrap();
+        let at = EXAMPLE.find("x + 1").unwrap();
+        assert_eq!(
+            structural_diff(EXAMPLE.as_bytes(), &before, after_text.as_bytes(), &after),
+            [Change {
+                kind: "binary_expression",
+                before: at..at + 5,
+                after: at..at + 4,
+                before_text: b"x + 1",
+                after_text: b"g(x)",
+            }]
+        );
+        assert!(
+            structural_diff(EXAMPLE.as_bytes(), &before, EXAMPLE.as_bytes(), &before).is_empty()
+        );
+    }
+
+    #[test]
+    fn empty_and_shadowed_nodes_are_never_picked() {
+        // The `;` is missing, so the parser adds an empty `MISSING` node
+        let files = files(&["fn f() { let x = 1 }"]);
+        for reservoir_sampling in [false, true] {
+            let config = builder()
+                .with_reservoir_sampling(reservoir_sampling)
+                .build()
+                .unwrap();
+            let mut splicer = Splicer::new(config, &files).unwrap();
+            let (text, tree) = splicer.trees[0];
+            assert!(Descendants::new(tree, None).any(|(node, _)| node.is_missing()));
+            let nodes = splicer.nodes(text, tree);
+            let mut ranges = HashSet::new();
+            for node in splicer.collected(&nodes).iter() {
+                assert!(!node.is_missing() && !node.byte_range().is_empty());
+                assert!(ranges.insert(node.byte_range()));
+            }
+            for seed in 0..64 {
+                let node = splicer.pick_node(&mut StdRng::seed_from_u64(seed), tree, &nodes);
+                assert!(!node.is_missing() && !node.byte_range().is_empty());
+            }
+        }
+    }
+
+    /// Appends a digit to the first number
+    struct Weighted(&'static str, u32);
+
+    impl MutationOperator for Weighted {
+        fn apply<'t>(
+            &self,
+            _rng: &mut dyn RngCore,
+            text: &[u8],
+            _tree: &'t Tree,
+            ctx: &MutationContext<'_, 't>,
+        ) -> Option<(Node<'t>, Vec<u8>)> {
+            let node = ctx.nodes().iter().find(|n| n.kind() == "integer_literal")?;
+            Some((*node, [&text[node.byte_range()], b"0"].concat()))
+        }
+
+        fn weight(&self) -> u32 {
+            self.1
+        }
+
+        fn name(&self) -> &'static str {
+            self.0
+        }
+    }
+
+    #[test]
+    fn operator_frequencies_match_weights() {
+        let weights = [("a", 1), ("b", 2), ("c", 3), ("d", 4)];
+        let operators: Vec<Arc<dyn MutationOperator>> = weights
+            .iter()
+            .map(|(name, weight)| Arc::new(Weighted(name, *weight)) as Arc<dyn MutationOperator>)
+            .collect();
+        let config = builder()
+            .with_operators(operators)
+            .with_allow_repeats(true)
+            .with_tests(500)
+            .build()
+            .unwrap();
+        let files = files(&["fn f() -> u8 { 1 }"]);
+        let picked: Vec<_> = splice_with_provenance(config, &files)
+            .unwrap()
+            .flat_map(|out| out.operators)
+            .collect();
+        assert!(picked.len() >= 1000, "{}", picked.len());
+        for (name, weight) in weights {
+            let count = picked
+                .iter()
+                .filter(|op| **op == Operator::Custom(name))
+                .count();
+            let frequency = count as f64 / picked.len() as f64;
+            let expected = f64::from(weight) / 10.0;
+            assert!((frequency - expected).abs() < 0.05, "{name}: {frequency}");
+        }
+    }
+
+    #[test]
+    fn edits_reproduce_the_outputs() {
+        let files = corpus();
+        let config = builder()
+            .with_crossover(true)
+            .with_batch_size(3)
+            .with_intra_splices(2)
+            .with_allow_repeats(true)
+            .with_tests(64)
+            .build()
+            .unwrap();
+        let mut edited = 0;
+        for out in splice_with_edits(config, &files).unwrap() {
+            let (source, _tree) = &files[&out.source_file];
+            assert_eq!(apply_edits(source, &out.edits), out.bytes);
+            if !out.edits.is_empty() {
+                edited += 1;
+            }
+        }
+        assert!(edited > 0);
+    }
+
+    #[test]
+    fn traces_replay_without_the_rest_of_the_corpus() {
+        let files = corpus();
+        let config = builder()
+            .with_crossover(true)
+            .with_batch_size(2)
+            .with_allow_repeats(true)
+            .with_tests(32)
+            .build()
+            .unwrap();
+        for out in splice_with_edits(config, &files).unwrap() {
+            let trace = out.trace().unwrap();
+            assert_eq!(replay(&trace, &files).unwrap(), out.bytes);
+            let (name, file) = files.get_key_value(&trace.source_file).unwrap();
+            let alone = HashMap::from([(name.clone(), file.clone())]);
+            assert_eq!(replay(&trace, &alone).unwrap(), out.bytes);
+            if let Some(edit) = trace.edits.first() {
+                let mut changed = alone.clone();
+                let text = &mut changed.get_mut(name).unwrap().0;
+                text.splice(edit.byte_range.start..edit.byte_range.start, *b"//\n");
+                assert!(matches!(
+                    replay(&trace, &changed),
+                    Err(SpliceError::Replay(_))
+                ));
+            }
+        }
+
+        let out = splice_with_provenance(builder().build().unwrap(), &files)
+            .unwrap()
+            .find(|out| !out.operators.is_empty())
+            .unwrap();
+        assert_eq!(out.trace(), None);
+    }
+
+    #[test]
+    fn files_are_picked_by_energy() {
+        let files = files(&["fn f() {}", "fn g() {}", "fn h() {}"]);
+        let picks = || {
+            let config = builder()
+                .with_file_selection(FileSelection::Energy)
+                .with_allow_repeats(true)
+                .with_tests(1000)
+                .build()
+                .unwrap();
+            let mut splicer = Splicer::new(config, &files).unwrap();
+            assert!(splicer.set_energy("1.rs", 3));
+            assert!(splicer.set_energy("2.rs", 1));
+            assert!(splicer.bump_energy("2.rs", 5));
+            assert_eq!(splicer.energy("2.rs"), Some(6));
+            assert!(!splicer.set_energy("3.rs", 1));
+            std::iter::from_fn(|| splicer.next_output())
+                .map(|out| out.source_file)
+                .collect::<Vec<_>>()
+        };
+        let picked = picks();
+        assert_eq!(picked, picks());
+        for (file, energy) in [("0.rs", 1), ("1.rs", 3), ("2.rs", 6)] {
+            let count = picked.iter().filter(|f| *f == file).count();
+            assert!(count.abs_diff(100 * energy) < 50, "{file}: {count}");
+        }
+    }
+
+    #[test]
+    fn added_inputs_are_spliced_from() {
+        let files = files(&["fn f() -> u8 { 1 + 2 }"]);
+        let config = || {
+            builder()
+                .with_include_kinds(["integer_literal"])
+                .with_allow_repeats(true)
+                .with_tests(64)
+                .build()
+                .unwrap()
+        };
+        let mut splicer = Splicer::new(config(), &files).unwrap();
+        let before: Vec<_> = std::iter::from_fn(|| splicer.next_output()).collect();
+        assert!(before.iter().all(|out| !contains(&out.bytes, b"77")));
+        let mut splicer = Splicer::new(config(), &files).unwrap();
+        splicer
+            .add_input("added.rs", b"fn g() -> u8 { 77 }".to_vec())
+            .unwrap();
+        assert!(std::iter::from_fn(|| splicer.next_output())
+            .any(|out| out.source_file == "0.rs" && contains(&out.bytes, b"77")));
+
+        let mut persistent = PersistentSplicer::new(config(), &files).unwrap();
+        persistent
+            .add_input("added.rs", b"fn g() -> u8 { 77 }".to_vec())
+            .unwrap();
+        assert!((0..64).any(|seed| {
+            let mut input = b"fn h() -> u8 { 3 }".to_vec();
+            persistent.mutate_in_place(&mut input, seed);
+            contains(&input, b"77")
+        }));
+    }
+
+    #[test]
+    fn added_inputs_outlive_the_splicer_that_added_them() {
+        let files = files(&["fn f() -> u8 { 1 + 2 }"]);
+        let config = builder()
+            .with_include_kinds(["integer_literal"])
+            .with_allow_repeats(true)
+            .with_tests(64)
+            .build()
+            .unwrap();
+        let mut splicer = Splicer::new(config, &files).unwrap();
+        splicer
+            .add_input("added.rs", b"fn g() -> u8 { 77 }".to_vec())
+            .unwrap();
+        let mut clone = splicer.clone();
+        drop(splicer);
+        assert!(std::iter::from_fn(|| clone.next_output()).any(|out| contains(&out.bytes, b"77")));
+    }
+
+    #[test]
+    fn added_inputs_in_other_languages_are_errors() {
+        let files = files(&[EXAMPLE]);
+        let mut splicer = Splicer::new(builder().build().unwrap(), &files).unwrap();
+        assert!(matches!(
+            splicer.add_input("added.rs", b"]) }) ]) }".to_vec()),
+            Err(SpliceError::WrongLanguage)
+        ));
+        assert_eq!(splicer.trees.len(), 1);
+        splicer.add_input("blank.rs", b"\n".to_vec()).unwrap();
+        assert_eq!(splicer.trees.len(), 2);
+    }
+
+    #[test]
+    fn frequent_candidates_are_picked_more_often() {
+        let files = files(&["fn f() -> u8 { 1 + 1 + 1 + 1 + 1 + 1 + 1 + 1 + 2 + 3 }"]);
+        // How often a `2` or `3` is replaced with a `1`, rather than the other
+        let ones = |candidate_weighting| {
+            let config = builder()
+                .with_include_kinds(["integer_literal"])
+                .with_candidate_weighting(candidate_weighting)
+                .build()
+                .unwrap();
+            let mut splicer = Splicer::new(config, &files).unwrap();
+            let (text, tree) = splicer.trees[0];
+            let nodes = splicer.nodes(text, tree);
+            let spliced: Vec<_> = (0..1024)
+                .filter_map(|seed| {
+                    let mut rng = StdRng::seed_from_u64(seed);
+                    splicer.splice_node(&mut rng, text, tree, &nodes)
+                })
+                .filter(|(node, _)| text[node.byte_range()] != *b"1")
+                .collect();
+            assert!(spliced.len() > 100);
+            let ones = spliced.iter().filter(|(_, new)| new == b"1").count();
+            ones as f64 / spliced.len() as f64
+        };
+        // 1 in 2 uniformly, 8 in 9 by frequency
+        let uniform = ones(CandidateWeighting::Uniform);
+        assert!(uniform < 0.65, "{uniform}");
+        let by_frequency = ones(CandidateWeighting::ByFrequency);
+        assert!(by_frequency > 0.8, "{by_frequency}");
+    }
+
+    #[test]
+    fn candidate_counts_are_occurrences() {
+        let files = corpus();
+        let mut occurrences: CandidateCounts = BTreeMap::new();
+        for (text, tree) in files.values() {
+            for (node, _) in Descendants::new(tree, None) {
+                if !node.is_error() && !node.is_missing() {
+                    *occurrences
+                        .entry(node.kind())
+                        .or_default()
+                        .entry(&text[node.byte_range()])
+                        .or_default() += 1;
+                }
+            }
+        }
+        for field_aware in [false, true] {
+            let config = builder().with_field_aware(field_aware).build().unwrap();
+            assert_eq!(candidate_counts(&files, &config), occurrences);
+        }
+
+        let mut some = files.clone();
+        let (name, (text, _)) = some
+            .iter()
+            .next()
+            .map(|(n, f)| (n.clone(), f.clone()))
+            .unwrap();
+        some.remove(&name);
+        let mut splicer = Splicer::new(builder().build().unwrap(), &some).unwrap();
+        splicer.add_input(name, text).unwrap();
+        assert_eq!(splicer.candidate_counts(), occurrences);
+    }
+
+    /// Turns a `1` into a `2` and back
+    struct Flip;
+
+    impl MutationOperator for Flip {
+        fn apply<'t>(
+            &self,
+            _rng: &mut dyn RngCore,
+            text: &[u8],
+            _tree: &'t Tree,
+            ctx: &MutationContext<'_, 't>,
+        ) -> Option<(Node<'t>, Vec<u8>)> {
+            let node = ctx.nodes().iter().find(|n| n.kind() == "integer_literal")?;
+            let flipped = if text[node.byte_range()] == *b"1" {
+                b"2"
+            } else {
+                b"1"
+            };
+            Some((*node, flipped.to_vec()))
+        }
+
+        fn weight(&self) -> u32 {
+            1
+        }
+    }
+
+    #[test]
+    fn outputs_equal_to_inputs_are_dropped() {
+        let sources = ["fn f() -> u8 { 1 }", "fn f() -> u8 { 2 }"];
+        let files = files(&sources);
+        let outputs = |drop_unchanged| {
+            let config = builder()
+                .with_operators(vec![Arc::new(Flip)])
+                .with_inter_splices(3)
+                .with_drop_unchanged(drop_unchanged)
+                .with_allow_repeats(true)
+                .with_tests(64)
+                .build()
+                .unwrap();
+            splice_with_provenance(config, &files)
+                .unwrap()
+                .collect::<Vec<_>>()
+        };
+        let unchanged = |out: &Output| sources.iter().any(|s| out.bytes == s.as_bytes());
+        // Flipping twice reverts the mutation, flipping once makes the other
+        // input
+        let kept = outputs(false);
+        assert!(kept
+            .iter()
+            .any(|out| unchanged(out) && out.operators.len() == 2));
+        assert!(kept.iter().all(unchanged));
+        assert!(outputs(true).is_empty());
+    }
+
+    #[test]
+    fn outputs_stay_under_max_tree_depth() {
+        let files = corpus();
+        let max = files
+            .values()
+            .map(|(_, tree)| tree_depth(tree))
+            .max()
+            .unwrap();
+        let deepest = |builder: ConfigBuilder| {
+            let config = builder
+                .with_duplications(25)
+                .with_inter_splices(32)
+                .with_tests(64)
+                .build()
+                .unwrap();
+            splice(config, &files)
+                .unwrap()
+                .map(|out| tree_depth(&parse(tree_sitter_rust::language(), &out, None).unwrap()))
+                .max()
+                .unwrap()
+        };
+        assert!(deepest(builder()) > max);
+        assert!(deepest(builder().with_max_tree_depth(max)) <= max);
+    }
+
+    #[test]
+    fn runs_stop_at_the_byte_budget() {
+        let files = corpus();
+        let config = || builder().with_allow_repeats(true).with_tests(32);
+        let unlimited: Vec<_> = splice(config().build().unwrap(), &files).unwrap().collect();
+        let first: usize = unlimited[..5].iter().map(Vec::len).sum();
+        for budget in [first, first + unlimited[5].len() - 1] {
+            let config = config().with_total_bytes_budget(budget).build().unwrap();
+            let budgeted: Vec<_> = splice(config, &files).unwrap().collect();
+            assert_eq!(budgeted, unlimited[..5]);
+        }
+        let config = config().with_total_bytes_budget(0).build().unwrap();
+        assert_eq!(splice(config, &files).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn insertions_are_of_legal_kinds() {
+        let files = files(&["fn f(x: u8) -> u8 {\n    let y = g(x, 1);\n    h(y, 2);\n    y\n}\n"]);
+        let mut splicer = Splicer::new(builder().build().unwrap(), &files).unwrap();
+        let (text, tree) = splicer.trees[0];
+        let mut kinds: HashMap<&[u8], Vec<&str>> = HashMap::new();
+        for (node, _) in Descendants::new(tree, None) {
+            kinds
+                .entry(&text[node.byte_range()])
+                .or_default()
+                .push(node.kind());
+        }
+        let nodes = splicer.nodes(text, tree);
+        let mut in_arguments = 0;
+        for seed in 0..256 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let Some((node, new)) = splicer.insert_node(&mut rng, text, &nodes) else {
+                continue;
+            };
+            let inserted = &new[insert_after(text, node, b"").len()..];
+            let siblings = splicer.node_types.legal_siblings(&node, field_name(node));
+            assert!(!siblings.is_empty());
+            assert!(kinds[inserted]
+                .iter()
+                .any(|k| siblings.iter().any(|s| s == k)));
+            if node.parent().unwrap().kind() == "arguments" {
+                in_arguments += 1;
+                assert!(!kinds[inserted].contains(&"let_declaration"));
+            }
+        }
+        assert!(in_arguments > 0);
+    }
+}
+
+#[cfg(all(test, feature = "parallel"))]
+mod parallel_tests {
+    use super::*;
+
+    const SOURCES: [&str; 3] = [
+        include_str!("../tests/corpus/shapes.rs"),
+        include_str!("../tests/corpus/stack.rs"),
+        include_str!("../tests/corpus/words.rs"),
+    ];
+
+    #[test]
+    fn par_splice_makes_the_outputs_of_splice() {
+        let files: HashMap<_, _> = SOURCES
+            .iter()
+            .enumerate()
+            .map(|(i, source)| {
+                let tree = parse(tree_sitter_rust::language(), source.as_bytes(), None).unwrap();
+                (format!("{i}.rs"), (source.as_bytes().to_vec(), tree))
+            })
+            .collect();
+        let config = || {
+            let node_types = NodeTypes::new(tree_sitter_rust::NODE_TYPES).unwrap();
+            ConfigBuilder::new(tree_sitter_rust::language(), node_types)
+                .with_seed(5)
+                .with_tests(16)
+                .build()
+                .unwrap()
+        };
+        let outputs: Vec<_> = splice(config(), &files).unwrap().collect();
+        for jobs in [1, 3, 4] {
+            assert_eq!(par_splice(config(), &files, jobs).unwrap(), outputs);
+        }
+    }
+
+    #[test]
+    fn parallel_branches_are_sequential_branches() {
+        let trees: Vec<_> = SOURCES
+            .iter()
+            .map(|source| parse(tree_sitter_rust::language(), source.as_bytes(), None).unwrap())
+            .collect();
+        let files: Vec<_> = SOURCES.iter().map(|s| s.as_bytes()).zip(&trees).collect();
+        let allowed = |kind: &str| kind != "line_comment";
+        for field_aware in [false, true] {
+            let parallel = Branches::all(files.clone(), &allowed, field_aware);
+            let sequential =
+                Branches::counted(Branches::sequential(files.clone(), &allowed, field_aware));
+            assert!(!parallel.0.is_empty());
+            assert_eq!(parallel.0, sequential.0);
+            assert_eq!(parallel.1, sequential.1);
+        }
     }
 }
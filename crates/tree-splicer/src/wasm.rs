@@ -0,0 +1,61 @@
+//! Support for JavaScript, e.g., for running in a browser
+//!
+//! The grammar isn't known here, so crates for each language export their own
+//! function with `#[wasm_bindgen]` that calls [`splice_js`] with their grammar,
+//! see `tree_splicer_rust::splice`.
+
+use tree_sitter::Language;
+use wasm_bindgen::JsValue;
+
+use crate::node_types::NodeTypes;
+use crate::splice::{splice_str, ConfigBuilder};
+
+/// Make `tests` variants of `source` with the default settings, as strings.
+/// Errors are strings too.
+pub fn splice_js(
+    language: Language,
+    node_types_json_str: &str,
+    source: &str,
+    seed: u64,
+    tests: usize,
+) -> Result<Vec<JsValue>, JsValue> {
+    splice_strings(language, node_types_json_str, source, seed, tests)
+        .map(|outputs| outputs.iter().map(|s| JsValue::from_str(s)).collect())
+        .map_err(|e| JsValue::from_str(&e))
+}
+
+/// [`splice_js`], but without `JsValue`s, which can only be made on wasm
+fn splice_strings(
+    language: Language,
+    node_types_json_str: &str,
+    source: &str,
+    seed: u64,
+    tests: usize,
+) -> Result<Vec<String>, String> {
+    let node_types = NodeTypes::new(node_types_json_str).map_err(|e| e.to_string())?;
+    let config = ConfigBuilder::new(language, node_types)
+        .with_seed(seed)
+        .with_tests(tests)
+        .build()
+        .map_err(|e| e.to_string())?;
+    Ok(splice_str(source, config)
+        .map_err(|e| e.to_string())?
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splice_js_outputs() {
+        let language = tree_sitter_rust::language();
+        let source = "fn f(x: u8) -> u8 { let y = x + 1; g(x, y); y }";
+        let outputs = splice_strings(language, tree_sitter_rust::NODE_TYPES, source, 0, 4).unwrap();
+        assert!(!outputs.is_empty() && outputs.len() <= 4);
+        let again = splice_strings(language, tree_sitter_rust::NODE_TYPES, source, 0, 4).unwrap();
+        assert_eq!(outputs, again);
+        assert!(splice_strings(language, "not json", source, 0, 4).is_err());
+        assert!(splice_strings(language, tree_sitter_rust::NODE_TYPES, "", 0, 4).is_err());
+    }
+}
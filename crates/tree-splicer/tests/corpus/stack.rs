@@ -0,0 +1,33 @@
+pub struct Stack<T> {
+    items: Vec<T>,
+}
+
+impl<T: Clone> Stack<T> {
+    pub fn new() -> Self {
+        Stack { items: Vec::new() }
+    }
+
+    pub fn push(&mut self, item: T) {
+        self.items.push(item);
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        self.items.pop()
+    }
+
+    pub fn peek(&self) -> Option<&T> {
+        self.items.last()
+    }
+}
+
+fn main() {
+    let mut stack = Stack::new();
+    for i in 0..10 {
+        if i % 3 == 0 {
+            stack.push(i * 2);
+        }
+    }
+    while let Some(top) = stack.pop() {
+        println!("{}", top);
+    }
+}
@@ -0,0 +1,23 @@
+use std::collections::HashMap;
+
+/// Count the words in `text`, ignoring case
+fn count_words(text: &str) -> HashMap<String, usize> {
+    let mut counts = HashMap::new();
+    for word in text.split_whitespace() {
+        let word = word.trim_matches(|c: char| !c.is_alphanumeric());
+        if word.is_empty() {
+            continue;
+        }
+        *counts.entry(word.to_lowercase()).or_insert(0) += 1;
+    }
+    counts
+}
+
+fn main() {
+    let text = "The quick brown fox jumps over the lazy dog. The end!";
+    let counts = count_words(text);
+    let mut words: Vec<_> = counts.into_iter().collect();
+    words.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    let top = &words[..3.min(words.len())];
+    assert_eq!(top[0], ("the".to_string(), 3));
+}